@@ -3,6 +3,7 @@
 //! This crate provides infrastructure for testing Avena components:
 //!
 //! - [`cluster::TestCluster`] - Spawn multi-node NATS clusters for integration tests
+//! - [`clocks::ClockNet`] - HLC nodes on manually driven clocks for deterministic ordering tests
 //! - [`chaos`] - Toxiproxy client for fault injection (requires `chaos` feature)
 //!
 //! # Quick Start
@@ -23,6 +24,7 @@
 //!
 //! - `chaos` - Enable Toxiproxy client for network fault injection
 
+pub mod clocks;
 pub mod cluster;
 
 #[cfg(feature = "chaos")]
@@ -0,0 +1,73 @@
+//! Wall clocks the HLC can be driven by.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Source of wall time, in milliseconds since the Unix epoch.
+pub trait ClockSource: Send + Sync {
+    fn now_ms(&self) -> u64;
+}
+
+/// The operating system's real-time clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl ClockSource for SystemClock {
+    fn now_ms(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0)
+    }
+}
+
+/// A clock that only moves when told to.
+///
+/// Clones share the same time, so a test can keep one and hand another to an
+/// [`crate::hlc::HlcClock`]. Leaving it alone simulates a frozen clock, and
+/// [`ManualClock::set`] can jump it forward or back.
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock {
+    now_ms: Arc<AtomicU64>,
+}
+
+impl ManualClock {
+    pub fn new(now_ms: u64) -> Self {
+        ManualClock {
+            now_ms: Arc::new(AtomicU64::new(now_ms)),
+        }
+    }
+
+    pub fn set(&self, now_ms: u64) {
+        self.now_ms.store(now_ms, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.now_ms
+            .fetch_add(by.as_millis() as u64, Ordering::SeqCst);
+    }
+}
+
+impl ClockSource for ManualClock {
+    fn now_ms(&self) -> u64 {
+        self.now_ms.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manual_clock_is_shared() {
+        let clock = ManualClock::new(1_000);
+        let other = clock.clone();
+
+        clock.advance(Duration::from_millis(500));
+        assert_eq!(other.now_ms(), 1_500);
+
+        other.set(10);
+        assert_eq!(clock.now_ms(), 10);
+    }
+}
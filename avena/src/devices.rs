@@ -1,33 +1,60 @@
 use std::collections::HashMap;
+use std::time::Duration;
 
-use crate::messages::{Device, PingRequest, PingResponse};
+use futures::{StreamExt, TryStreamExt};
+
+use crate::messages::{
+    subject_ping, subject_status, Announce, Device, PingRequest, PingResponse, StatusRequest,
+    StatusResponse, ANNOUNCE_SUBJECT,
+};
+use crate::Error;
 
 use super::Avena;
 
-const KV_DEVICES: &str = "avena_devices";
+pub(crate) const KV_DEVICES: &str = "avena_devices";
 
 impl Avena {
-    pub fn ping(&self, device: &str) -> PingResponse {
-        let msg = self
-            .nc
-            .request(&format!("avena.ping.{}", device), Vec::from(PingRequest {}))
-            .unwrap();
+    pub async fn ping(&self, device: &str) -> Result<PingResponse, Error> {
+        self.request(subject_ping(device), &PingRequest {}).await
+    }
 
-        msg.data.as_slice().try_into().unwrap()
+    pub async fn status(&self, device: &str) -> Result<StatusResponse, Error> {
+        self.request(subject_status(device), &StatusRequest {})
+            .await
     }
 
-    pub fn get_devices(&self) -> HashMap<String, Device> {
-        let kv = self.js.key_value(KV_DEVICES).unwrap();
+    pub async fn get_devices(&self) -> Result<HashMap<String, Device>, Error> {
+        let kv = self.js.get_key_value(KV_DEVICES).await?;
 
         let mut devices = HashMap::new();
-        for key in kv.keys().unwrap() {
-            let device = kv.get(&key).unwrap();
+        let mut keys = kv.keys().await?;
+        while let Some(key) = keys.try_next().await? {
+            if let Some(device) = kv.get(&key).await? {
+                devices.insert(key, Device::try_from(device.as_ref())?);
+            }
+        }
 
-            if let Some(device) = device {
-                devices.insert(key, Device::try_from(device.as_slice()).unwrap());
+        Ok(devices)
+    }
+
+    /// Listen for device announces for `duration`, keeping the latest from each device.
+    ///
+    /// Failing to subscribe is reported as nothing discovered.
+    pub async fn discover(&self, duration: Duration) -> HashMap<String, Announce> {
+        let mut discovered = HashMap::new();
+        let Ok(mut sub) = self.nc.subscribe(ANNOUNCE_SUBJECT).await else {
+            return discovered;
+        };
+
+        let deadline = tokio::time::Instant::now() + duration;
+        while let Ok(Some(msg)) = tokio::time::timeout_at(deadline, sub.next()).await {
+            if let Ok(announce) = Announce::try_from(msg.payload.as_ref()) {
+                discovered.insert(announce.device.clone(), announce);
             }
         }
 
-        devices
+        let _ = sub.unsubscribe().await;
+
+        discovered
     }
 }
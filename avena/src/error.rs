@@ -0,0 +1,165 @@
+use async_nats::jetstream::{
+    consumer::{self, pull::OrderedError},
+    context::{self, CreateKeyValueError, CreateStreamError, KeyValueError},
+    kv,
+    stream::ConsumerError,
+};
+
+use crate::messages::{DecodeError, ErrorCode};
+
+/// Errors from establishing a connection to an Avena NATS server.
+#[derive(Debug, thiserror::Error)]
+pub enum ConnectError {
+    #[error("timed out connecting to {url}")]
+    Timeout { url: String },
+
+    #[error("authentication with {url} failed: {source}")]
+    Auth {
+        url: String,
+        #[source]
+        source: async_nats::ConnectError,
+    },
+
+    #[error("unable to connect to {url}: {source}")]
+    Nats {
+        url: String,
+        #[source]
+        source: async_nats::ConnectError,
+    },
+}
+
+impl ConnectError {
+    pub(crate) fn from_nats(url: &str, err: async_nats::ConnectError) -> Self {
+        use async_nats::ConnectErrorKind;
+
+        let url = url.to_string();
+        match err.kind() {
+            ConnectErrorKind::TimedOut => ConnectError::Timeout { url },
+            ConnectErrorKind::Authentication | ConnectErrorKind::AuthorizationViolation => {
+                ConnectError::Auth { url, source: err }
+            }
+            _ => ConnectError::Nats { url, source: err },
+        }
+    }
+}
+
+/// Errors returned by [`crate::Avena`] operations.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("request to {subject} timed out")]
+    Timeout { subject: String },
+
+    #[error("no responders on {subject}")]
+    NoResponders { subject: String },
+
+    #[error("request to {subject} failed: {source}")]
+    Request {
+        subject: String,
+        #[source]
+        source: async_nats::RequestError,
+    },
+
+    #[error("{subject} replied with {code}: {message}")]
+    Remote {
+        subject: String,
+        code: ErrorCode,
+        message: String,
+    },
+
+    #[error("unable to subscribe: {0}")]
+    Subscribe(#[from] async_nats::SubscribeError),
+
+    #[error("unable to publish: {0}")]
+    Publish(#[from] async_nats::PublishError),
+
+    #[error("unable to sign request: {0}")]
+    Sign(nkeys::error::Error),
+
+    #[error("no randomness available")]
+    Random,
+
+    #[error("{0}")]
+    Secret(String),
+
+    #[error("malformed message: {0}")]
+    Decode(#[from] DecodeError),
+
+    #[error("unable to open key-value bucket: {0}")]
+    KeyValue(#[from] KeyValueError),
+
+    #[error("unable to create key-value bucket: {0}")]
+    CreateKeyValue(#[from] CreateKeyValueError),
+
+    #[error("unable to write key-value entry: {0}")]
+    Put(#[from] kv::PutError),
+
+    #[error("unable to read key-value entry: {0}")]
+    Entry(#[from] kv::EntryError),
+
+    #[error("unable to delete key-value entry: {0}")]
+    Delete(#[from] kv::UpdateError),
+
+    #[error("unable to watch key-value bucket: {0}")]
+    Watch(#[from] kv::WatchError),
+
+    #[error("key-value watch failed: {0}")]
+    Watcher(#[from] kv::WatcherError),
+
+    #[error("unable to open stream: {0}")]
+    Stream(#[from] CreateStreamError),
+
+    #[error("unable to create stream consumer: {0}")]
+    Consumer(#[from] ConsumerError),
+
+    #[error("unable to read stream: {0}")]
+    Messages(#[from] consumer::StreamError),
+
+    #[error("stream read failed: {0}")]
+    Ordered(#[from] OrderedError),
+
+    #[error("unable to publish to stream: {0}")]
+    StreamPublish(#[from] context::PublishError),
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Decode(err.into())
+    }
+}
+
+impl Error {
+    pub(crate) fn from_request(subject: &str, err: async_nats::RequestError) -> Self {
+        use async_nats::RequestErrorKind;
+
+        let subject = subject.to_string();
+        match err.kind() {
+            RequestErrorKind::TimedOut => Error::Timeout { subject },
+            RequestErrorKind::NoResponders => Error::NoResponders { subject },
+            RequestErrorKind::Other => Error::Request {
+                subject,
+                source: err,
+            },
+        }
+    }
+
+    /// Whether this is opening a key-value bucket that does not exist.
+    pub(crate) fn is_missing_bucket(&self) -> bool {
+        use async_nats::jetstream::context::{
+            GetStreamError, GetStreamErrorKind, KeyValueErrorKind,
+        };
+        use async_nats::jetstream::ErrorCode;
+
+        let Error::KeyValue(err) = self else {
+            return false;
+        };
+        if err.kind() != KeyValueErrorKind::GetBucket {
+            return false;
+        }
+        std::error::Error::source(err)
+            .and_then(|source| source.downcast_ref::<GetStreamError>())
+            .is_some_and(|err| {
+                matches!(err.kind(), GetStreamErrorKind::JetStream(err)
+                    if err.error_code() == ErrorCode::STREAM_NOT_FOUND)
+            })
+    }
+}
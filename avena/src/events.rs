@@ -0,0 +1,289 @@
+//! The `avena_events` stream: an append-only log of every state-changing
+//! action a device takes (links, workload commands, reconciles, ...).
+//!
+//! Devices append with an [`EventLog`]; anyone can read the log back with
+//! [`Avena::events`] or follow it live with [`Avena::follow_events`].
+//! Events are ordered by their HLC timestamp, so actions taken on different
+//! devices line up causally even when wall clocks disagree.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_nats::jetstream::consumer::DeliverPolicy;
+use async_nats::jetstream::{self, consumer, stream};
+use futures::{Stream, StreamExt, TryStreamExt};
+use time::OffsetDateTime;
+
+use crate::hlc::{HlcClock, HybridTimestamp, DEFAULT_MAX_DRIFT};
+use crate::messages::{subject_event, Event, EventKind, Message};
+use crate::Error;
+
+use super::Avena;
+
+/// Name of the JetStream stream holding every device's events.
+pub const EVENTS_STREAM: &str = "avena_events";
+
+const EVENTS_SUBJECTS: &str = "avena.events.>";
+
+// Long enough to look back at a field season, short enough to bound storage
+// on small devices.
+const EVENTS_MAX_AGE: Duration = Duration::from_secs(90 * 24 * 60 * 60);
+
+async fn events_stream(js: &jetstream::Context) -> Result<stream::Stream, Error> {
+    let stream = js
+        .get_or_create_stream(stream::Config {
+            name: EVENTS_STREAM.to_string(),
+            subjects: vec![EVENTS_SUBJECTS.to_string()],
+            max_age: EVENTS_MAX_AGE,
+            ..Default::default()
+        })
+        .await?;
+    Ok(stream)
+}
+
+/// Appends one device's events to the events stream.
+#[derive(Clone)]
+pub struct EventLog {
+    js: jetstream::Context,
+    hlc: Arc<HlcClock>,
+    device: String,
+}
+
+impl EventLog {
+    /// Open the log for `device`, creating the stream if needed.
+    pub async fn open(
+        js: jetstream::Context,
+        hlc: Arc<HlcClock>,
+        device: &str,
+    ) -> Result<Self, Error> {
+        events_stream(&js).await?;
+        Ok(EventLog {
+            js,
+            hlc,
+            device: device.to_string(),
+        })
+    }
+
+    /// Stamp an event with the device's clock and wait for the stream to
+    /// store it.
+    pub async fn record(
+        &self,
+        kind: EventKind,
+        issuer: Option<String>,
+        payload: serde_json::Value,
+    ) -> Result<Event, Error> {
+        let event = Event {
+            timestamp: self.hlc.tick(),
+            device: self.device.clone(),
+            issuer,
+            kind,
+            payload,
+        };
+        self.js
+            .publish(subject_event(&self.device, kind), event.encode().into())
+            .await?
+            .await?;
+        Ok(event)
+    }
+
+    /// A timestamp from the device's clock, for state kept alongside an
+    /// event that could not be recorded.
+    pub fn tick(&self) -> HybridTimestamp {
+        self.hlc.tick()
+    }
+}
+
+/// Which events to return. Every bound is optional; an empty query matches
+/// everything.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EventQuery {
+    pub device: Option<String>,
+    /// Only events at or after this wall time (ms since the Unix epoch).
+    pub since_ms: Option<u64>,
+    /// Only events before this wall time (ms since the Unix epoch).
+    pub until_ms: Option<u64>,
+    /// Only events that happened after this timestamp.
+    pub after: Option<HybridTimestamp>,
+    /// Only events that happened before this timestamp.
+    pub before: Option<HybridTimestamp>,
+}
+
+impl EventQuery {
+    pub fn matches(&self, event: &Event) -> bool {
+        let ts = &event.timestamp;
+        if matches!(&self.device, Some(device) if *device != event.device) {
+            return false;
+        }
+        if matches!(self.since_ms, Some(t) if ts.wall_time_ms < t) {
+            return false;
+        }
+        if matches!(self.until_ms, Some(t) if ts.wall_time_ms >= t) {
+            return false;
+        }
+        if matches!(&self.after, Some(t) if ts <= t) {
+            return false;
+        }
+        !matches!(&self.before, Some(t) if ts >= t)
+    }
+
+    fn filter_subject(&self) -> String {
+        match &self.device {
+            Some(device) => format!("avena.events.{device}.>"),
+            None => EVENTS_SUBJECTS.to_string(),
+        }
+    }
+
+    /// Where a consumer reading this query starts, `unbounded` without a
+    /// lower bound. Stored events are skipped by the time the stream stored
+    /// them, allowing for devices whose clocks run ahead of the server.
+    fn deliver_policy(&self, unbounded: DeliverPolicy) -> DeliverPolicy {
+        let after_ms = self.after.as_ref().map(|t| t.wall_time_ms);
+        let Some(since_ms) = self.since_ms.max(after_ms) else {
+            return unbounded;
+        };
+        let start_ms = since_ms.saturating_sub(DEFAULT_MAX_DRIFT.as_millis() as u64);
+        let start_time = OffsetDateTime::from_unix_timestamp_nanos(start_ms as i128 * 1_000_000)
+            .unwrap_or(OffsetDateTime::UNIX_EPOCH);
+        DeliverPolicy::ByStartTime { start_time }
+    }
+}
+
+impl Avena {
+    /// Every stored event matching `query`, in HLC order.
+    pub async fn events(&self, query: &EventQuery) -> Result<Vec<Event>, Error> {
+        let stream = events_stream(&self.js).await?;
+        let consumer = stream
+            .create_consumer(consumer::pull::OrderedConfig {
+                filter_subject: query.filter_subject(),
+                deliver_policy: query.deliver_policy(DeliverPolicy::All),
+                ..Default::default()
+            })
+            .await?;
+
+        // An ordered consumer never ends on its own; stop once everything
+        // stored when it was created has been read.
+        let pending = consumer.cached_info().num_pending as usize;
+        if pending == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut events = Vec::new();
+        let mut messages = consumer.messages().await?.take(pending);
+        while let Some(msg) = messages.try_next().await? {
+            let event = Event::decode(&msg.payload)?;
+            if query.matches(&event) {
+                events.push(event);
+            }
+        }
+        events.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+        Ok(events)
+    }
+
+    /// Stream events matching `query` as they are recorded.
+    ///
+    /// Without a `since_ms` or `after` bound only new events are streamed;
+    /// with one, matching stored events are replayed first, in stream order.
+    pub async fn follow_events(
+        &self,
+        query: EventQuery,
+    ) -> Result<impl Stream<Item = Result<Event, Error>>, Error> {
+        let deliver_policy = query.deliver_policy(DeliverPolicy::New);
+        let stream = events_stream(&self.js).await?;
+        let consumer = stream
+            .create_consumer(consumer::pull::OrderedConfig {
+                filter_subject: query.filter_subject(),
+                deliver_policy,
+                ..Default::default()
+            })
+            .await?;
+
+        let events = consumer
+            .messages()
+            .await?
+            .map(|msg| Ok(Event::decode(&msg?.payload)?))
+            .try_filter(move |event| futures::future::ready(query.matches(event)));
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(device: &str, wall_time_ms: u64, counter: u32) -> Event {
+        Event {
+            timestamp: HybridTimestamp {
+                wall_time_ms,
+                counter,
+                node_id: device.to_string(),
+            },
+            device: device.to_string(),
+            issuer: None,
+            kind: EventKind::WorkloadStarted,
+            payload: serde_json::Value::Null,
+        }
+    }
+
+    #[test]
+    fn test_empty_query_matches_everything() {
+        let query = EventQuery::default();
+        assert!(query.matches(&event("dev1", 0, 0)));
+        assert_eq!(query.filter_subject(), "avena.events.>");
+    }
+
+    #[test]
+    fn test_query_by_device_and_time() {
+        let query = EventQuery {
+            device: Some("dev1".to_string()),
+            since_ms: Some(1_000),
+            until_ms: Some(2_000),
+            ..Default::default()
+        };
+        assert_eq!(query.filter_subject(), "avena.events.dev1.>");
+        assert!(query.matches(&event("dev1", 1_000, 0)));
+        assert!(!query.matches(&event("dev1", 2_000, 0)));
+        assert!(!query.matches(&event("dev1", 999, 5)));
+        assert!(!query.matches(&event("dev2", 1_500, 0)));
+    }
+
+    #[test]
+    fn test_query_by_hlc_range_is_exclusive() {
+        let query = EventQuery {
+            after: Some(event("dev1", 1_000, 1).timestamp),
+            before: Some(event("dev1", 1_000, 3).timestamp),
+            ..Default::default()
+        };
+        assert!(!query.matches(&event("dev1", 1_000, 1)));
+        assert!(query.matches(&event("dev1", 1_000, 2)));
+        assert!(!query.matches(&event("dev1", 1_000, 3)));
+    }
+
+    #[test]
+    fn test_lower_bound_skips_older_stored_events() {
+        let start = |secs| DeliverPolicy::ByStartTime {
+            start_time: OffsetDateTime::from_unix_timestamp(secs).unwrap(),
+        };
+
+        let query = EventQuery::default();
+        assert_eq!(query.deliver_policy(DeliverPolicy::New), DeliverPolicy::New);
+
+        let query = EventQuery {
+            since_ms: Some(1_700_000_120_000),
+            ..Default::default()
+        };
+        assert_eq!(
+            query.deliver_policy(DeliverPolicy::All),
+            start(1_700_000_060)
+        );
+
+        let query = EventQuery {
+            since_ms: Some(1_700_000_120_000),
+            after: Some(event("dev1", 1_700_000_180_000, 0).timestamp),
+            ..Default::default()
+        };
+        assert_eq!(
+            query.deliver_policy(DeliverPolicy::All),
+            start(1_700_000_120)
+        );
+    }
+}
@@ -0,0 +1,101 @@
+//! Scatter-gather requests to every device on the network.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::messages::{
+    Message, PingRequest, PingResponse, StatusRequest, StatusResponse, BROADCAST_PING_SUBJECT,
+    BROADCAST_STATUS_SUBJECT,
+};
+use crate::rpc::Reply;
+use crate::Error;
+
+use super::Avena;
+
+/// Replies gathered from the fleet, keyed by device id.
+#[derive(Debug, Clone)]
+pub struct FleetReport<T> {
+    pub replies: HashMap<String, Reply<T>>,
+    /// Registered devices that did not reply before the deadline, sorted.
+    pub missing: Vec<String>,
+}
+
+/// Replies that name the device that sent them.
+trait DeviceReply {
+    fn device(&self) -> &str;
+}
+
+impl DeviceReply for PingResponse {
+    fn device(&self) -> &str {
+        &self.device
+    }
+}
+
+impl DeviceReply for StatusResponse {
+    fn device(&self) -> &str {
+        &self.device
+    }
+}
+
+impl Avena {
+    /// Ping every reachable device, returning the replies that arrive within `timeout`.
+    ///
+    /// Failing to send the ping is reported as no replies; use
+    /// [`Avena::fleet_ping`] to tell the two apart.
+    pub async fn broadcast_ping(&self, timeout: Duration) -> HashMap<String, PingResponse> {
+        self.scatter::<_, PingResponse>(BROADCAST_PING_SUBJECT, &PingRequest {}, timeout)
+            .await
+            .unwrap_or_default()
+            .into_iter()
+            .map(|reply| (reply.response.device.clone(), reply.response))
+            .collect()
+    }
+
+    /// Ping every device and report which registered devices did not answer.
+    pub async fn fleet_ping(&self, timeout: Duration) -> Result<FleetReport<PingResponse>, Error> {
+        self.fleet(BROADCAST_PING_SUBJECT, &PingRequest {}, timeout)
+            .await
+    }
+
+    /// Collect the status of every device and report which registered devices did not answer.
+    pub async fn fleet_status(
+        &self,
+        timeout: Duration,
+    ) -> Result<FleetReport<StatusResponse>, Error> {
+        self.fleet(BROADCAST_STATUS_SUBJECT, &StatusRequest {}, timeout)
+            .await
+    }
+
+    async fn fleet<Req, Resp>(
+        &self,
+        subject: &str,
+        req: &Req,
+        timeout: Duration,
+    ) -> Result<FleetReport<Resp>, Error>
+    where
+        Req: Message,
+        Resp: Message + DeviceReply,
+    {
+        let replies: HashMap<_, _> = self
+            .scatter::<_, Resp>(subject, req, timeout)
+            .await?
+            .into_iter()
+            .map(|reply| (reply.response.device().to_string(), reply))
+            .collect();
+
+        let registered = match self.get_devices().await {
+            Ok(devices) => devices,
+            // No registry bucket yet means no device is expected to answer
+            Err(err) if err.is_missing_bucket() => HashMap::new(),
+            Err(err) => return Err(err),
+        };
+
+        let mut missing: Vec<_> = registered
+            .into_keys()
+            .filter(|id| !replies.contains_key(id))
+            .collect();
+        missing.sort();
+
+        Ok(FleetReport { replies, missing })
+    }
+}
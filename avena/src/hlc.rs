@@ -1,9 +1,37 @@
+use data_encoding::BASE64URL_NOPAD;
+use nkeys::KeyPair;
 use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
-use std::sync::{Arc, Mutex};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, UNIX_EPOCH};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+use crate::clock::{ClockSource, SystemClock};
 
 const HLC_HEADER: &str = "Avena-HLC";
+const HLC_SIGNATURE_HEADER: &str = "Avena-HLC-Signature";
+
+/// How far ahead of local wall time a remote timestamp may be by default.
+pub const DEFAULT_MAX_DRIFT: Duration = Duration::from_secs(60);
+
+/// How often [`HlcClock::spawn_saver`] persists the clock.
+pub const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How far past the persisted value a clock loaded from disk starts.
+///
+/// Timestamps handed out after the last save are lost in a crash. They are
+/// at most [`SAVE_INTERVAL`] of local time later, plus whatever a merged
+/// remote timestamp carried the clock ahead of it, which is bounded by
+/// [`DEFAULT_MAX_DRIFT`].
+pub const RESTART_MARGIN: Duration =
+    Duration::from_secs(SAVE_INTERVAL.as_secs() + DEFAULT_MAX_DRIFT.as_secs());
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct HybridTimestamp {
@@ -14,11 +42,11 @@ pub struct HybridTimestamp {
 
 impl HybridTimestamp {
     pub fn now(node_id: &str, last: Option<&HybridTimestamp>) -> Self {
-        let wall = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .map(|d| d.as_millis() as u64)
-            .unwrap_or(0);
+        Self::at(SystemClock.now_ms(), node_id, last)
+    }
 
+    /// Like [`HybridTimestamp::now`], with the wall time given explicitly.
+    pub fn at(wall: u64, node_id: &str, last: Option<&HybridTimestamp>) -> Self {
         match last {
             Some(prev) => {
                 if wall > prev.wall_time_ms {
@@ -43,16 +71,21 @@ impl HybridTimestamp {
         }
     }
 
+    /// Parse the `Avena-HLC` header of a message, if present and well formed.
+    pub fn from_headers(headers: Option<&async_nats::HeaderMap>) -> Option<Self> {
+        headers?.get(HLC_HEADER)?.as_str().parse().ok()
+    }
+
     pub fn is_newer_than(&self, other: &HybridTimestamp) -> bool {
         matches!(self.cmp(other), Ordering::Greater)
     }
 
     pub fn merge(&self, other: &HybridTimestamp, node_id: &str) -> Self {
-        let wall = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .map(|d| d.as_millis() as u64)
-            .unwrap_or(0);
+        self.merge_at(SystemClock.now_ms(), other, node_id)
+    }
 
+    /// Like [`HybridTimestamp::merge`], with the wall time given explicitly.
+    pub fn merge_at(&self, wall: u64, other: &HybridTimestamp, node_id: &str) -> Self {
         let max_wall = wall.max(self.wall_time_ms).max(other.wall_time_ms);
 
         let counter = if max_wall == wall && wall > self.wall_time_ms && wall > other.wall_time_ms {
@@ -113,61 +146,322 @@ impl std::str::FromStr for HybridTimestamp {
     }
 }
 
+/// What to do with a remote timestamp too far ahead of local wall time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DriftPolicy {
+    /// Ignore the timestamp entirely.
+    Reject,
+    /// Merge the timestamp as if it were exactly the maximum drift ahead.
+    Clamp,
+}
+
+/// Why a remote timestamp was not merged as-is.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum HlcRejection {
+    #[error("{node_id} is {drift_ms}ms ahead of local time")]
+    TooFarAhead { node_id: String, drift_ms: u64 },
+
+    #[error("timestamp from {node_id} is not signed by its trusted key")]
+    Untrusted { node_id: String },
+}
+
+/// Counts of remote timestamps the clock refused to merge as-is.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HlcStats {
+    pub rejected: u64,
+    pub clamped: u64,
+    pub untrusted: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    rejected: AtomicU64,
+    clamped: AtomicU64,
+    untrusted: AtomicU64,
+}
+
 #[derive(Clone)]
 pub struct HlcClock {
     node_id: String,
     state: Arc<Mutex<HybridTimestamp>>,
+    max_drift: Option<Duration>,
+    drift_policy: DriftPolicy,
+    signer: Option<Arc<KeyPair>>,
+    trusted: Arc<RwLock<Option<HashMap<String, String>>>>,
+    counters: Arc<Counters>,
+    source: Arc<dyn ClockSource>,
 }
 
 impl HlcClock {
     pub fn new(node_id: &str) -> Self {
-        let initial = HybridTimestamp::now(node_id, None);
-        HlcClock {
-            node_id: node_id.to_string(),
-            state: Arc::new(Mutex::new(initial)),
-        }
+        Self::with_source(node_id, Arc::new(SystemClock))
     }
 
+    /// A clock driven by `source` instead of the system clock.
+    pub fn with_source(node_id: &str, source: Arc<dyn ClockSource>) -> Self {
+        let initial = HybridTimestamp::at(source.now_ms(), node_id, None);
+        Self::with_state(node_id, initial, source)
+    }
+
+    /// Resume from a persisted timestamp.
+    ///
+    /// Starts [`RESTART_MARGIN`] past `persisted`, or at the current time if
+    /// that is later, so the clock never repeats a timestamp it handed out
+    /// before a crash.
     pub fn from_persisted(node_id: &str, persisted: HybridTimestamp) -> Self {
-        let merged = HybridTimestamp::now(node_id, Some(&persisted));
+        Self::from_persisted_with_source(node_id, persisted, Arc::new(SystemClock))
+    }
+
+    /// Like [`HlcClock::from_persisted`], driven by `source`.
+    pub fn from_persisted_with_source(
+        node_id: &str,
+        persisted: HybridTimestamp,
+        source: Arc<dyn ClockSource>,
+    ) -> Self {
+        let floor = HybridTimestamp {
+            wall_time_ms: persisted
+                .wall_time_ms
+                .saturating_add(RESTART_MARGIN.as_millis() as u64),
+            ..persisted
+        };
+        let merged = HybridTimestamp::at(source.now_ms(), node_id, Some(&floor));
+        Self::with_state(node_id, merged, source)
+    }
+
+    fn with_state(node_id: &str, state: HybridTimestamp, source: Arc<dyn ClockSource>) -> Self {
         HlcClock {
             node_id: node_id.to_string(),
-            state: Arc::new(Mutex::new(merged)),
+            state: Arc::new(Mutex::new(state)),
+            max_drift: Some(DEFAULT_MAX_DRIFT),
+            drift_policy: DriftPolicy::Reject,
+            signer: None,
+            trusted: Arc::new(RwLock::new(None)),
+            counters: Arc::new(Counters::default()),
+            source,
         }
     }
 
-    pub fn load_or_new(node_id: &str, path: &std::path::Path) -> Self {
-        if let Ok(contents) = std::fs::read_to_string(path) {
-            if let Ok(persisted) = serde_json::from_str::<HybridTimestamp>(&contents) {
-                return Self::from_persisted(node_id, persisted);
+    /// Bound how far ahead of local wall time a remote timestamp may be.
+    ///
+    /// `None` merges every timestamp, however far in the future.
+    pub fn with_max_drift(mut self, max_drift: Option<Duration>, policy: DriftPolicy) -> Self {
+        self.max_drift = max_drift;
+        self.drift_policy = policy;
+        self
+    }
+
+    /// Sign the timestamps this clock attaches to outgoing headers, so peers
+    /// that trust only known nodes can check they came from this device.
+    pub fn with_signing_key(mut self, key: Arc<KeyPair>) -> Self {
+        self.signer = Some(key);
+        self
+    }
+
+    /// Only merge timestamps signed by the public nkey listed for their
+    /// node id, such as the device keys in the registry. The node id in the
+    /// header is self-declared, so the signature is what is trusted.
+    /// `None` accepts every node, signed or not.
+    ///
+    /// Shared by every clone of this clock.
+    pub fn set_trusted_nodes(&self, nodes: Option<HashMap<String, String>>) {
+        *self.trusted.write().unwrap() = nodes;
+    }
+
+    pub fn stats(&self) -> HlcStats {
+        HlcStats {
+            rejected: self.counters.rejected.load(AtomicOrdering::Relaxed),
+            clamped: self.counters.clamped.load(AtomicOrdering::Relaxed),
+            untrusted: self.counters.untrusted.load(AtomicOrdering::Relaxed),
+        }
+    }
+
+    /// Load the clock saved at `path`, or start a new one if there is none.
+    ///
+    /// An unreadable file still proves the clock ran until it was last
+    /// written, so its modification time is used as the persisted value.
+    pub fn load_or_new(node_id: &str, path: &Path) -> Self {
+        Self::load_or_new_with_source(node_id, path, Arc::new(SystemClock))
+    }
+
+    /// Like [`HlcClock::load_or_new`], driven by `source`.
+    pub fn load_or_new_with_source(
+        node_id: &str,
+        path: &Path,
+        source: Arc<dyn ClockSource>,
+    ) -> Self {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Self::with_source(node_id, source)
+            }
+            Err(e) => {
+                warn!("Unable to read HLC state {}: {e}", path.display());
+                return Self::from_modified(node_id, path, source);
+            }
+        };
+
+        match serde_json::from_str::<HybridTimestamp>(&contents) {
+            Ok(persisted) => Self::from_persisted_with_source(node_id, persisted, source),
+            Err(e) => {
+                warn!("Corrupt HLC state {}: {e}", path.display());
+                Self::from_modified(node_id, path, source)
             }
         }
-        Self::new(node_id)
     }
 
-    pub fn save(&self, path: &std::path::Path) -> std::io::Result<()> {
-        let state = self.state.lock().unwrap();
-        let json = serde_json::to_string(&*state).map_err(|e| {
-            std::io::Error::new(std::io::ErrorKind::Other, e)
-        })?;
-        if let Some(parent) = path.parent() {
-            std::fs::create_dir_all(parent)?;
+    fn from_modified(node_id: &str, path: &Path, source: Arc<dyn ClockSource>) -> Self {
+        let modified = fs::metadata(path)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok());
+
+        match modified {
+            Some(modified) => Self::from_persisted_with_source(
+                node_id,
+                HybridTimestamp {
+                    wall_time_ms: modified.as_millis() as u64,
+                    counter: 0,
+                    node_id: node_id.to_string(),
+                },
+                source,
+            ),
+            None => Self::with_source(node_id, source),
+        }
+    }
+
+    /// Atomically write the current timestamp to `path`.
+    ///
+    /// The state goes to a temporary file that is synced and then renamed over
+    /// `path`, so a crash leaves either the old or the new state, never a torn
+    /// file.
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let json = serde_json::to_vec(&self.current())?;
+
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        fs::create_dir_all(parent)?;
+
+        let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+        tmp_name.push(".tmp");
+        let tmp = parent.join(tmp_name);
+
+        let mut file = File::create(&tmp)?;
+        file.write_all(&json)?;
+        file.sync_all()?;
+        drop(file);
+
+        fs::rename(&tmp, path)?;
+
+        // Make the rename itself durable
+        File::open(parent)?.sync_all()
+    }
+
+    /// Save the clock to `path` every [`SAVE_INTERVAL`] until the returned
+    /// handle is shut down or dropped, then save once more.
+    pub fn spawn_saver(&self, path: impl Into<PathBuf>) -> HlcSaver {
+        let clock = self.clone();
+        let path = path.into();
+        let (stop, mut stopped) = oneshot::channel::<()>();
+
+        let handle = tokio::spawn(async move {
+            loop {
+                // Resolves early on shutdown, and also when the handle is dropped
+                let stopping = tokio::time::timeout(SAVE_INTERVAL, &mut stopped)
+                    .await
+                    .is_ok();
+
+                let (c, p) = (clock.clone(), path.clone());
+                match tokio::task::spawn_blocking(move || c.save(&p)).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => warn!("Unable to save HLC state to {}: {e}", path.display()),
+                    Err(e) => warn!("HLC save task failed: {e}"),
+                }
+
+                if stopping {
+                    break;
+                }
+            }
+        });
+
+        HlcSaver {
+            stop: Some(stop),
+            handle,
         }
-        std::fs::write(path, json)
     }
 
     pub fn tick(&self) -> HybridTimestamp {
         let mut state = self.state.lock().unwrap();
-        let new_ts = HybridTimestamp::now(&self.node_id, Some(&state));
+        let new_ts = HybridTimestamp::at(self.source.now_ms(), &self.node_id, Some(&state));
         *state = new_ts.clone();
         new_ts
     }
 
+    /// Merge a remote timestamp, returning the local clock afterwards.
+    ///
+    /// Timestamps refused by [`HlcClock::try_receive`] leave the clock as is.
     pub fn receive(&self, remote: &HybridTimestamp) -> HybridTimestamp {
+        self.try_receive(remote).unwrap_or_else(|_| self.current())
+    }
+
+    /// Merge an unsigned remote timestamp, see [`HlcClock::try_receive_signed`].
+    pub fn try_receive(&self, remote: &HybridTimestamp) -> Result<HybridTimestamp, HlcRejection> {
+        self.try_receive_signed(remote, None)
+    }
+
+    /// Merge a remote timestamp if it is within the drift bound and, when
+    /// only trusted nodes are accepted, signed by its node's key. Clamped
+    /// timestamps are merged and count as success.
+    pub fn try_receive_signed(
+        &self,
+        remote: &HybridTimestamp,
+        signature: Option<&str>,
+    ) -> Result<HybridTimestamp, HlcRejection> {
+        if let Some(trusted) = self.trusted.read().unwrap().as_ref() {
+            let verified = trusted
+                .get(&remote.node_id)
+                .zip(signature)
+                .is_some_and(|(key, sig)| verify(key, remote, sig));
+            if !verified {
+                self.counters
+                    .untrusted
+                    .fetch_add(1, AtomicOrdering::Relaxed);
+                warn!(node_id = %remote.node_id, "Ignoring HLC not signed by a trusted node");
+                return Err(HlcRejection::Untrusted {
+                    node_id: remote.node_id.clone(),
+                });
+            }
+        }
+
+        let mut remote = remote.clone();
+        if let Some(max_drift) = self.max_drift {
+            let max_drift_ms = max_drift.as_millis() as u64;
+            let drift_ms = remote.wall_time_ms.saturating_sub(self.source.now_ms());
+            if drift_ms > max_drift_ms {
+                match self.drift_policy {
+                    DriftPolicy::Reject => {
+                        self.counters.rejected.fetch_add(1, AtomicOrdering::Relaxed);
+                        warn!(node_id = %remote.node_id, drift_ms, "Rejecting HLC too far ahead of local time");
+                        return Err(HlcRejection::TooFarAhead {
+                            node_id: remote.node_id,
+                            drift_ms,
+                        });
+                    }
+                    DriftPolicy::Clamp => {
+                        self.counters.clamped.fetch_add(1, AtomicOrdering::Relaxed);
+                        warn!(node_id = %remote.node_id, drift_ms, "Clamping HLC too far ahead of local time");
+                        remote.wall_time_ms -= drift_ms - max_drift_ms;
+                        remote.counter = 0;
+                    }
+                }
+            }
+        }
+
         let mut state = self.state.lock().unwrap();
-        let merged = state.merge(remote, &self.node_id);
+        let merged = state.merge_at(self.source.now_ms(), &remote, &self.node_id);
         *state = merged.clone();
-        merged
+        Ok(merged)
     }
 
     pub fn current(&self) -> HybridTimestamp {
@@ -180,20 +474,57 @@ impl HlcClock {
 
     pub fn attach_to_headers(&self, headers: &mut async_nats::HeaderMap) {
         let ts = self.tick();
-        headers.insert(HLC_HEADER, ts.to_string().as_str());
+        let ts = ts.to_string();
+        if let Some(signer) = &self.signer {
+            if let Ok(sig) = signer.sign(ts.as_bytes()) {
+                headers.insert(HLC_SIGNATURE_HEADER, BASE64URL_NOPAD.encode(&sig).as_str());
+            }
+        }
+        headers.insert(HLC_HEADER, ts.as_str());
     }
 
-    pub fn extract_and_merge(&self, headers: Option<&async_nats::HeaderMap>) -> Option<HybridTimestamp> {
-        let headers = headers?;
-        let value = headers.get(HLC_HEADER)?;
-        let remote: HybridTimestamp = value.as_str().parse().ok()?;
-        Some(self.receive(&remote))
+    pub fn extract_and_merge(
+        &self,
+        headers: Option<&async_nats::HeaderMap>,
+    ) -> Option<HybridTimestamp> {
+        let remote = HybridTimestamp::from_headers(headers)?;
+        let signature = headers
+            .and_then(|h| h.get(HLC_SIGNATURE_HEADER))
+            .map(|v| v.as_str());
+        self.try_receive_signed(&remote, signature).ok()
+    }
+}
+
+fn verify(public_key: &str, ts: &HybridTimestamp, signature: &str) -> bool {
+    let Ok(key) = KeyPair::from_public_key(public_key) else {
+        return false;
+    };
+    let Ok(sig) = BASE64URL_NOPAD.decode(signature.as_bytes()) else {
+        return false;
+    };
+    key.verify(ts.to_string().as_bytes(), &sig).is_ok()
+}
+
+/// Handle to the task started by [`HlcClock::spawn_saver`].
+pub struct HlcSaver {
+    stop: Option<oneshot::Sender<()>>,
+    handle: JoinHandle<()>,
+}
+
+impl HlcSaver {
+    /// Stop the saver, waiting for its final save to finish.
+    pub async fn shutdown(mut self) {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+        let _ = (&mut self.handle).await;
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::clock::ManualClock;
 
     #[test]
     fn test_now_without_prior() {
@@ -266,4 +597,256 @@ mod tests {
         assert!(ts1 < ts2);
         assert!(ts2.is_newer_than(&ts1));
     }
+
+    fn future(node_id: &str) -> HybridTimestamp {
+        HybridTimestamp {
+            wall_time_ms: u64::MAX - 1000,
+            counter: 5,
+            node_id: node_id.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_receive_rejects_far_future() {
+        let clock = HlcClock::new("local");
+        let before = clock.current();
+
+        let err = clock.try_receive(&future("liar")).unwrap_err();
+        assert!(matches!(err, HlcRejection::TooFarAhead { ref node_id, .. } if node_id == "liar"));
+
+        let after = clock.receive(&future("liar"));
+        assert!(after.wall_time_ms < before.wall_time_ms + DEFAULT_MAX_DRIFT.as_millis() as u64);
+        assert_eq!(clock.stats().rejected, 2);
+    }
+
+    #[test]
+    fn test_receive_clamps_far_future() {
+        let max_drift = Duration::from_secs(10);
+        let clock = HlcClock::new("local").with_max_drift(Some(max_drift), DriftPolicy::Clamp);
+        let before = SystemClock.now_ms();
+
+        let merged = clock.try_receive(&future("liar")).unwrap();
+        assert!(merged.wall_time_ms <= SystemClock.now_ms() + max_drift.as_millis() as u64);
+        assert!(merged.wall_time_ms >= before + max_drift.as_millis() as u64);
+        assert_eq!(clock.stats().clamped, 1);
+    }
+
+    #[test]
+    fn test_receive_within_drift() {
+        let clock = HlcClock::new("local");
+        let remote = HybridTimestamp {
+            wall_time_ms: SystemClock.now_ms() + 5_000,
+            counter: 0,
+            node_id: "peer".to_string(),
+        };
+
+        let merged = clock.try_receive(&remote).unwrap();
+        assert!(
+            merged
+                >= HybridTimestamp {
+                    node_id: "local".to_string(),
+                    ..remote
+                }
+        );
+        assert_eq!(clock.stats(), HlcStats::default());
+    }
+
+    #[test]
+    fn test_receive_untrusted() {
+        let dev1 = Arc::new(KeyPair::new_user());
+        let clock = HlcClock::new("local");
+        clock.set_trusted_nodes(Some(HashMap::from([(
+            "dev1".to_string(),
+            dev1.public_key(),
+        )])));
+
+        // Unknown nodes and unsigned timestamps are both refused
+        let remote = HybridTimestamp::now("stranger", None);
+        assert!(matches!(
+            clock.try_receive(&remote),
+            Err(HlcRejection::Untrusted { .. })
+        ));
+        assert!(matches!(
+            clock.try_receive(&HybridTimestamp::now("dev1", None)),
+            Err(HlcRejection::Untrusted { .. })
+        ));
+        assert_eq!(clock.stats().untrusted, 2);
+    }
+
+    #[test]
+    fn test_receive_signed_headers() {
+        let dev1 = Arc::new(KeyPair::new_user());
+        let sender = HlcClock::new("dev1").with_signing_key(dev1.clone());
+        let clock = HlcClock::new("local");
+        clock.set_trusted_nodes(Some(HashMap::from([(
+            "dev1".to_string(),
+            dev1.public_key(),
+        )])));
+
+        let mut headers = async_nats::HeaderMap::new();
+        sender.attach_to_headers(&mut headers);
+        assert!(clock.extract_and_merge(Some(&headers)).is_some());
+        assert_eq!(clock.stats().untrusted, 0);
+    }
+
+    #[test]
+    fn test_receive_forged_node_id() {
+        let dev1 = KeyPair::new_user();
+        let clock = HlcClock::new("local");
+        clock.set_trusted_nodes(Some(HashMap::from([(
+            "dev1".to_string(),
+            dev1.public_key(),
+        )])));
+
+        // Another key claiming to be dev1
+        let forger = HlcClock::new("dev1").with_signing_key(Arc::new(KeyPair::new_user()));
+        let mut headers = async_nats::HeaderMap::new();
+        forger.attach_to_headers(&mut headers);
+        assert!(clock.extract_and_merge(Some(&headers)).is_none());
+
+        // A signed timestamp whose contents were altered in transit
+        let ts = HybridTimestamp::now("dev1", None);
+        let sig = BASE64URL_NOPAD.encode(&dev1.sign(ts.to_string().as_bytes()).unwrap());
+        let altered = HybridTimestamp {
+            counter: ts.counter + 1,
+            ..ts.clone()
+        };
+        assert!(clock.try_receive_signed(&altered, Some(&sig)).is_err());
+        assert!(clock.try_receive_signed(&ts, Some(&sig)).is_ok());
+        assert_eq!(clock.stats().untrusted, 2);
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state/hlc.json");
+
+        let clock = HlcClock::new("node1");
+        let saved = clock.tick();
+        clock.save(&path).unwrap();
+        assert!(!dir.path().join("state/hlc.json.tmp").exists());
+
+        let loaded = HlcClock::load_or_new("node1", &path).current();
+        assert!(loaded.wall_time_ms >= saved.wall_time_ms + RESTART_MARGIN.as_millis() as u64);
+    }
+
+    #[test]
+    fn test_restart_after_merge_before_crash() {
+        // The last save was a full interval ago, and since then the clock
+        // merged a remote timestamp as far ahead as it accepts
+        let clock = HlcClock::new("node1");
+        let saved = HybridTimestamp {
+            wall_time_ms: SystemClock.now_ms() - SAVE_INTERVAL.as_millis() as u64,
+            ..clock.current()
+        };
+        let remote = HybridTimestamp {
+            wall_time_ms: SystemClock.now_ms() + DEFAULT_MAX_DRIFT.as_millis() as u64 - 1000,
+            counter: 5,
+            node_id: "node2".to_string(),
+        };
+        let handed_out = clock.try_receive(&remote).unwrap();
+
+        let restarted = HlcClock::from_persisted("node1", saved);
+        assert!(restarted.tick() > handed_out);
+    }
+
+    #[test]
+    fn test_load_corrupt_state_stays_monotonic() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hlc.json");
+        fs::write(&path, "{\"wall_time_ms\": 17").unwrap();
+        let written = SystemClock.now_ms();
+
+        let loaded = HlcClock::load_or_new("node1", &path).current();
+        assert!(loaded.wall_time_ms >= written + RESTART_MARGIN.as_millis() as u64 - 1000);
+    }
+
+    #[test]
+    fn test_load_missing_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let loaded = HlcClock::load_or_new("node1", &dir.path().join("hlc.json")).current();
+        assert!(loaded.wall_time_ms <= SystemClock.now_ms());
+    }
+
+    #[test]
+    fn test_load_with_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hlc.json");
+        let source = ManualClock::new(1_000_000);
+
+        let missing = HlcClock::load_or_new_with_source("node1", &path, Arc::new(source.clone()));
+        assert_eq!(missing.current().wall_time_ms, 1_000_000);
+
+        // A source past the persisted floor wins, and keeps driving the clock
+        let persisted = HybridTimestamp {
+            wall_time_ms: 1_000,
+            counter: 7,
+            node_id: "node1".to_string(),
+        };
+        fs::write(&path, serde_json::to_vec(&persisted).unwrap()).unwrap();
+        let loaded = HlcClock::load_or_new_with_source("node1", &path, Arc::new(source.clone()));
+        assert_eq!(loaded.current().wall_time_ms, 1_000_000);
+        source.advance(Duration::from_millis(5));
+        assert_eq!(loaded.tick().wall_time_ms, 1_000_005);
+    }
+
+    #[tokio::test]
+    async fn test_saver_saves_on_shutdown() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hlc.json");
+
+        let clock = HlcClock::new("node1");
+        let saver = clock.spawn_saver(&path);
+        let last = clock.tick();
+        saver.shutdown().await;
+
+        let persisted: HybridTimestamp =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(persisted, last);
+    }
+
+    #[test]
+    fn test_frozen_clock_advances_counter() {
+        let source = ManualClock::new(1_000);
+        let clock = HlcClock::with_source("node1", Arc::new(source.clone()));
+
+        let first = clock.tick();
+        let second = clock.tick();
+        assert_eq!(second.wall_time_ms, 1_000);
+        assert_eq!(second.counter, first.counter + 1);
+
+        source.advance(Duration::from_millis(1));
+        let third = clock.tick();
+        assert_eq!((third.wall_time_ms, third.counter), (1_001, 0));
+    }
+
+    #[test]
+    fn test_clock_jumping_back_stays_monotonic() {
+        let source = ManualClock::new(10_000);
+        let clock = HlcClock::with_source("node1", Arc::new(source.clone()));
+        let before = clock.tick();
+
+        source.set(5_000);
+        let after = clock.tick();
+        assert!(after > before);
+        assert_eq!(after.wall_time_ms, 10_000);
+    }
+
+    #[test]
+    fn test_drift_measured_against_source() {
+        let source = ManualClock::new(1_000_000);
+        let clock = HlcClock::with_source("node1", Arc::new(source.clone()));
+        let remote = HybridTimestamp {
+            wall_time_ms: 1_000_000 + 2 * DEFAULT_MAX_DRIFT.as_millis() as u64,
+            counter: 0,
+            node_id: "peer".to_string(),
+        };
+
+        assert!(clock.try_receive(&remote).is_err());
+        source.advance(DEFAULT_MAX_DRIFT);
+        assert_eq!(
+            clock.try_receive(&remote).unwrap().wall_time_ms,
+            remote.wall_time_ms
+        );
+    }
 }
@@ -1,29 +1,129 @@
-use nats::{connect, jetstream, jetstream::JetStream, Connection};
+use std::sync::Arc;
+use std::time::Duration;
 
-pub mod messages;
+use async_nats::{jetstream, Client, ConnectOptions};
+
+mod error;
 
+pub mod clock;
 pub mod devices;
+pub mod events;
+pub mod fleet;
+pub mod hlc;
+pub mod links;
+pub mod messages;
+pub mod presence;
+pub mod reported;
+pub mod rpc;
+pub mod secrets;
+pub mod streams;
+pub mod test_utils;
+
+pub use error::{ConnectError, Error};
+
+use hlc::HlcClock;
+use messages::{Encoding, Message};
+use rpc::RpcClient;
 
 pub struct Avena {
-    nc: Connection,
-    js: JetStream,
+    nc: Client,
+    js: jetstream::Context,
+    hlc: Arc<HlcClock>,
+    rpc: RpcClient,
 }
 
 impl Avena {
-    pub fn connect(connection_urls: &str) -> Self {
-        // FIXME: Need library errors
-        let nc = connect(connection_urls).unwrap();
+    /// Connect to an unauthenticated NATS server.
+    pub async fn connect(connection_urls: &str) -> Result<Self, ConnectError> {
+        Self::connect_with_options(connection_urls, ConnectOptions::new()).await
+    }
+
+    /// Connect to a NATS server using user/password authentication.
+    pub async fn connect_with_auth(
+        connection_urls: &str,
+        user: &str,
+        password: &str,
+    ) -> Result<Self, ConnectError> {
+        let options = ConnectOptions::with_user_and_password(user.into(), password.into());
+        Self::connect_with_options(connection_urls, options).await
+    }
+
+    /// Connect with caller supplied options (credentials, TLS, timeouts, ...).
+    pub async fn connect_with_options(
+        connection_urls: &str,
+        options: ConnectOptions,
+    ) -> Result<Self, ConnectError> {
+        let nc = options
+            .connect(connection_urls)
+            .await
+            .map_err(|e| ConnectError::from_nats(connection_urls, e))?;
         let js = jetstream::new(nc.clone());
 
-        Avena { nc, js }
+        // Client ids are unique per server, which is enough to break HLC ties.
+        let hlc = Arc::new(HlcClock::new(&format!(
+            "client-{}",
+            nc.server_info().client_id
+        )));
+        let rpc = RpcClient::new(nc.clone(), hlc.clone());
+
+        Ok(Avena { nc, js, hlc, rpc })
     }
 
-    pub fn nc(&self) -> Connection {
+    pub fn nc(&self) -> Client {
         // NATS clone is fast
         self.nc.clone()
     }
 
-    pub fn js(&self) -> JetStream {
+    pub fn js(&self) -> jetstream::Context {
         self.js.clone()
     }
+
+    /// Use the JetStream API of `domain`, for networks whose devices run
+    /// their own JetStream domain.
+    pub fn with_jetstream_domain(mut self, domain: &str) -> Self {
+        self.js = jetstream::with_domain(self.nc.clone(), domain);
+        self
+    }
+
+    /// Send requests in `encoding` instead of JSON.
+    ///
+    /// Devices reply in the encoding of the request, so this also picks the
+    /// encoding of replies.
+    pub fn with_encoding(mut self, encoding: Encoding) -> Self {
+        self.rpc = self.rpc.with_encoding(encoding);
+        self
+    }
+
+    /// The clock stamped onto every request this client sends.
+    pub fn hlc(&self) -> Arc<HlcClock> {
+        self.hlc.clone()
+    }
+
+    /// Send a typed request and wait for its typed reply.
+    pub async fn request<Req: Message, Resp: Message>(
+        &self,
+        subject: impl Into<String>,
+        req: &Req,
+    ) -> Result<Resp, Error> {
+        self.rpc.request(subject, req).await
+    }
+
+    pub async fn request_with_timeout<Req: Message, Resp: Message>(
+        &self,
+        subject: impl Into<String>,
+        req: &Req,
+        timeout: Duration,
+    ) -> Result<Resp, Error> {
+        self.rpc.request_with_timeout(subject, req, timeout).await
+    }
+
+    /// Send one request and collect every reply that arrives within `timeout`.
+    pub async fn scatter<Req: Message, Resp: Message>(
+        &self,
+        subject: impl Into<String>,
+        req: &Req,
+        timeout: Duration,
+    ) -> Result<Vec<rpc::Reply<Resp>>, Error> {
+        self.rpc.scatter(subject, req, timeout).await
+    }
 }
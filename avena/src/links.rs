@@ -0,0 +1,40 @@
+use std::time::Duration;
+
+use crate::messages::{
+    subject_link_register, subject_link_unregister, LinkRegisterRequest, LinkRegisterResponse,
+    LinkUnregisterRequest, LinkUnregisterResponse,
+};
+use crate::Error;
+
+use super::Avena;
+
+// Registering makes the device dial the remote and complete a handshake.
+const LINK_TIMEOUT: Duration = Duration::from_secs(30);
+
+impl Avena {
+    /// Ask `device` to link its NATS to `remote_url` as a leaf node.
+    pub async fn register_link(
+        &self,
+        device: &str,
+        remote_url: &str,
+    ) -> Result<LinkRegisterResponse, Error> {
+        let req = LinkRegisterRequest {
+            remote_url: remote_url.to_string(),
+        };
+        self.request_with_timeout(subject_link_register(device), &req, LINK_TIMEOUT)
+            .await
+    }
+
+    /// Ask `device` to drop its leaf link to `remote_url`.
+    pub async fn unregister_link(
+        &self,
+        device: &str,
+        remote_url: &str,
+    ) -> Result<LinkUnregisterResponse, Error> {
+        let req = LinkUnregisterRequest {
+            remote_url: remote_url.to_string(),
+        };
+        self.request_with_timeout(subject_link_unregister(device), &req, LINK_TIMEOUT)
+            .await
+    }
+}
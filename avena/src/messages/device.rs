@@ -0,0 +1,58 @@
+use serde::{Deserialize, Serialize};
+
+use super::WorkloadState;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PingRequest {}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PingResponse {
+    pub device: String,
+    pub avena_version: String,
+    #[serde(default)]
+    pub uptime_ms: u64,
+    #[serde(default)]
+    pub nats_name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatusRequest {}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatusResponse {
+    pub device: String,
+    pub avena_version: String,
+    #[serde(default)]
+    pub uptime_ms: u64,
+    #[serde(default)]
+    pub workloads: Vec<WorkloadState>,
+}
+
+/// Broadcast periodically by every device on [`super::ANNOUNCE_SUBJECT`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Announce {
+    pub device: String,
+    pub avena_version: String,
+    #[serde(default)]
+    pub uptime_ms: u64,
+    #[serde(default)]
+    pub nats_name: String,
+    #[serde(default)]
+    pub pubkey: Option<String>,
+}
+
+/// Device registry entry kept in the `avena_devices` KV bucket.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Device {
+    pub id: String,
+    pub version: String,
+    #[serde(default)]
+    pub last_seen_ms: Option<u64>,
+    /// Uptime the device reported in the announce that last updated this entry.
+    #[serde(default)]
+    pub uptime_ms: Option<u64>,
+    #[serde(default)]
+    pub nats_name: Option<String>,
+    #[serde(default)]
+    pub pubkey: Option<String>,
+}
@@ -0,0 +1,57 @@
+use async_nats::HeaderMap;
+
+/// Header naming the encoding of a message payload.
+pub const CONTENT_TYPE_HEADER: &str = "Content-Type";
+
+/// Wire encodings a [`super::Message`] can be written in.
+///
+/// JSON is the default and what every payload without a content type is
+/// assumed to be. CBOR carries the same fields at a fraction of the size,
+/// which matters on metered cellular links.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Encoding {
+    #[default]
+    Json,
+    Cbor,
+}
+
+impl Encoding {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Encoding::Json => "application/json",
+            Encoding::Cbor => "application/cbor",
+        }
+    }
+
+    /// Parse a content type, ignoring parameters such as `; charset=utf-8`.
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        match content_type.split(';').next()?.trim() {
+            "application/json" => Some(Encoding::Json),
+            "application/cbor" => Some(Encoding::Cbor),
+            _ => None,
+        }
+    }
+
+    /// Encoding of a message, falling back to JSON when it has no usable content type.
+    pub fn from_headers(headers: Option<&HeaderMap>) -> Self {
+        headers
+            .and_then(|h| h.get(CONTENT_TYPE_HEADER))
+            .and_then(|v| Self::from_content_type(v.as_str()))
+            .unwrap_or_default()
+    }
+
+    /// Mark a message as written in this encoding.
+    pub fn insert(&self, headers: &mut HeaderMap) {
+        headers.insert(CONTENT_TYPE_HEADER, self.content_type());
+    }
+}
+
+/// Errors decoding a payload in any [`Encoding`].
+#[derive(Debug, thiserror::Error)]
+pub enum DecodeError {
+    #[error("invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("invalid CBOR: {0}")]
+    Cbor(#[from] ciborium::de::Error<std::io::Error>),
+}
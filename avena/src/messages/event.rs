@@ -0,0 +1,68 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::hlc::HybridTimestamp;
+
+/// One state-changing action taken by a device, as stored in the events
+/// stream on [`super::subject_event`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+    pub timestamp: HybridTimestamp,
+    pub device: String,
+    /// Who asked for the action, `None` for actions the device took on its
+    /// own. For exec this is the public key that signed the request. For
+    /// other RPCs it is the HLC node id the caller claimed, which nothing
+    /// authenticates; treat it as a hint, not an audit identity.
+    #[serde(default)]
+    pub issuer: Option<String>,
+    pub kind: EventKind,
+    /// Details specific to `kind`.
+    #[serde(default)]
+    pub payload: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    LinkRegistered,
+    LinkUnregistered,
+    WorkloadStarted,
+    WorkloadStopped,
+    WorkloadRestarted,
+    WorkloadDeployed,
+    WorkloadRemoved,
+    WorkloadRejected,
+    WorkloadRolledBack,
+    WorkloadExec,
+    SecretChanged,
+    DeviceExpired,
+    #[serde(other)]
+    Unknown,
+}
+
+impl EventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::LinkRegistered => "link_registered",
+            EventKind::LinkUnregistered => "link_unregistered",
+            EventKind::WorkloadStarted => "workload_started",
+            EventKind::WorkloadStopped => "workload_stopped",
+            EventKind::WorkloadRestarted => "workload_restarted",
+            EventKind::WorkloadDeployed => "workload_deployed",
+            EventKind::WorkloadRemoved => "workload_removed",
+            EventKind::WorkloadRejected => "workload_rejected",
+            EventKind::WorkloadRolledBack => "workload_rolled_back",
+            EventKind::WorkloadExec => "workload_exec",
+            EventKind::SecretChanged => "secret_changed",
+            EventKind::DeviceExpired => "device_expired",
+            EventKind::Unknown => "unknown",
+        }
+    }
+}
+
+impl fmt::Display for EventKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
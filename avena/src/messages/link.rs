@@ -0,0 +1,57 @@
+use serde::{Deserialize, Serialize};
+
+/// Sent by a device asking to join another device's NATS as a leaf node.
+///
+/// `signature` signs `"{nonce}|{from_id}"` with the offering device's key.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LinkOffer {
+    pub from_id: String,
+    pub from_pubkey: String,
+    pub nonce: String,
+    #[serde(default)]
+    pub leaf_url: String,
+    pub signature: String,
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+/// Reply to a [`LinkOffer`].
+///
+/// `signature` signs `"ACCEPT|{nonce}"` with the accepting device's key.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LinkAccept {
+    pub to_id: String,
+    pub to_pubkey: String,
+    pub nonce_response: String,
+    #[serde(default)]
+    pub leaf_url: String,
+    #[serde(default)]
+    pub creds_inline: Option<String>,
+    pub signature: String,
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LinkRegisterRequest {
+    pub remote_url: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LinkRegisterResponse {
+    pub ok: bool,
+    #[serde(default)]
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LinkUnregisterRequest {
+    pub remote_url: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LinkUnregisterResponse {
+    pub ok: bool,
+    #[serde(default)]
+    pub message: String,
+}
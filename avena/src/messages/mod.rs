@@ -1,57 +1,612 @@
-use serde::{Deserialize, Serialize};
+//! Wire types shared by avenad, avenactl and anything else on an avena network.
+//!
+//! Every payload is encoded with the schema version it was written with (the
+//! `v` field) alongside its own fields. Decoding ignores fields it does not
+//! understand and fills in defaults for ones that are missing, so devices
+//! running different avenad versions can keep talking to each other.
+//! Payloads written before versioning existed decode as version 0.
+//!
+//! Payloads are JSON unless a `Content-Type` header says otherwise; see
+//! [`Encoding`]. Values stored in KV are always JSON.
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct PingRequest {}
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
-impl From<PingRequest> for Vec<u8> {
-    fn from(msg: PingRequest) -> Self {
-        serde_json::to_vec(&msg).unwrap()
-    }
+mod device;
+mod encoding;
+mod event;
+mod link;
+mod rpc;
+mod secret;
+mod stream;
+mod workload;
+
+pub use device::*;
+pub use encoding::*;
+pub use event::*;
+pub use link::*;
+pub use rpc::*;
+pub use secret::*;
+pub use stream::*;
+pub use workload::*;
+
+/// Periodic device announcements.
+pub const ANNOUNCE_SUBJECT: &str = "avena.announce";
+
+/// Pings answered by every device on the network.
+pub const BROADCAST_PING_SUBJECT: &str = "avena.broadcast.ping";
+
+/// Status requests answered by every device on the network.
+pub const BROADCAST_STATUS_SUBJECT: &str = "avena.broadcast.status";
+
+/// Link offers from devices that want to join this device's NATS as a leaf.
+pub const LINK_OFFER_SUBJECT: &str = "avena.link.offer";
+
+/// Subject a device answers direct pings on.
+pub fn subject_ping(device: &str) -> String {
+    format!("avena.device.{device}.ping")
 }
 
-impl TryFrom<&[u8]> for PingRequest {
-    type Error = serde_json::Error;
+/// Subject a device answers status requests on.
+pub fn subject_status(device: &str) -> String {
+    format!("avena.device.{device}.status")
+}
 
-    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        serde_json::from_slice(value)
-    }
+/// Subject a device answers workload list requests on.
+pub fn subject_workloads_list(device: &str) -> String {
+    format!("avena.device.{device}.workloads.list")
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct PingResponse {
-    pub device: String,
-    pub avena_version: String,
+/// Subject a device accepts workload commands (start, stop, logs, ...) on.
+pub fn subject_workload_command(device: &str) -> String {
+    format!("avena.device.{device}.workloads.command")
 }
 
-impl From<PingResponse> for Vec<u8> {
-    fn from(msg: PingResponse) -> Self {
-        serde_json::to_vec(&msg).unwrap()
-    }
+/// Subject a device streams workload logs from.
+pub fn subject_workload_logs(device: &str) -> String {
+    format!("avena.device.{device}.workloads.logs")
 }
 
-impl TryFrom<&[u8]> for PingResponse {
-    type Error = serde_json::Error;
+/// Subject a device runs commands in workload containers from.
+pub fn subject_workload_exec(device: &str) -> String {
+    format!("avena.device.{device}.workloads.exec")
+}
 
-    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        serde_json::from_slice(value)
-    }
+/// Subject a device accepts link registrations on.
+pub fn subject_link_register(device: &str) -> String {
+    format!("avena.device.{device}.link.register")
+}
+
+/// Subject a device accepts link removals on.
+pub fn subject_link_unregister(device: &str) -> String {
+    format!("avena.device.{device}.link.unregister")
 }
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Device {
-    pub id: String,
-    pub version: String,
+
+/// Subject a device records events of `kind` on.
+pub fn subject_event(device: &str, kind: EventKind) -> String {
+    format!("avena.events.{device}.{kind}")
 }
 
-impl From<Device> for Vec<u8> {
-    fn from(msg: Device) -> Self {
-        serde_json::to_vec(&msg).unwrap()
+/// A payload exchanged over NATS or stored in JetStream KV.
+pub trait Message: Serialize + DeserializeOwned {
+    /// Schema version written into every encoded payload of this type.
+    const SCHEMA_VERSION: u32;
+
+    fn encode(&self) -> Vec<u8> {
+        self.encode_as(Encoding::Json)
     }
+
+    fn decode(bytes: &[u8]) -> Result<Self, serde_json::Error> {
+        Self::decode_versioned(bytes).map(|(_, msg)| msg)
+    }
+
+    /// Decode a payload, also returning the schema version it was written with.
+    fn decode_versioned(bytes: &[u8]) -> Result<(u32, Self), serde_json::Error> {
+        let msg: Versioned<Self> = serde_json::from_slice(bytes)?;
+        Ok((msg.v, msg.body))
+    }
+
+    fn encode_as(&self, encoding: Encoding) -> Vec<u8> {
+        let msg = Versioned {
+            v: Self::SCHEMA_VERSION,
+            body: self,
+        };
+
+        match encoding {
+            Encoding::Json => serde_json::to_vec(&msg).expect("avena messages always serialize"),
+            Encoding::Cbor => {
+                let mut buf = Vec::new();
+                ciborium::ser::into_writer(&msg, &mut buf)
+                    .expect("avena messages always serialize");
+                buf
+            }
+        }
+    }
+
+    fn decode_as(bytes: &[u8], encoding: Encoding) -> Result<Self, DecodeError> {
+        let msg: Versioned<Self> = match encoding {
+            Encoding::Json => serde_json::from_slice(bytes)?,
+            Encoding::Cbor => ciborium::de::from_reader(bytes)?,
+        };
+        Ok(msg.body)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Versioned<T> {
+    #[serde(default)]
+    v: u32,
+    #[serde(flatten)]
+    body: T,
+}
+
+/// Implement [`Message`] and the byte conversions used throughout avena.
+macro_rules! messages {
+    ($($ty:ty => $version:expr),* $(,)?) => {
+        $(
+            impl Message for $ty {
+                const SCHEMA_VERSION: u32 = $version;
+            }
+
+            impl From<$ty> for Vec<u8> {
+                fn from(msg: $ty) -> Self {
+                    msg.encode()
+                }
+            }
+
+            impl TryFrom<&[u8]> for $ty {
+                type Error = serde_json::Error;
+
+                fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+                    <$ty>::decode(value)
+                }
+            }
+        )*
+    };
+}
+
+// Bump a version when the meaning of an existing field changes. Adding
+// optional fields does not need a bump.
+messages! {
+    PingRequest => 1,
+    PingResponse => 1,
+    StatusRequest => 1,
+    StatusResponse => 1,
+    Announce => 1,
+    Device => 1,
+    LinkOffer => 1,
+    LinkAccept => 1,
+    LinkRegisterRequest => 1,
+    LinkRegisterResponse => 1,
+    LinkUnregisterRequest => 1,
+    LinkUnregisterResponse => 1,
+    WorkloadDesiredState => 1,
+    WorkloadsListRequest => 1,
+    WorkloadsListResponse => 1,
+    WorkloadCommandRequest => 1,
+    WorkloadCommandResponse => 1,
+    ErrorResponse => 1,
+    Event => 1,
+    ReportedState => 1,
+    LogsRequest => 1,
+    ExecRequest => 1,
+    StreamOpened => 1,
+    OutputChunk => 1,
+    StreamControl => 1,
+    SealedSecret => 1,
 }
 
-impl TryFrom<&[u8]> for Device {
-    type Error = serde_json::Error;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_carries_version() {
+        let bytes = Vec::from(PingRequest {});
+        let value: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(value["v"], PingRequest::SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn test_decode_ignores_unknown_fields() {
+        let bytes = br#"{"v":7,"device":"dev1","avena_version":"9.9.9","uptime_ms":5,"nats_name":"n","from_the_future":{"a":1}}"#;
+        let (version, pong) = PingResponse::decode_versioned(bytes).unwrap();
+        assert_eq!(version, 7);
+        assert_eq!(pong.device, "dev1");
+        assert_eq!(pong.uptime_ms, 5);
+    }
+
+    #[test]
+    fn test_decode_unversioned_payload() {
+        let bytes = br#"{"id":"dev1","version":"0.1.0"}"#;
+        let (version, device) = Device::decode_versioned(bytes).unwrap();
+        assert_eq!(version, 0);
+        assert_eq!(device.id, "dev1");
+        assert_eq!(device.last_seen_ms, None);
+        assert_eq!(device.pubkey, None);
+    }
+
+    #[test]
+    fn test_workload_spec_defaults_missing_fields() {
+        let bytes = br#"{"name":"nginx","spec":{"image":"docker.io/nginx"}}"#;
+        let desired = WorkloadDesiredState::try_from(&bytes[..]).unwrap();
+        assert_eq!(desired.spec.image, "docker.io/nginx");
+        assert!(desired.spec.ports.is_empty());
+        assert!(desired.timestamp.is_none());
+        assert!(!desired.forced);
+    }
+
+    #[test]
+    fn test_volume_by_name_only() {
+        let bytes = br#"{"image":"nginx","volumes":["cache",{"name":"www","path":"/srv","readonly":true}]}"#;
+        let spec: WorkloadSpec = serde_json::from_slice(bytes).unwrap();
+        assert_eq!(spec.volumes[0].name, "cache");
+        assert_eq!(spec.volumes[0].path, "/data");
+        assert_eq!(spec.volumes[1].path, "/srv");
+        assert!(spec.volumes[1].readonly);
+    }
+
+    #[test]
+    fn test_dependency_by_name_is_required() {
+        let bytes =
+            br#"{"image":"ingest","depends_on":["db",{"workload":"metrics","kind":"wants"}]}"#;
+        let spec: WorkloadSpec = serde_json::from_slice(bytes).unwrap();
+        assert_eq!(spec.depends_on[0].workload, "db");
+        assert_eq!(spec.depends_on[0].kind, DependencyKind::Requires);
+        assert_eq!(spec.depends_on[1].kind, DependencyKind::Wants);
+    }
+
+    #[test]
+    fn test_state_without_sync_fields() {
+        let bytes = br#"{"name":"nginx","state":"running","image":"nginx"}"#;
+        let state: WorkloadState = serde_json::from_slice(bytes).unwrap();
+        assert_eq!(state.sync, WorkloadSync::Unknown);
+        assert_eq!(state.spec, None);
+        assert_eq!(state.pid, None);
+        assert_eq!(state.pull, None);
+    }
+
+    #[test]
+    fn test_unknown_workload_status() {
+        let bytes = br#"{"name":"nginx","state":"hibernating","restart_count":0,"image":"nginx"}"#;
+        let state: WorkloadState = serde_json::from_slice(bytes).unwrap();
+        assert_eq!(state.state, WorkloadStatus::Unknown);
+    }
+
+    #[test]
+    fn test_unknown_event_kind() {
+        let bytes = br#"{"timestamp":{"wall_time_ms":1,"counter":0,"node_id":"dev1"},"device":"dev1","kind":"device_exploded"}"#;
+        let event = Event::try_from(&bytes[..]).unwrap();
+        assert_eq!(event.kind, EventKind::Unknown);
+        assert!(event.payload.is_null());
+    }
+
+    #[test]
+    fn test_workload_command_roundtrip() {
+        let req = WorkloadCommandRequest {
+            workload: "nginx".to_string(),
+            command: WorkloadCommand::Logs { tail: Some(10) },
+        };
+        let decoded = WorkloadCommandRequest::try_from(Vec::from(req.clone()).as_slice()).unwrap();
+        assert_eq!(decoded, req);
+    }
+
+    fn spec() -> WorkloadSpec {
+        WorkloadSpec {
+            tag: Some("1.27".to_string()),
+            cmd: Some("nginx".to_string()),
+            args: vec!["-g".to_string(), "daemon off;".to_string()],
+            env: vec![("MODE".to_string(), "field".to_string())],
+            mounts: vec![MountSpec {
+                host: "/srv/www".to_string(),
+                container: "/usr/share/nginx/html".to_string(),
+                readonly: true,
+            }],
+            devices: vec!["/dev/ttyUSB0".to_string()],
+            perms: PermSpec {
+                publish: vec!["sensors.>".to_string()],
+                subscribe: vec!["config.nginx".to_string()],
+            },
+            ports: vec![PortSpec {
+                container: 80,
+                host: 8080,
+            }],
+            volumes: vec![VolumeSpec {
+                name: "cache".to_string(),
+                path: "/var/cache/nginx".to_string(),
+                readonly: false,
+            }],
+            health: Some(HealthCheckSpec {
+                cmd: "curl -f http://localhost/".to_string(),
+                interval_ms: Some(30_000),
+                timeout_ms: Some(5_000),
+                retries: Some(3),
+                start_period_ms: None,
+            }),
+            resources: ResourceSpec {
+                memory_max: Some(256 * 1024 * 1024),
+                cpu_quota_percent: Some(50),
+                cpu_weight: Some(100),
+                pids_max: Some(512),
+                io_weight: Some(50),
+            },
+            depends_on: vec![
+                DependencySpec {
+                    workload: "postgres".to_string(),
+                    kind: DependencyKind::Requires,
+                },
+                DependencySpec {
+                    workload: "metrics".to_string(),
+                    kind: DependencyKind::Wants,
+                },
+            ],
+            volume_retention: VolumeRetention::Purge,
+            secrets: vec![
+                SecretRef {
+                    name: "tls-key".to_string(),
+                    env: None,
+                },
+                SecretRef {
+                    name: "db-password".to_string(),
+                    env: Some("DB_PASSWORD".to_string()),
+                },
+            ],
+            stabilize_secs: Some(120),
+            ..WorkloadSpec::new("docker.io/nginx")
+        }
+    }
+
+    fn workload_state() -> WorkloadState {
+        WorkloadState {
+            name: "nginx".to_string(),
+            state: WorkloadStatus::Running,
+            exit_code: Some(0),
+            restart_count: 2,
+            started_at: Some(1_700_000_000_000),
+            pid: Some(4242),
+            image: "docker.io/nginx:1.27".to_string(),
+            health: Some(WorkloadHealth::Unhealthy),
+            limits: ResourceSpec {
+                memory_max: Some(256 * 1024 * 1024),
+                pids_max: Some(512),
+                ..Default::default()
+            },
+            usage: Some(ResourceUsage {
+                memory_bytes: Some(42 * 1024 * 1024),
+                cpu_ns: Some(1_500_000_000),
+            }),
+            spec: Some(spec()),
+            sync: WorkloadSync::OutOfSync,
+            digest: Some(
+                "sha256:4c0fdaa8b6341bfdeca5f18f7837462c80cff90527ee35ef185571e1c327beac"
+                    .to_string(),
+            ),
+            pull: Some(ImagePull {
+                image: "docker.io/nginx:1.28".to_string(),
+                state: PullState::Failed,
+                progress: Some("Copying blob 2d429b9e73a6 done".to_string()),
+                error: Some("connection reset by peer".to_string()),
+                started_at: 1_700_000_000_000,
+            }),
+            rollback: Some(WorkloadRollback {
+                timestamp: crate::hlc::HybridTimestamp {
+                    wall_time_ms: 1_700_000_000_000,
+                    counter: 3,
+                    node_id: "dev1".to_string(),
+                },
+                reason: "unit restarted 4 times".to_string(),
+                failed_spec: spec(),
+            }),
+        }
+    }
+
+    fn assert_roundtrip<T: Message + PartialEq + std::fmt::Debug>(msg: T) {
+        for encoding in [Encoding::Json, Encoding::Cbor] {
+            let decoded = T::decode_as(&msg.encode_as(encoding), encoding).unwrap();
+            assert_eq!(decoded, msg, "{encoding:?}");
+        }
+    }
+
+    #[test]
+    fn test_roundtrip_all_messages() {
+        assert_roundtrip(PingRequest {});
+        assert_roundtrip(PingResponse {
+            device: "dev1".to_string(),
+            avena_version: "0.1.0".to_string(),
+            uptime_ms: 42,
+            nats_name: "nats-dev1".to_string(),
+        });
+        assert_roundtrip(StatusRequest {});
+        assert_roundtrip(StatusResponse {
+            device: "dev1".to_string(),
+            avena_version: "0.1.0".to_string(),
+            uptime_ms: 42,
+            workloads: vec![workload_state()],
+        });
+        assert_roundtrip(Announce {
+            device: "dev1".to_string(),
+            avena_version: "0.1.0".to_string(),
+            uptime_ms: 42,
+            nats_name: "nats-dev1".to_string(),
+            pubkey: Some("UDEV1".to_string()),
+        });
+        assert_roundtrip(Device {
+            id: "dev1".to_string(),
+            version: "0.1.0".to_string(),
+            last_seen_ms: Some(1_700_000_000_000),
+            uptime_ms: Some(42),
+            nats_name: None,
+            pubkey: Some("UDEV1".to_string()),
+        });
+        assert_roundtrip(LinkOffer {
+            from_id: "dev1".to_string(),
+            from_pubkey: "UDEV1".to_string(),
+            nonce: "n0nce".to_string(),
+            leaf_url: "nats://dev1:7422".to_string(),
+            signature: "c2ln".to_string(),
+            token: None,
+        });
+        assert_roundtrip(LinkAccept {
+            to_id: "dev2".to_string(),
+            to_pubkey: "UDEV2".to_string(),
+            nonce_response: "n0nce".to_string(),
+            leaf_url: "nats://dev2:7422".to_string(),
+            creds_inline: Some("-----BEGIN NATS USER JWT-----".to_string()),
+            signature: "c2ln".to_string(),
+            token: Some("secret".to_string()),
+        });
+        assert_roundtrip(LinkRegisterRequest {
+            remote_url: "nats://dev2:7422".to_string(),
+        });
+        assert_roundtrip(LinkRegisterResponse {
+            ok: true,
+            message: "stored link request".to_string(),
+        });
+        assert_roundtrip(LinkUnregisterRequest {
+            remote_url: "nats://dev2:7422".to_string(),
+        });
+        assert_roundtrip(LinkUnregisterResponse {
+            ok: false,
+            message: "no link found".to_string(),
+        });
+        assert_roundtrip(WorkloadDesiredState {
+            name: "nginx".to_string(),
+            spec: spec(),
+            timestamp: Some(crate::hlc::HybridTimestamp {
+                wall_time_ms: 1_700_000_000_000,
+                counter: 3,
+                node_id: "dev1".to_string(),
+            }),
+            issuer: Some("UISSUER".to_string()),
+            forced: true,
+        });
+        assert_roundtrip(WorkloadsListRequest {});
+        assert_roundtrip(WorkloadsListResponse {
+            device: "dev1".to_string(),
+            workloads: vec![WorkloadListItem {
+                name: "nginx".to_string(),
+                spec: Some(spec()),
+                state: WorkloadStatusLite {
+                    status: WorkloadStatus::Stopped,
+                    since: None,
+                    sync: WorkloadSync::Orphaned,
+                },
+            }],
+        });
+        for command in [
+            WorkloadCommand::Start,
+            WorkloadCommand::Stop,
+            WorkloadCommand::Restart,
+            WorkloadCommand::Logs { tail: Some(10) },
+        ] {
+            assert_roundtrip(WorkloadCommandRequest {
+                workload: "nginx".to_string(),
+                command,
+            });
+        }
+        assert_roundtrip(WorkloadCommandResponse {
+            ok: true,
+            message: String::new(),
+            logs: Some("started\n".to_string()),
+        });
+        assert_roundtrip(ReportedState {
+            device: "dev1".to_string(),
+            timestamp: crate::hlc::HybridTimestamp {
+                wall_time_ms: 1_700_000_000_000,
+                counter: 3,
+                node_id: "dev1".to_string(),
+            },
+            workloads: vec![workload_state()],
+        });
+        assert_roundtrip(LogsRequest {
+            workload: "nginx".to_string(),
+            inbox: "_INBOX.abc".to_string(),
+            follow: true,
+            since_ms: Some(1_700_000_000_000),
+            tail: Some(100),
+            window: 16,
+        });
+        assert_roundtrip(StreamOpened {
+            control: "_INBOX.def".to_string(),
+        });
+        assert_roundtrip(OutputChunk {
+            seq: 3,
+            kind: OutputKind::Stderr,
+            data: "listening on :80\n".to_string(),
+            done: true,
+            exit_code: Some(0),
+            error: None,
+        });
+        assert_roundtrip(StreamControl {
+            acked: 3,
+            cancel: true,
+            input: Some("ls\n".to_string()),
+            close_input: true,
+        });
+        assert_roundtrip(ExecRequest {
+            workload: "nginx".to_string(),
+            inbox: "_INBOX.abc".to_string(),
+            command: vec!["sh".to_string(), "-c".to_string(), "ls /".to_string()],
+            tty: true,
+            timeout_secs: Some(60),
+            window: 16,
+            caller: "UCALLER".to_string(),
+            issued_ms: 1_700_000_000_000,
+            nonce: "bm9uY2U".to_string(),
+            signature: "c2ln".to_string(),
+        });
+        assert_roundtrip(SealedSecret {
+            device: "dev1".to_string(),
+            name: "db-password".to_string(),
+            recipient: "UDEVICE".to_string(),
+            ephemeral: "ZXBo".to_string(),
+            ciphertext: "Y2lwaGVy".to_string(),
+            timestamp: None,
+        });
+        assert_roundtrip(ErrorResponse::unauthorized("not allowed"));
+        assert_roundtrip(Event {
+            timestamp: crate::hlc::HybridTimestamp {
+                wall_time_ms: 1_700_000_000_000,
+                counter: 0,
+                node_id: "dev1".to_string(),
+            },
+            device: "dev1".to_string(),
+            issuer: Some("client-7".to_string()),
+            kind: EventKind::LinkRegistered,
+            payload: serde_json::json!({ "remote_url": "nats://dev2:7422" }),
+        });
+    }
+
+    #[test]
+    fn test_cbor_is_smaller() {
+        let msg = WorkloadsListResponse {
+            device: "dev1".to_string(),
+            workloads: vec![WorkloadListItem {
+                name: "nginx".to_string(),
+                spec: Some(spec()),
+                state: WorkloadStatusLite {
+                    status: WorkloadStatus::Running,
+                    since: Some(1_700_000_000_000),
+                    sync: WorkloadSync::InSync,
+                },
+            }],
+        };
+        assert!(msg.encode_as(Encoding::Cbor).len() < msg.encode_as(Encoding::Json).len());
+    }
+
+    #[test]
+    fn test_encoding_from_headers() {
+        assert_eq!(Encoding::from_headers(None), Encoding::Json);
+
+        let mut headers = async_nats::HeaderMap::new();
+        Encoding::Cbor.insert(&mut headers);
+        assert_eq!(Encoding::from_headers(Some(&headers)), Encoding::Cbor);
 
-    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        serde_json::from_slice(value)
+        let mut headers = async_nats::HeaderMap::new();
+        headers.insert(CONTENT_TYPE_HEADER, "application/x-unknown");
+        assert_eq!(Encoding::from_headers(Some(&headers)), Encoding::Json);
+        assert_eq!(
+            Encoding::from_content_type("application/json; charset=utf-8"),
+            Some(Encoding::Json)
+        );
     }
 }
@@ -0,0 +1,66 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The request could not be decoded or was otherwise invalid.
+    BadRequest,
+    /// The caller is not allowed to make this request.
+    Unauthorized,
+    /// The handler did not finish before the caller's deadline.
+    DeadlineExceeded,
+    /// The handler failed.
+    Internal,
+    #[serde(other)]
+    Unknown,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::BadRequest => "bad_request",
+            ErrorCode::Unauthorized => "unauthorized",
+            ErrorCode::DeadlineExceeded => "deadline_exceeded",
+            ErrorCode::Internal => "internal",
+            ErrorCode::Unknown => "unknown",
+        }
+    }
+}
+
+impl std::fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Reply sent in place of the expected response when a request fails.
+///
+/// Error replies are marked with the `Avena-Error` header so they can be told
+/// apart from successful replies without decoding the payload twice.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ErrorResponse {
+    pub code: ErrorCode,
+    #[serde(default)]
+    pub message: String,
+}
+
+impl ErrorResponse {
+    pub fn new(code: ErrorCode, message: impl std::fmt::Display) -> Self {
+        ErrorResponse {
+            code,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn bad_request(message: impl std::fmt::Display) -> Self {
+        Self::new(ErrorCode::BadRequest, message)
+    }
+
+    pub fn unauthorized(message: impl std::fmt::Display) -> Self {
+        Self::new(ErrorCode::Unauthorized, message)
+    }
+
+    pub fn internal(message: impl std::fmt::Display) -> Self {
+        Self::new(ErrorCode::Internal, message)
+    }
+}
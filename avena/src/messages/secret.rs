@@ -0,0 +1,20 @@
+use serde::{Deserialize, Serialize};
+
+use crate::hlc::HybridTimestamp;
+
+/// A secret value encrypted to one device, stored under `{device}/{name}` in
+/// the secrets bucket. Only the device holding the matching identity seed
+/// can open it; see [`crate::secrets`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SealedSecret {
+    pub device: String,
+    pub name: String,
+    /// Public key of the device identity the value is sealed to.
+    pub recipient: String,
+    /// The sender's one-time X25519 public key, base64url.
+    pub ephemeral: String,
+    /// The encrypted value, base64url.
+    pub ciphertext: String,
+    #[serde(default)]
+    pub timestamp: Option<HybridTimestamp>,
+}
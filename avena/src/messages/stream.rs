@@ -0,0 +1,64 @@
+use serde::{Deserialize, Serialize};
+
+/// Which output of the remote process a chunk came from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputKind {
+    #[default]
+    Stdout,
+    Stderr,
+    #[serde(other)]
+    Unknown,
+}
+
+/// Reply to a request that opens an output stream. Chunks follow on the
+/// caller's inbox; the caller acknowledges them and may cancel the stream on
+/// `control`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StreamOpened {
+    pub control: String,
+}
+
+/// One piece of output streamed from a device to a caller's inbox.
+///
+/// `seq` counts chunks from 1. A chunk with no data repeats the last `seq`
+/// and only shows the device is still there.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OutputChunk {
+    pub seq: u64,
+    #[serde(default)]
+    pub kind: OutputKind,
+    #[serde(default)]
+    pub data: String,
+    /// Set on the last chunk of the stream.
+    #[serde(default)]
+    pub done: bool,
+    /// Exit status of the remote process, on the last chunk.
+    #[serde(default)]
+    pub exit_code: Option<i32>,
+    /// Why the stream ended early, on the last chunk.
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+impl OutputChunk {
+    pub fn is_keepalive(&self) -> bool {
+        self.data.is_empty() && !self.done
+    }
+}
+
+/// Sent by the caller on a stream's control subject, both to acknowledge
+/// chunks and to show it is still listening.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StreamControl {
+    /// Every chunk up to and including this `seq` has been received.
+    pub acked: u64,
+    #[serde(default)]
+    pub cancel: bool,
+    /// Input for the remote process, on streams that take it.
+    #[serde(default)]
+    pub input: Option<String>,
+    /// No more input follows.
+    #[serde(default)]
+    pub close_input: bool,
+}
@@ -0,0 +1,549 @@
+use serde::{Deserialize, Serialize};
+
+use crate::hlc::HybridTimestamp;
+
+/// Desired state of one workload, stored under `device/{device-id}/{name}`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WorkloadDesiredState {
+    pub name: String,
+    pub spec: WorkloadSpec,
+    #[serde(default)]
+    pub timestamp: Option<HybridTimestamp>,
+    #[serde(default)]
+    pub issuer: Option<String>,
+    #[serde(default)]
+    pub forced: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WorkloadSpec {
+    pub image: String,
+    #[serde(default)]
+    pub tag: Option<String>,
+    #[serde(default)]
+    pub cmd: Option<String>,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: Vec<(String, String)>,
+    #[serde(default)]
+    pub mounts: Vec<MountSpec>,
+    #[serde(default)]
+    pub devices: Vec<String>,
+    #[serde(default)]
+    pub perms: PermSpec,
+    #[serde(default)]
+    pub ports: Vec<PortSpec>,
+    #[serde(default)]
+    pub volumes: Vec<VolumeSpec>,
+    #[serde(default)]
+    pub health: Option<HealthCheckSpec>,
+    #[serde(default)]
+    pub resources: ResourceSpec,
+    /// Other workloads on the same device this one starts after.
+    #[serde(default)]
+    pub depends_on: Vec<DependencySpec>,
+    /// What happens to the workload's named volumes when it is removed.
+    #[serde(default)]
+    pub volume_retention: VolumeRetention,
+    /// Device secrets handed to the workload.
+    #[serde(default)]
+    pub secrets: Vec<SecretRef>,
+    /// Seconds an update must keep running, and healthy if it has a health
+    /// check, before it is kept as the deployment to roll back to. `None`
+    /// waits [`DEFAULT_STABILIZE_SECS`]; `0` turns automatic rollback off.
+    #[serde(default)]
+    pub stabilize_secs: Option<u64>,
+}
+
+impl WorkloadSpec {
+    /// A spec running `image` with everything else left at its default.
+    pub fn new(image: impl Into<String>) -> Self {
+        WorkloadSpec {
+            image: image.into(),
+            tag: None,
+            cmd: None,
+            args: vec![],
+            env: vec![],
+            mounts: vec![],
+            devices: vec![],
+            perms: PermSpec::default(),
+            ports: vec![],
+            volumes: vec![],
+            health: None,
+            resources: ResourceSpec::default(),
+            depends_on: vec![],
+            volume_retention: VolumeRetention::default(),
+            secrets: vec![],
+            stabilize_secs: None,
+        }
+    }
+}
+
+/// How long an updated workload is watched when its spec does not say.
+pub const DEFAULT_STABILIZE_SECS: u64 = 60;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MountSpec {
+    pub host: String,
+    pub container: String,
+    #[serde(default)]
+    pub readonly: bool,
+}
+
+/// A named podman volume mounted into the workload.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "VolumeSpecRepr")]
+pub struct VolumeSpec {
+    pub name: String,
+    pub path: String,
+    #[serde(default)]
+    pub readonly: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VolumeRetention {
+    /// Keep the podman volume and its data for a later deployment.
+    #[default]
+    Retain,
+    /// Delete the podman volume along with the workload.
+    Purge,
+}
+
+/// Specs written before volumes had a mount path list them by name only and
+/// mounted each of them at this path.
+const LEGACY_VOLUME_PATH: &str = "/data";
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum VolumeSpecRepr {
+    Name(String),
+    Full {
+        name: String,
+        path: String,
+        #[serde(default)]
+        readonly: bool,
+    },
+}
+
+impl From<VolumeSpecRepr> for VolumeSpec {
+    fn from(repr: VolumeSpecRepr) -> Self {
+        match repr {
+            VolumeSpecRepr::Name(name) => VolumeSpec {
+                name,
+                path: LEGACY_VOLUME_PATH.to_string(),
+                readonly: false,
+            },
+            VolumeSpecRepr::Full {
+                name,
+                path,
+                readonly,
+            } => VolumeSpec {
+                name,
+                path,
+                readonly,
+            },
+        }
+    }
+}
+
+/// A workload that has to be started before this one. Given as a bare name,
+/// the dependency is required.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "DependencySpecRepr")]
+pub struct DependencySpec {
+    pub workload: String,
+    #[serde(default)]
+    pub kind: DependencyKind,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DependencyKind {
+    /// Do not run without the dependency, and stop when it stops.
+    #[default]
+    Requires,
+    /// Start after the dependency if it is there, but run without it.
+    Wants,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum DependencySpecRepr {
+    Name(String),
+    Full {
+        workload: String,
+        #[serde(default)]
+        kind: DependencyKind,
+    },
+}
+
+impl From<DependencySpecRepr> for DependencySpec {
+    fn from(repr: DependencySpecRepr) -> Self {
+        match repr {
+            DependencySpecRepr::Name(workload) => DependencySpec {
+                workload,
+                kind: DependencyKind::Requires,
+            },
+            DependencySpecRepr::Full { workload, kind } => DependencySpec { workload, kind },
+        }
+    }
+}
+
+/// A secret stored for the workload's device, mounted as a file at
+/// `/run/secrets/{name}` or, with `env`, set as that environment variable.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SecretRef {
+    pub name: String,
+    #[serde(default)]
+    pub env: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PortSpec {
+    pub container: u16,
+    pub host: u16,
+}
+
+/// A command podman runs inside the container to decide whether it is
+/// healthy. Unset durations and retries use podman's defaults.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HealthCheckSpec {
+    pub cmd: String,
+    #[serde(default)]
+    pub interval_ms: Option<u64>,
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub retries: Option<u32>,
+    /// Failures during this long after start do not count against `retries`.
+    #[serde(default)]
+    pub start_period_ms: Option<u64>,
+}
+
+/// Limits systemd enforces on a workload's unit. Unset limits are left to
+/// systemd's defaults.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ResourceSpec {
+    /// Hard memory limit, in bytes.
+    #[serde(default)]
+    pub memory_max: Option<u64>,
+    /// CPU time the workload may use, in percent of one CPU (200 = two CPUs).
+    #[serde(default)]
+    pub cpu_quota_percent: Option<u32>,
+    /// Share of CPU time under contention, 1-10000 (systemd default 100).
+    #[serde(default)]
+    pub cpu_weight: Option<u32>,
+    #[serde(default)]
+    pub pids_max: Option<u64>,
+    /// Share of block IO under contention, 1-10000 (systemd default 100).
+    #[serde(default)]
+    pub io_weight: Option<u32>,
+}
+
+/// Resources a running workload is using, as accounted by systemd.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ResourceUsage {
+    #[serde(default)]
+    pub memory_bytes: Option<u64>,
+    /// CPU time used since the unit started, in nanoseconds.
+    #[serde(default)]
+    pub cpu_ns: Option<u64>,
+}
+
+/// NATS subjects a workload may publish and subscribe to.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PermSpec {
+    #[serde(default)]
+    pub publish: Vec<String>,
+    #[serde(default)]
+    pub subscribe: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkloadStatus {
+    Running,
+    Stopped,
+    Error,
+    #[serde(other)]
+    Unknown,
+}
+
+impl std::fmt::Display for WorkloadStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            WorkloadStatus::Running => "running",
+            WorkloadStatus::Stopped => "stopped",
+            WorkloadStatus::Error => "error",
+            WorkloadStatus::Unknown => "unknown",
+        })
+    }
+}
+
+/// Result of a workload's health check, as last observed by podman.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkloadHealth {
+    Starting,
+    Healthy,
+    Unhealthy,
+    #[serde(other)]
+    Unknown,
+}
+
+impl std::fmt::Display for WorkloadHealth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            WorkloadHealth::Starting => "starting",
+            WorkloadHealth::Healthy => "healthy",
+            WorkloadHealth::Unhealthy => "unhealthy",
+            WorkloadHealth::Unknown => "unknown",
+        })
+    }
+}
+
+/// Whether a workload runs the spec it is meant to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkloadSync {
+    /// Deployed from its current desired spec and running.
+    InSync,
+    /// Its desired spec is not deployed yet, or it is not running.
+    OutOfSync,
+    /// Present on the device without a desired spec.
+    Orphaned,
+    #[default]
+    #[serde(other)]
+    Unknown,
+}
+
+impl std::fmt::Display for WorkloadSync {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            WorkloadSync::InSync => "in sync",
+            WorkloadSync::OutOfSync => "out of sync",
+            WorkloadSync::Orphaned => "orphaned",
+            WorkloadSync::Unknown => "unknown",
+        })
+    }
+}
+
+/// Observed state of a workload on a device.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WorkloadState {
+    pub name: String,
+    pub state: WorkloadStatus,
+    /// Exit status of the last run, once it has exited.
+    #[serde(default)]
+    pub exit_code: Option<i32>,
+    /// Restarts systemd made since the unit was last started by hand.
+    #[serde(default)]
+    pub restart_count: u32,
+    /// Epoch milliseconds the current or last run started.
+    #[serde(default)]
+    pub started_at: Option<u64>,
+    #[serde(default)]
+    pub pid: Option<u32>,
+    pub image: String,
+    /// `None` when the workload has no health check.
+    #[serde(default)]
+    pub health: Option<WorkloadHealth>,
+    /// Limits in force on the workload's unit.
+    #[serde(default)]
+    pub limits: ResourceSpec,
+    #[serde(default)]
+    pub usage: Option<ResourceUsage>,
+    /// The desired spec from KV, `None` for orphaned workloads.
+    #[serde(default)]
+    pub spec: Option<WorkloadSpec>,
+    #[serde(default)]
+    pub sync: WorkloadSync,
+    /// Digest of the image the running container was created from.
+    #[serde(default)]
+    pub digest: Option<String>,
+    /// A pull of the workload's new image that is in progress, or that
+    /// failed and left the previous deployment running.
+    #[serde(default)]
+    pub pull: Option<ImagePull>,
+    /// The rollback in effect, until the desired spec changes.
+    #[serde(default)]
+    pub rollback: Option<WorkloadRollback>,
+}
+
+/// An update that failed within its stabilization period and was replaced by
+/// the last known-good deployment.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WorkloadRollback {
+    pub timestamp: HybridTimestamp,
+    pub reason: String,
+    /// The spec rolled back from. It is not deployed again until the desired
+    /// spec changes.
+    pub failed_spec: WorkloadSpec,
+}
+
+/// The pull of a workload's new image, made before its unit is switched over
+/// to it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImagePull {
+    pub image: String,
+    pub state: PullState,
+    /// The last progress line podman printed.
+    #[serde(default)]
+    pub progress: Option<String>,
+    #[serde(default)]
+    pub error: Option<String>,
+    /// Epoch milliseconds the pull started.
+    pub started_at: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PullState {
+    Pulling,
+    Failed,
+    #[serde(other)]
+    Unknown,
+}
+
+impl std::fmt::Display for PullState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            PullState::Pulling => "pulling",
+            PullState::Failed => "pull failed",
+            PullState::Unknown => "unknown",
+        })
+    }
+}
+
+/// The workload states a device last observed, kept under its id in the
+/// reported-state bucket.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReportedState {
+    pub device: String,
+    pub timestamp: HybridTimestamp,
+    #[serde(default)]
+    pub workloads: Vec<WorkloadState>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WorkloadStatusLite {
+    pub status: WorkloadStatus,
+    #[serde(default)]
+    pub since: Option<u64>,
+    #[serde(default)]
+    pub sync: WorkloadSync,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WorkloadListItem {
+    pub name: String,
+    /// The desired spec from KV, `None` for orphaned workloads.
+    #[serde(default)]
+    pub spec: Option<WorkloadSpec>,
+    pub state: WorkloadStatusLite,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WorkloadsListRequest {}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WorkloadsListResponse {
+    pub device: String,
+    #[serde(default)]
+    pub workloads: Vec<WorkloadListItem>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkloadCommand {
+    Start,
+    Stop,
+    Restart,
+    /// Returns the logs in one response. Use [`LogsRequest`] for large logs
+    /// or to follow them.
+    Logs { tail: Option<u32> },
+}
+
+/// Stream a workload's logs to `inbox` as [`super::OutputChunk`]s.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogsRequest {
+    pub workload: String,
+    pub inbox: String,
+    /// Keep streaming new lines until cancelled.
+    #[serde(default)]
+    pub follow: bool,
+    /// Only lines logged at or after this wall time (ms since the Unix epoch).
+    #[serde(default)]
+    pub since_ms: Option<u64>,
+    /// Only the last this many lines.
+    #[serde(default)]
+    pub tail: Option<u32>,
+    /// Chunks the device may send ahead of the caller's acknowledgements.
+    pub window: u32,
+}
+
+/// Run a command in a workload's container, streaming its output to `inbox`
+/// as [`super::OutputChunk`]s.
+///
+/// Devices only run requests signed by a key they allow, see
+/// [`ExecRequest::signed_payload`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExecRequest {
+    pub workload: String,
+    pub inbox: String,
+    pub command: Vec<String>,
+    /// Allocate a TTY and forward the caller's input to the command.
+    #[serde(default)]
+    pub tty: bool,
+    /// Kill the command after this many seconds.
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+    /// Chunks the device may send ahead of the caller's acknowledgements.
+    pub window: u32,
+    /// Public nkey of the caller.
+    pub caller: String,
+    /// When the caller signed the request (ms since the Unix epoch).
+    pub issued_ms: u64,
+    /// Random per request, so a device runs each signed request only once.
+    #[serde(default)]
+    pub nonce: String,
+    /// The caller's signature over [`Self::signed_payload`], base64url
+    /// without padding.
+    pub signature: String,
+}
+
+impl ExecRequest {
+    /// The bytes `signature` covers. They name `device`, so a request can
+    /// not be replayed against another device, and `nonce`, so it can not
+    /// be replayed against the same one.
+    pub fn signed_payload(&self, device: &str) -> Vec<u8> {
+        serde_json::to_vec(&(
+            device,
+            &self.workload,
+            &self.inbox,
+            &self.command,
+            self.tty,
+            self.timeout_secs,
+            self.issued_ms,
+            &self.nonce,
+        ))
+        .expect("exec requests always serialize")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WorkloadCommandRequest {
+    pub workload: String,
+    pub command: WorkloadCommand,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WorkloadCommandResponse {
+    pub ok: bool,
+    #[serde(default)]
+    pub message: String,
+    #[serde(default)]
+    pub logs: Option<String>,
+}
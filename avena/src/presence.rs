@@ -0,0 +1,295 @@
+//! Device presence derived from the `avena_devices` registry.
+//!
+//! Every device refreshes its registry entry when it announces itself, so the
+//! age of `last_seen_ms` says how recently it was heard from. A device is
+//! online while that age is under [`PresenceConfig::stale_after`], stale until
+//! [`PresenceConfig::offline_after`], and offline after that. Entries older
+//! than [`PresenceConfig::expire_after`] are deleted from the registry by
+//! avenad.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_nats::jetstream::kv::{Entry, Operation};
+use futures::{stream, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+
+use crate::devices::KV_DEVICES;
+use crate::messages::Device;
+use crate::Error;
+
+use super::Avena;
+
+/// How often [`Avena::watch_devices`] re-checks devices that went quiet.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Liveness thresholds, measured from a device's `last_seen_ms`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PresenceConfig {
+    pub stale_after: Duration,
+    pub offline_after: Duration,
+    /// Offline devices are removed from the registry once this old.
+    pub expire_after: Duration,
+}
+
+impl Default for PresenceConfig {
+    fn default() -> Self {
+        PresenceConfig {
+            stale_after: Duration::from_secs(30),
+            offline_after: Duration::from_secs(90),
+            expire_after: Duration::from_secs(7 * 24 * 60 * 60),
+        }
+    }
+}
+
+impl PresenceConfig {
+    /// Presence of a device last seen at `last_seen_ms`, as of `now_ms`.
+    ///
+    /// Devices that have never been seen are offline.
+    pub fn classify(&self, last_seen_ms: Option<u64>, now_ms: u64) -> Presence {
+        let Some(age) = last_seen_ms.map(|seen| now_ms.saturating_sub(seen)) else {
+            return Presence::Offline;
+        };
+
+        if age < self.stale_after.as_millis() as u64 {
+            Presence::Online
+        } else if age < self.offline_after.as_millis() as u64 {
+            Presence::Stale
+        } else {
+            Presence::Offline
+        }
+    }
+
+    /// Whether a registry entry last seen at `last_seen_ms` should be deleted.
+    ///
+    /// Entries that were never seen are left alone.
+    pub fn is_expired(&self, last_seen_ms: Option<u64>, now_ms: u64) -> bool {
+        last_seen_ms
+            .is_some_and(|seen| now_ms.saturating_sub(seen) >= self.expire_after.as_millis() as u64)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Presence {
+    Online,
+    Stale,
+    Offline,
+}
+
+impl fmt::Display for Presence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Presence::Online => "online",
+            Presence::Stale => "stale",
+            Presence::Offline => "offline",
+        })
+    }
+}
+
+/// A device moving from one presence to another.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PresenceChange {
+    /// The latest registry entry for the device.
+    pub device: Device,
+    /// `None` the first time a device is seen.
+    pub previous: Option<Presence>,
+    pub presence: Presence,
+}
+
+/// Tracks the presence of every known device and reports transitions.
+#[derive(Debug)]
+pub(crate) struct PresenceTracker {
+    config: PresenceConfig,
+    devices: HashMap<String, (Device, Presence)>,
+}
+
+impl PresenceTracker {
+    pub(crate) fn new(config: PresenceConfig) -> Self {
+        PresenceTracker {
+            config,
+            devices: HashMap::new(),
+        }
+    }
+
+    /// Record a new registry entry.
+    pub(crate) fn update(&mut self, device: Device, now_ms: u64) -> Option<PresenceChange> {
+        let presence = self.config.classify(device.last_seen_ms, now_ms);
+        let previous = self
+            .devices
+            .insert(device.id.clone(), (device.clone(), presence))
+            .map(|(_, p)| p);
+
+        (previous != Some(presence)).then_some(PresenceChange {
+            device,
+            previous,
+            presence,
+        })
+    }
+
+    /// Forget a device whose registry entry was deleted.
+    pub(crate) fn remove(&mut self, id: &str) -> Option<PresenceChange> {
+        let (device, previous) = self.devices.remove(id)?;
+
+        (previous != Presence::Offline).then_some(PresenceChange {
+            device,
+            previous: Some(previous),
+            presence: Presence::Offline,
+        })
+    }
+
+    /// Re-classify every device against the current time.
+    pub(crate) fn tick(&mut self, now_ms: u64) -> Vec<PresenceChange> {
+        let mut changes = Vec::new();
+        for (device, presence) in self.devices.values_mut() {
+            let next = self.config.classify(device.last_seen_ms, now_ms);
+            if next != *presence {
+                changes.push(PresenceChange {
+                    device: device.clone(),
+                    previous: Some(*presence),
+                    presence: next,
+                });
+                *presence = next;
+            }
+        }
+
+        changes
+    }
+}
+
+enum Input {
+    Entry(Result<Entry, async_nats::jetstream::kv::WatcherError>),
+    Tick,
+}
+
+impl Avena {
+    /// Stream presence transitions for every device in the registry.
+    ///
+    /// Each device currently in the registry is reported once up front, then
+    /// again whenever it crosses a threshold in `config` or its entry is
+    /// deleted.
+    pub async fn watch_devices(
+        &self,
+        config: PresenceConfig,
+    ) -> Result<impl Stream<Item = Result<PresenceChange, Error>>, Error> {
+        let kv = self.js.get_key_value(KV_DEVICES).await?;
+        let entries = kv.watch_with_history(">").await?.map(Input::Entry);
+
+        let ticks = stream::unfold(
+            tokio::time::interval(CHECK_INTERVAL),
+            |mut interval| async {
+                interval.tick().await;
+                Some((Input::Tick, interval))
+            },
+        );
+
+        let mut tracker = PresenceTracker::new(config);
+        let changes = stream::select(entries, ticks).flat_map(move |input| {
+            let now = now_millis();
+            let changes: Vec<Result<PresenceChange, Error>> = match input {
+                Input::Tick => tracker.tick(now).into_iter().map(Ok).collect(),
+                Input::Entry(Err(err)) => vec![Err(err.into())],
+                Input::Entry(Ok(entry)) => match entry.operation {
+                    Operation::Put => match Device::try_from(entry.value.as_ref()) {
+                        Ok(device) => tracker.update(device, now).into_iter().map(Ok).collect(),
+                        Err(err) => vec![Err(err.into())],
+                    },
+                    Operation::Delete | Operation::Purge => {
+                        tracker.remove(&entry.key).into_iter().map(Ok).collect()
+                    }
+                },
+            };
+            stream::iter(changes)
+        });
+
+        Ok(changes)
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(id: &str, last_seen_ms: Option<u64>) -> Device {
+        Device {
+            id: id.to_string(),
+            version: "0.1.0".to_string(),
+            last_seen_ms,
+            uptime_ms: None,
+            nats_name: None,
+            pubkey: None,
+        }
+    }
+
+    #[test]
+    fn test_classify_thresholds() {
+        let config = PresenceConfig::default();
+        let now = 1_000_000;
+
+        assert_eq!(config.classify(Some(now - 1_000), now), Presence::Online);
+        assert_eq!(config.classify(Some(now - 30_000), now), Presence::Stale);
+        assert_eq!(config.classify(Some(now - 90_000), now), Presence::Offline);
+        assert_eq!(config.classify(None, now), Presence::Offline);
+        // A clock slightly behind the writer's still counts as online
+        assert_eq!(config.classify(Some(now + 500), now), Presence::Online);
+    }
+
+    #[test]
+    fn test_expiry_ignores_never_seen() {
+        let config = PresenceConfig {
+            expire_after: Duration::from_secs(60),
+            ..Default::default()
+        };
+
+        assert!(config.is_expired(Some(0), 60_000));
+        assert!(!config.is_expired(Some(1), 60_000));
+        assert!(!config.is_expired(None, 60_000));
+    }
+
+    #[test]
+    fn test_tracker_reports_transitions_once() {
+        let mut tracker = PresenceTracker::new(PresenceConfig::default());
+
+        let first = tracker.update(device("dev1", Some(0)), 0).unwrap();
+        assert_eq!(first.previous, None);
+        assert_eq!(first.presence, Presence::Online);
+
+        // A fresh announce while online is not a transition
+        assert!(tracker
+            .update(device("dev1", Some(10_000)), 10_000)
+            .is_none());
+
+        let stale = tracker.tick(45_000);
+        assert_eq!(stale.len(), 1);
+        assert_eq!(stale[0].previous, Some(Presence::Online));
+        assert_eq!(stale[0].presence, Presence::Stale);
+        assert!(tracker.tick(46_000).is_empty());
+
+        let offline = tracker.tick(100_000);
+        assert_eq!(offline[0].presence, Presence::Offline);
+
+        let back = tracker
+            .update(device("dev1", Some(100_000)), 100_000)
+            .unwrap();
+        assert_eq!(back.previous, Some(Presence::Offline));
+        assert_eq!(back.presence, Presence::Online);
+    }
+
+    #[test]
+    fn test_tracker_removal() {
+        let mut tracker = PresenceTracker::new(PresenceConfig::default());
+        tracker.update(device("dev1", Some(0)), 0);
+
+        let removed = tracker.remove("dev1").unwrap();
+        assert_eq!(removed.presence, Presence::Offline);
+        assert!(tracker.remove("dev1").is_none());
+    }
+}
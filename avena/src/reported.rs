@@ -0,0 +1,111 @@
+//! The `avena_reported` bucket: the workload states each device last
+//! observed, under the device id.
+//!
+//! Every device keeps its own key current with a [`StateReporter`]. The
+//! bucket replicates over leaf links like desired state does, so
+//! [`Avena::reported_state`] still answers for a device that is partitioned
+//! away, with the HLC timestamp of its last report.
+
+use std::sync::Arc;
+
+use async_nats::jetstream::{self, kv};
+use futures::TryStreamExt;
+
+use crate::hlc::HlcClock;
+use crate::messages::{ReportedState, WorkloadState};
+use crate::Error;
+
+use super::Avena;
+
+/// Name of the KV bucket holding every device's reported state.
+pub const REPORTED_BUCKET: &str = "avena_reported";
+
+async fn reported_bucket(js: &jetstream::Context) -> Result<kv::Store, Error> {
+    if let Ok(kv) = js.get_key_value(REPORTED_BUCKET).await {
+        return Ok(kv);
+    }
+    let kv = js
+        .create_key_value(kv::Config {
+            bucket: REPORTED_BUCKET.to_string(),
+            history: 5,
+            ..Default::default()
+        })
+        .await?;
+    Ok(kv)
+}
+
+/// The reported-state bucket, `None` if no device has reported yet.
+async fn existing_reported_bucket(js: &jetstream::Context) -> Result<Option<kv::Store>, Error> {
+    match js.get_key_value(REPORTED_BUCKET).await.map_err(Error::from) {
+        Ok(kv) => Ok(Some(kv)),
+        Err(err) if err.is_missing_bucket() => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Writes one device's observed workload states to the reported-state bucket.
+#[derive(Clone)]
+pub struct StateReporter {
+    kv: kv::Store,
+    hlc: Arc<HlcClock>,
+    device: String,
+}
+
+impl StateReporter {
+    /// Open the reporter for `device`, creating the bucket if needed.
+    pub async fn open(
+        js: jetstream::Context,
+        hlc: Arc<HlcClock>,
+        device: &str,
+    ) -> Result<Self, Error> {
+        Ok(StateReporter {
+            kv: reported_bucket(&js).await?,
+            hlc,
+            device: device.to_string(),
+        })
+    }
+
+    /// Replace the device's reported state with `workloads`.
+    pub async fn report(&self, workloads: Vec<WorkloadState>) -> Result<ReportedState, Error> {
+        let state = ReportedState {
+            device: self.device.clone(),
+            timestamp: self.hlc.tick(),
+            workloads,
+        };
+        self.kv
+            .put(&self.device, Vec::from(state.clone()).into())
+            .await?;
+        Ok(state)
+    }
+}
+
+impl Avena {
+    /// The last state `device` reported, `None` if it never reported.
+    pub async fn reported_state(&self, device: &str) -> Result<Option<ReportedState>, Error> {
+        let Some(kv) = existing_reported_bucket(&self.js).await? else {
+            return Ok(None);
+        };
+        match kv.get(device).await? {
+            Some(value) => Ok(Some(ReportedState::try_from(value.as_ref())?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The last reported state of every device, ordered by device id.
+    pub async fn reported_states(&self) -> Result<Vec<ReportedState>, Error> {
+        let Some(kv) = existing_reported_bucket(&self.js).await? else {
+            return Ok(vec![]);
+        };
+
+        let mut states = vec![];
+        let mut keys = kv.keys().await?;
+        while let Some(key) = keys.try_next().await? {
+            if let Some(value) = kv.get(&key).await? {
+                states.push(ReportedState::try_from(value.as_ref())?);
+            }
+        }
+        states.sort_by(|a, b| a.device.cmp(&b.device));
+
+        Ok(states)
+    }
+}
@@ -0,0 +1,289 @@
+//! Typed request/reply over NATS.
+//!
+//! Both sides merge the peer's `Avena-HLC` header into their clock and attach
+//! their own to what they send. Callers pass their timeout along in the
+//! `Avena-Timeout` header (milliseconds) so servers can give up on work nobody
+//! is waiting for. Failed requests are answered with an
+//! [`ErrorResponse`] marked by the `Avena-Error` header.
+//!
+//! Requests carry the [`Encoding`] they were written in, and servers reply in
+//! the same one. Messages without a content type are JSON, so older peers
+//! keep working.
+
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_nats::{Client, HeaderMap};
+use futures::StreamExt;
+use tracing::warn;
+
+use crate::hlc::{HlcClock, HybridTimestamp};
+use crate::messages::{Encoding, ErrorCode, ErrorResponse, Message};
+use crate::Error;
+
+const ERROR_HEADER: &str = "Avena-Error";
+const TIMEOUT_HEADER: &str = "Avena-Timeout";
+
+/// Used when the caller does not pick a timeout.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Client half of the RPC layer.
+#[derive(Clone)]
+pub struct RpcClient {
+    nc: Client,
+    hlc: Arc<HlcClock>,
+    timeout: Duration,
+    encoding: Encoding,
+}
+
+impl RpcClient {
+    pub fn new(nc: Client, hlc: Arc<HlcClock>) -> Self {
+        RpcClient {
+            nc,
+            hlc,
+            timeout: DEFAULT_TIMEOUT,
+            encoding: Encoding::default(),
+        }
+    }
+
+    /// Change the timeout used by [`RpcClient::request`].
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Change the encoding requests are sent in, and so the one replies come back in.
+    pub fn with_encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    pub async fn request<Req: Message, Resp: Message>(
+        &self,
+        subject: impl Into<String>,
+        req: &Req,
+    ) -> Result<Resp, Error> {
+        self.request_with_timeout(subject, req, self.timeout).await
+    }
+
+    pub async fn request_with_timeout<Req: Message, Resp: Message>(
+        &self,
+        subject: impl Into<String>,
+        req: &Req,
+        timeout: Duration,
+    ) -> Result<Resp, Error> {
+        let subject = subject.into();
+
+        let mut headers = HeaderMap::new();
+        self.hlc.attach_to_headers(&mut headers);
+        headers.insert(TIMEOUT_HEADER, timeout.as_millis().to_string().as_str());
+        self.encoding.insert(&mut headers);
+
+        let request = async_nats::Request::new()
+            .payload(req.encode_as(self.encoding).into())
+            .headers(headers)
+            .timeout(Some(timeout));
+
+        let msg = self
+            .nc
+            .send_request(subject.clone(), request)
+            .await
+            .map_err(|e| Error::from_request(&subject, e))?;
+
+        self.hlc.extract_and_merge(msg.headers.as_ref());
+
+        decode_reply(&subject, msg.headers.as_ref(), &msg.payload)
+    }
+
+    /// Publish one request and collect every reply that arrives within `timeout`.
+    ///
+    /// Meant for subjects many devices answer on. Replies that fail to decode
+    /// or carry an error envelope are dropped.
+    pub async fn scatter<Req: Message, Resp: Message>(
+        &self,
+        subject: impl Into<String>,
+        req: &Req,
+        timeout: Duration,
+    ) -> Result<Vec<Reply<Resp>>, Error> {
+        let subject = subject.into();
+        let inbox = self.nc.new_inbox();
+        let mut sub = self.nc.subscribe(inbox.clone()).await?;
+
+        let mut headers = HeaderMap::new();
+        self.hlc.attach_to_headers(&mut headers);
+        headers.insert(TIMEOUT_HEADER, timeout.as_millis().to_string().as_str());
+        self.encoding.insert(&mut headers);
+
+        let sent = Instant::now();
+        let deadline = tokio::time::Instant::from_std(sent + timeout);
+        self.nc
+            .publish_with_reply_and_headers(
+                subject.clone(),
+                inbox,
+                headers,
+                req.encode_as(self.encoding).into(),
+            )
+            .await?;
+
+        let mut replies = Vec::new();
+        while let Ok(Some(msg)) = tokio::time::timeout_at(deadline, sub.next()).await {
+            let rtt = sent.elapsed();
+            let hlc = HybridTimestamp::from_headers(msg.headers.as_ref());
+            self.hlc.extract_and_merge(msg.headers.as_ref());
+
+            if let Ok(response) = decode_reply(&subject, msg.headers.as_ref(), &msg.payload) {
+                replies.push(Reply { rtt, hlc, response });
+            }
+        }
+
+        let _ = sub.unsubscribe().await;
+
+        Ok(replies)
+    }
+}
+
+/// One reply collected by [`RpcClient::scatter`].
+#[derive(Debug, Clone)]
+pub struct Reply<T> {
+    /// Time from publishing the request to receiving this reply.
+    pub rtt: Duration,
+    /// The responder's HLC timestamp, if it sent one.
+    pub hlc: Option<HybridTimestamp>,
+    pub response: T,
+}
+
+/// Decode a reply that may be an error envelope instead of `Resp`.
+pub(crate) fn decode_reply<Resp: Message>(
+    subject: &str,
+    headers: Option<&HeaderMap>,
+    payload: &[u8],
+) -> Result<Resp, Error> {
+    let encoding = Encoding::from_headers(headers);
+
+    if headers.and_then(|h| h.get(ERROR_HEADER)).is_some() {
+        let err = ErrorResponse::decode_as(payload, encoding)?;
+        return Err(Error::Remote {
+            subject: subject.to_string(),
+            code: err.code,
+            message: err.message,
+        });
+    }
+
+    Ok(Resp::decode_as(payload, encoding)?)
+}
+
+/// Details about an incoming request, handed to handlers with the request.
+#[derive(Debug, Clone)]
+pub struct RequestContext {
+    pub subject: String,
+    /// The caller's HLC timestamp, if it sent one.
+    pub caller: Option<HybridTimestamp>,
+    /// How long the caller is willing to wait for a reply.
+    pub timeout: Option<Duration>,
+}
+
+/// Server half of the RPC layer.
+#[derive(Clone)]
+pub struct RpcServer {
+    nc: Client,
+    hlc: Arc<HlcClock>,
+}
+
+impl RpcServer {
+    pub fn new(nc: Client, hlc: Arc<HlcClock>) -> Self {
+        RpcServer { nc, hlc }
+    }
+
+    /// Answer requests on `subject` with `handler` until the subscription ends.
+    ///
+    /// Requests are handled one at a time, in the order they arrive. Handlers
+    /// that outlive the caller's timeout are cancelled and answered with
+    /// [`ErrorCode::DeadlineExceeded`].
+    pub async fn serve<Req, Resp, F, Fut>(
+        &self,
+        subject: impl Into<String>,
+        handler: F,
+    ) -> Result<(), Error>
+    where
+        Req: Message,
+        Resp: Message,
+        F: Fn(Req, RequestContext) -> Fut,
+        Fut: Future<Output = Result<Resp, ErrorResponse>>,
+    {
+        let subject = subject.into();
+        let mut sub = self.nc.subscribe(subject.clone()).await?;
+
+        while let Some(msg) = sub.next().await {
+            self.hlc.extract_and_merge(msg.headers.as_ref());
+
+            let Some(reply) = msg.reply else {
+                continue;
+            };
+
+            let ctx = RequestContext {
+                subject: msg.subject.to_string(),
+                caller: HybridTimestamp::from_headers(msg.headers.as_ref()),
+                timeout: msg
+                    .headers
+                    .as_ref()
+                    .and_then(|h| h.get(TIMEOUT_HEADER))
+                    .and_then(|v| v.as_str().parse().ok())
+                    .map(Duration::from_millis),
+            };
+
+            let encoding = Encoding::from_headers(msg.headers.as_ref());
+            let result = match Req::decode_as(&msg.payload, encoding) {
+                Ok(req) => {
+                    let timeout = ctx.timeout;
+                    with_deadline(timeout, handler(req, ctx)).await
+                }
+                Err(err) => Err(ErrorResponse::bad_request(format!(
+                    "invalid request on {subject}: {err}"
+                ))),
+            };
+
+            let mut headers = HeaderMap::new();
+            self.hlc.attach_to_headers(&mut headers);
+            encoding.insert(&mut headers);
+
+            let payload = match result {
+                Ok(resp) => resp.encode_as(encoding),
+                Err(err) => {
+                    headers.insert(ERROR_HEADER, err.code.as_str());
+                    err.encode_as(encoding)
+                }
+            };
+
+            // A caller that went away must not stop the server answering others
+            if let Err(err) = self
+                .nc
+                .publish_with_headers(reply, headers, payload.into())
+                .await
+            {
+                warn!("Unable to reply on {subject}: {err}");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Run a handler, giving up once the caller has stopped waiting.
+async fn with_deadline<Resp>(
+    timeout: Option<Duration>,
+    fut: impl Future<Output = Result<Resp, ErrorResponse>>,
+) -> Result<Resp, ErrorResponse> {
+    let Some(timeout) = timeout else {
+        return fut.await;
+    };
+
+    tokio::time::timeout(timeout, fut)
+        .await
+        .unwrap_or_else(|_| {
+            Err(ErrorResponse::new(
+                ErrorCode::DeadlineExceeded,
+                format!("no reply within {}ms", timeout.as_millis()),
+            ))
+        })
+}
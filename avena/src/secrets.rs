@@ -0,0 +1,321 @@
+//! The `avena_secrets` bucket: secret values for workloads, each encrypted
+//! to the one device allowed to read it, under `{device}.{name}`.
+//!
+//! A value is sealed to the X25519 key that corresponds to the device's
+//! ed25519 identity key: the sender agrees on a key with a one-time X25519
+//! key of its own, derives a ChaCha20-Poly1305 key from it with HKDF-SHA256,
+//! and binds the ciphertext to the device and secret name. Every device
+//! replicates the bucket, but only the recipient can open its entries.
+
+use async_nats::jetstream::{self, consumer, kv};
+use curve25519_dalek::edwards::CompressedEdwardsY;
+use curve25519_dalek::montgomery::MontgomeryPoint;
+use data_encoding::BASE64URL_NOPAD;
+use futures::{StreamExt, TryStreamExt};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305};
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::{digest, hkdf};
+
+use crate::hlc::HybridTimestamp;
+use crate::messages::SealedSecret;
+use crate::Error;
+
+use super::Avena;
+
+/// Name of the KV bucket holding sealed secrets.
+pub const SECRETS_BUCKET: &str = "avena_secrets";
+
+const KDF_INFO: &[u8] = b"avena secret v1";
+
+/// Key of the secret `name` of `device` in [`SECRETS_BUCKET`]. With `>`
+/// for `name`, the pattern matching every secret of `device`.
+pub fn secret_key(device: &str, name: &str) -> String {
+    format!("{device}.{name}")
+}
+
+/// Secret names become podman secret names and file names under
+/// `/run/secrets`.
+pub fn check_secret_name(name: &str) -> Result<(), Error> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::Secret(format!("invalid secret name {name:?}")))
+    }
+}
+
+/// Open the secrets bucket, creating it if needed.
+pub async fn secrets_bucket(js: &jetstream::Context) -> Result<kv::Store, Error> {
+    if let Ok(kv) = js.get_key_value(SECRETS_BUCKET).await {
+        return Ok(kv);
+    }
+    let kv = js
+        .create_key_value(kv::Config {
+            bucket: SECRETS_BUCKET.to_string(),
+            history: 1,
+            ..Default::default()
+        })
+        .await?;
+    Ok(kv)
+}
+
+/// The X25519 public key of the device whose nkey public key is `pubkey`.
+fn x25519_public(pubkey: &str) -> Result<MontgomeryPoint, Error> {
+    let (_, raw) = nkeys::from_public_key(pubkey).map_err(Error::Sign)?;
+    let point = CompressedEdwardsY(raw)
+        .decompress()
+        .ok_or_else(|| Error::Secret(format!("{pubkey} is not a valid device key")))?;
+    Ok(point.to_montgomery())
+}
+
+/// The X25519 secret scalar of the device with nkey `seed`, as derived for
+/// ed25519 keys (RFC 8032 section 5.1.5). It is clamped when used.
+fn x25519_secret(seed: &str) -> Result<[u8; 32], Error> {
+    let (_, raw) = nkeys::decode_seed(seed).map_err(Error::Sign)?;
+    let hash = digest::digest(&digest::SHA512, &raw);
+    let mut scalar = [0; 32];
+    scalar.copy_from_slice(&hash.as_ref()[..32]);
+    Ok(scalar)
+}
+
+fn content_key(
+    shared: &MontgomeryPoint,
+    ephemeral: &MontgomeryPoint,
+    recipient: &MontgomeryPoint,
+) -> Result<LessSafeKey, Error> {
+    // A low order point from a malicious peer gives a key anyone can compute
+    if shared.0 == [0; 32] {
+        return Err(Error::Secret("invalid key agreement".to_string()));
+    }
+    let salt = hkdf::Salt::new(
+        hkdf::HKDF_SHA256,
+        &[ephemeral.as_bytes().as_slice(), recipient.as_bytes()].concat(),
+    );
+    let prk = salt.extract(shared.as_bytes());
+    let okm = prk
+        .expand(&[KDF_INFO], &CHACHA20_POLY1305)
+        .map_err(|_| Error::Secret("key derivation failed".to_string()))?;
+    Ok(LessSafeKey::new(UnboundKey::from(okm)))
+}
+
+/// Every key is used for one message only, so a fixed nonce is safe.
+fn nonce() -> Nonce {
+    Nonce::assume_unique_for_key([0; 12])
+}
+
+/// Encrypt `value` so only the device with identity key `recipient` can
+/// read it as its secret `name`.
+pub fn seal(
+    device: &str,
+    name: &str,
+    recipient: &str,
+    value: &[u8],
+    timestamp: Option<HybridTimestamp>,
+) -> Result<SealedSecret, Error> {
+    check_secret_name(name)?;
+    let recipient_point = x25519_public(recipient)?;
+
+    let mut ephemeral_secret = [0; 32];
+    SystemRandom::new()
+        .fill(&mut ephemeral_secret)
+        .map_err(|_| Error::Random)?;
+    let ephemeral = MontgomeryPoint::mul_base_clamped(ephemeral_secret);
+    let shared = recipient_point.mul_clamped(ephemeral_secret);
+
+    let key = content_key(&shared, &ephemeral, &recipient_point)?;
+    let mut ciphertext = value.to_vec();
+    let aad = secret_key(device, name);
+    key.seal_in_place_append_tag(nonce(), Aad::from(aad.as_bytes()), &mut ciphertext)
+        .map_err(|_| Error::Secret("encryption failed".to_string()))?;
+
+    Ok(SealedSecret {
+        device: device.to_string(),
+        name: name.to_string(),
+        recipient: recipient.to_string(),
+        ephemeral: BASE64URL_NOPAD.encode(ephemeral.as_bytes()),
+        ciphertext: BASE64URL_NOPAD.encode(&ciphertext),
+        timestamp,
+    })
+}
+
+/// Decrypt `sealed` with the identity `seed` of the device it was sealed to.
+pub fn open(sealed: &SealedSecret, seed: &str) -> Result<Vec<u8>, Error> {
+    let pubkey = nkeys::KeyPair::from_seed(seed)
+        .map_err(Error::Sign)?
+        .public_key();
+    if sealed.recipient != pubkey {
+        return Err(Error::Secret(format!(
+            "secret {} is sealed to another key",
+            sealed.name
+        )));
+    }
+    let recipient = x25519_public(&pubkey)?;
+
+    let ephemeral: [u8; 32] = BASE64URL_NOPAD
+        .decode(sealed.ephemeral.as_bytes())
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| Error::Secret(format!("secret {} is malformed", sealed.name)))?;
+    let ephemeral = MontgomeryPoint(ephemeral);
+    let shared = ephemeral.mul_clamped(x25519_secret(seed)?);
+
+    let key = content_key(&shared, &ephemeral, &recipient)?;
+    let mut buf = BASE64URL_NOPAD
+        .decode(sealed.ciphertext.as_bytes())
+        .map_err(|_| Error::Secret(format!("secret {} is malformed", sealed.name)))?;
+    let aad = secret_key(&sealed.device, &sealed.name);
+    let value = key
+        .open_in_place(nonce(), Aad::from(aad.as_bytes()), &mut buf)
+        .map_err(|_| Error::Secret(format!("secret {} cannot be decrypted", sealed.name)))?;
+    Ok(value.to_vec())
+}
+
+/// Watch the secrets of `device` for changes.
+pub async fn watch_device_secrets(kv: &kv::Store, device: &str) -> Result<kv::Watch, Error> {
+    Ok(kv.watch(secret_key(device, ">")).await?)
+}
+
+/// The sealed secrets stored for `device`, ordered by name. Only that
+/// device's keys are read, not the whole fleet's.
+pub async fn device_secrets(kv: &kv::Store, device: &str) -> Result<Vec<SealedSecret>, Error> {
+    let consumer = kv
+        .stream
+        .create_consumer(consumer::pull::OrderedConfig {
+            filter_subject: format!("{}{}", kv.prefix, secret_key(device, ">")),
+            deliver_policy: consumer::DeliverPolicy::LastPerSubject,
+            ..Default::default()
+        })
+        .await?;
+
+    // An ordered consumer never ends on its own; stop once every key stored
+    // when it was created has been read.
+    let pending = consumer.cached_info().num_pending as usize;
+    if pending == 0 {
+        return Ok(vec![]);
+    }
+
+    let mut secrets = vec![];
+    let mut messages = consumer.messages().await?.take(pending);
+    while let Some(msg) = messages.try_next().await? {
+        // Deleted and purged secrets leave a marker without a value
+        let removed = msg
+            .headers
+            .as_ref()
+            .is_some_and(|headers| headers.get("KV-Operation").is_some());
+        if !removed {
+            secrets.push(SealedSecret::try_from(msg.payload.as_ref())?);
+        }
+    }
+    secrets.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(secrets)
+}
+
+impl Avena {
+    /// Store `value` as the secret `name` of `device`, sealed to the device's
+    /// registered identity key.
+    pub async fn put_secret(
+        &self,
+        device: &str,
+        name: &str,
+        value: &[u8],
+    ) -> Result<SealedSecret, Error> {
+        let recipient = self
+            .get_devices()
+            .await?
+            .remove(device)
+            .and_then(|device| device.pubkey)
+            .ok_or_else(|| Error::Secret(format!("device {device} has no registered key")))?;
+        let sealed = seal(device, name, &recipient, value, Some(self.hlc.tick()))?;
+
+        let kv = secrets_bucket(&self.js).await?;
+        kv.put(secret_key(device, name), Vec::from(sealed.clone()).into())
+            .await?;
+        Ok(sealed)
+    }
+
+    /// The secrets stored for `device`, ordered by name. Values stay sealed.
+    pub async fn secrets(&self, device: &str) -> Result<Vec<SealedSecret>, Error> {
+        let kv = secrets_bucket(&self.js).await?;
+        device_secrets(&kv, device).await
+    }
+
+    /// Delete the secret `name` of `device`, returning whether it existed.
+    pub async fn delete_secret(&self, device: &str, name: &str) -> Result<bool, Error> {
+        let kv = secrets_bucket(&self.js).await?;
+        let key = secret_key(device, name);
+        if kv.get(&key).await?.is_none() {
+            return Ok(false);
+        }
+        // Purge rather than delete, so no old ciphertext stays behind
+        kv.purge(&key).await?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device_key() -> nkeys::KeyPair {
+        nkeys::KeyPair::new_user()
+    }
+
+    #[test]
+    fn test_only_the_recipient_opens_a_secret() {
+        let device = device_key();
+        let sealed = seal(
+            "dev1",
+            "db-password",
+            &device.public_key(),
+            b"hunter2",
+            None,
+        )
+        .unwrap();
+        assert_ne!(sealed.ciphertext, BASE64URL_NOPAD.encode(b"hunter2"));
+
+        let seed = device.seed().unwrap();
+        assert_eq!(open(&sealed, &seed).unwrap(), b"hunter2");
+
+        let other = device_key();
+        let mut stolen = sealed.clone();
+        stolen.recipient = other.public_key();
+        assert!(open(&stolen, &other.seed().unwrap()).is_err());
+        assert!(open(&sealed, &other.seed().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_each_seal_is_different() {
+        let device = device_key();
+        let a = seal("dev1", "token", &device.public_key(), b"x", None).unwrap();
+        let b = seal("dev1", "token", &device.public_key(), b"x", None).unwrap();
+        assert_ne!(a.ephemeral, b.ephemeral);
+        assert_ne!(a.ciphertext, b.ciphertext);
+    }
+
+    #[test]
+    fn test_ciphertext_is_bound_to_its_name() {
+        let device = device_key();
+        let seed = device.seed().unwrap();
+        let sealed = seal("dev1", "token", &device.public_key(), b"x", None).unwrap();
+
+        let mut renamed = sealed.clone();
+        renamed.name = "other".to_string();
+        assert!(open(&renamed, &seed).is_err());
+
+        let mut moved = sealed;
+        moved.device = "dev2".to_string();
+        assert!(open(&moved, &seed).is_err());
+    }
+
+    #[test]
+    fn test_rejects_bad_names() {
+        let device = device_key();
+        for name in ["", ".hidden", "a/b", "a,type=env"] {
+            assert!(seal("dev1", name, &device.public_key(), b"x", None).is_err());
+        }
+    }
+}
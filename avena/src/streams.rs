@@ -0,0 +1,390 @@
+//! Output streamed from a device to a caller, such as workload logs or a
+//! command run in a workload's container.
+//!
+//! The caller subscribes to an inbox and names it in the request that opens
+//! the stream. The device replies with a control subject and sends
+//! [`OutputChunk`]s to the inbox, never more than `window` chunks ahead of
+//! the caller's acknowledgements, so a slow caller slows the device down
+//! instead of losing output or overrunning NATS' max payload. Both sides
+//! send keepalives and give up on a peer that goes quiet, so a stream whose
+//! caller vanished does not run forever. Streams of interactive commands
+//! also carry the caller's input on the control subject.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_nats::{Client, Subscriber};
+use data_encoding::BASE64URL_NOPAD;
+use futures::StreamExt;
+use ring::rand::{SecureRandom, SystemRandom};
+use tokio::time::{Instant, Interval};
+
+use crate::messages::{
+    subject_workload_exec, subject_workload_logs, ExecRequest, LogsRequest, Message, OutputChunk,
+    OutputKind, StreamControl, StreamOpened,
+};
+use crate::Error;
+
+use super::Avena;
+
+/// Chunks a device may send ahead of the caller unless asked otherwise.
+pub const DEFAULT_WINDOW: u32 = 16;
+
+/// Most data a device puts in one chunk, well under NATS' default 1 MiB
+/// max payload.
+pub const MAX_CHUNK_BYTES: usize = 64 * 1024;
+
+/// How often each side shows the other it is still there.
+pub const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long either side waits to hear from the other before giving up.
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Which logs [`Avena::workload_logs`] streams.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LogOptions {
+    /// Keep streaming new lines until cancelled.
+    pub follow: bool,
+    /// Only lines logged at or after this wall time (ms since the Unix epoch).
+    pub since_ms: Option<u64>,
+    /// Only the last this many lines.
+    pub tail: Option<u32>,
+}
+
+/// What [`Avena::workload_exec`] runs.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExecOptions {
+    pub command: Vec<String>,
+    /// Allocate a TTY and take input through [`OutputStream::send_input`].
+    pub tty: bool,
+    /// Have the device kill the command after this long.
+    pub timeout: Option<Duration>,
+}
+
+impl Avena {
+    /// Stream the logs of `workload` on `device`.
+    pub async fn workload_logs(
+        &self,
+        device: &str,
+        workload: &str,
+        options: LogOptions,
+    ) -> Result<OutputStream, Error> {
+        let inbox = self.nc.new_inbox();
+        let req = LogsRequest {
+            workload: workload.to_string(),
+            inbox: inbox.clone(),
+            follow: options.follow,
+            since_ms: options.since_ms,
+            tail: options.tail,
+            window: DEFAULT_WINDOW,
+        };
+        self.open_stream(subject_workload_logs(device), &req, inbox)
+            .await
+    }
+
+    /// Run a command in the container of `workload` on `device`.
+    ///
+    /// The request is signed with `key`, which the device must allow to exec.
+    pub async fn workload_exec(
+        &self,
+        device: &str,
+        workload: &str,
+        options: ExecOptions,
+        key: &nkeys::KeyPair,
+    ) -> Result<OutputStream, Error> {
+        let inbox = self.nc.new_inbox();
+        let mut nonce = [0; 16];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| Error::Random)?;
+        let mut req = ExecRequest {
+            workload: workload.to_string(),
+            inbox: inbox.clone(),
+            command: options.command,
+            tty: options.tty,
+            timeout_secs: options.timeout.map(|t| t.as_secs().max(1)),
+            window: DEFAULT_WINDOW,
+            caller: key.public_key(),
+            issued_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            nonce: BASE64URL_NOPAD.encode(&nonce),
+            signature: String::new(),
+        };
+        let signature = key.sign(&req.signed_payload(device)).map_err(Error::Sign)?;
+        req.signature = BASE64URL_NOPAD.encode(&signature);
+
+        self.open_stream(subject_workload_exec(device), &req, inbox)
+            .await
+    }
+
+    /// Send `req`, which names `inbox`, and receive the stream it opens.
+    pub async fn open_stream<Req: Message>(
+        &self,
+        subject: impl Into<String>,
+        req: &Req,
+        inbox: String,
+    ) -> Result<OutputStream, Error> {
+        // Subscribe first so no chunk sent right after the reply is missed
+        let chunks = self.nc.subscribe(inbox).await?;
+        let opened: StreamOpened = self.request(subject, req).await?;
+        Ok(OutputStream::new(self.nc.clone(), chunks, opened.control))
+    }
+}
+
+/// Receiving end of a device's output stream.
+///
+/// Dropping it before the stream is done cancels the stream.
+pub struct OutputStream {
+    nc: Client,
+    chunks: Subscriber,
+    control: String,
+    acked: u64,
+    keepalive: Interval,
+    last_heard: Instant,
+    finished: bool,
+}
+
+impl OutputStream {
+    fn new(nc: Client, chunks: Subscriber, control: String) -> Self {
+        OutputStream {
+            nc,
+            chunks,
+            control,
+            acked: 0,
+            keepalive: tokio::time::interval(KEEPALIVE_INTERVAL),
+            last_heard: Instant::now(),
+            finished: false,
+        }
+    }
+
+    /// The next chunk of output, `None` after the last one.
+    pub async fn next(&mut self) -> Option<Result<OutputChunk, Error>> {
+        if self.finished {
+            return None;
+        }
+
+        loop {
+            tokio::select! {
+                msg = self.chunks.next() => {
+                    let Some(msg) = msg else {
+                        self.finished = true;
+                        return None;
+                    };
+                    self.last_heard = Instant::now();
+                    let chunk = match OutputChunk::try_from(msg.payload.as_ref()) {
+                        Ok(chunk) => chunk,
+                        Err(err) => return Some(Err(err.into())),
+                    };
+                    if chunk.is_keepalive() {
+                        continue;
+                    }
+
+                    self.acked = self.acked.max(chunk.seq);
+                    if chunk.done {
+                        self.finished = true;
+                    } else if let Err(err) = self.send_control(false).await {
+                        return Some(Err(err));
+                    }
+                    return Some(Ok(chunk));
+                }
+                _ = self.keepalive.tick() => {
+                    if self.last_heard.elapsed() > IDLE_TIMEOUT {
+                        self.finished = true;
+                        return Some(Err(Error::Timeout {
+                            subject: self.control.clone(),
+                        }));
+                    }
+                    if let Err(err) = self.send_control(false).await {
+                        return Some(Err(err));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Send `data` to the remote command's input.
+    pub async fn send_input(&self, data: String) -> Result<(), Error> {
+        self.send(StreamControl {
+            input: Some(data),
+            ..self.ack()
+        })
+        .await
+    }
+
+    /// Close the remote command's input.
+    pub async fn close_input(&self) -> Result<(), Error> {
+        self.send(StreamControl {
+            close_input: true,
+            ..self.ack()
+        })
+        .await
+    }
+
+    /// Tell the device to stop streaming.
+    pub async fn cancel(mut self) -> Result<(), Error> {
+        self.finished = true;
+        self.send_control(true).await?;
+        let _ = self.nc.flush().await;
+        Ok(())
+    }
+
+    fn ack(&self) -> StreamControl {
+        StreamControl {
+            acked: self.acked,
+            ..Default::default()
+        }
+    }
+
+    async fn send_control(&self, cancel: bool) -> Result<(), Error> {
+        self.send(StreamControl {
+            cancel,
+            ..self.ack()
+        })
+        .await
+    }
+
+    async fn send(&self, control: StreamControl) -> Result<(), Error> {
+        self.nc
+            .publish(self.control.clone(), control.encode().into())
+            .await?;
+        Ok(())
+    }
+}
+
+impl Drop for OutputStream {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        // Best effort; the device also gives up once keepalives stop
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let nc = self.nc.clone();
+        let subject = self.control.clone();
+        let payload = StreamControl {
+            cancel: true,
+            ..self.ack()
+        }
+        .encode();
+        runtime.spawn(async move {
+            let _ = nc.publish(subject, payload.into()).await;
+        });
+    }
+}
+
+/// Sending end of an output stream, used by devices.
+pub struct StreamSink {
+    nc: Client,
+    inbox: String,
+    control: Subscriber,
+    control_subject: String,
+    window: u64,
+    seq: u64,
+    acked: u64,
+    last_heard: Instant,
+}
+
+impl StreamSink {
+    /// Start a stream to `inbox`, taking control messages on a new subject.
+    pub async fn open(nc: Client, inbox: String, window: u32) -> Result<Self, Error> {
+        let control_subject = nc.new_inbox();
+        let control = nc.subscribe(control_subject.clone()).await?;
+        Ok(StreamSink {
+            nc,
+            inbox,
+            control,
+            control_subject,
+            window: u64::from(window.max(1)),
+            seq: 0,
+            acked: 0,
+            last_heard: Instant::now(),
+        })
+    }
+
+    /// Where the caller sends [`StreamControl`] messages.
+    pub fn control_subject(&self) -> &str {
+        &self.control_subject
+    }
+
+    /// Whether the caller has room for another chunk.
+    pub fn has_credit(&self) -> bool {
+        self.seq - self.acked < self.window
+    }
+
+    /// Whether the caller has not been heard from for too long.
+    pub fn caller_gone(&self) -> bool {
+        self.last_heard.elapsed() > IDLE_TIMEOUT
+    }
+
+    /// Wait for the caller's next control message, after applying its
+    /// acknowledgement. Returns `None` once the caller cancelled the stream.
+    ///
+    /// Cancel safe, so it can wait alongside the output being streamed.
+    pub async fn control(&mut self) -> Option<StreamControl> {
+        loop {
+            let msg = self.control.next().await?;
+            let Ok(control) = StreamControl::try_from(msg.payload.as_ref()) else {
+                continue;
+            };
+            self.last_heard = Instant::now();
+            self.acked = self.acked.max(control.acked.min(self.seq));
+            return (!control.cancel).then_some(control);
+        }
+    }
+
+    /// Send one chunk of output. Callers check [`Self::has_credit`] first.
+    pub async fn send(&mut self, kind: OutputKind, data: String) -> Result<(), Error> {
+        self.seq += 1;
+        self.publish(OutputChunk {
+            seq: self.seq,
+            kind,
+            data,
+            done: false,
+            exit_code: None,
+            error: None,
+        })
+        .await
+    }
+
+    /// Show the caller the stream is still open while there is no output.
+    pub async fn keepalive(&self) -> Result<(), Error> {
+        self.publish(OutputChunk {
+            seq: self.seq,
+            kind: OutputKind::Stdout,
+            data: String::new(),
+            done: false,
+            exit_code: None,
+            error: None,
+        })
+        .await
+    }
+
+    /// End the stream.
+    pub async fn finish(
+        mut self,
+        exit_code: Option<i32>,
+        error: Option<String>,
+    ) -> Result<(), Error> {
+        self.seq += 1;
+        self.publish(OutputChunk {
+            seq: self.seq,
+            kind: OutputKind::Stdout,
+            data: String::new(),
+            done: true,
+            exit_code,
+            error,
+        })
+        .await?;
+        let _ = self.nc.flush().await;
+        Ok(())
+    }
+
+    async fn publish(&self, chunk: OutputChunk) -> Result<(), Error> {
+        self.nc
+            .publish(self.inbox.clone(), chunk.encode().into())
+            .await?;
+        Ok(())
+    }
+}
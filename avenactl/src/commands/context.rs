@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+
 use clap::Parser;
 use color_eyre::{eyre::eyre, Result};
 
@@ -29,14 +31,53 @@ enum ContextCommands {
     },
 
     /// Add
-    Add {
-        #[clap(required = true)]
-        /// Name of context to add to local configuration
-        name: String,
+    Add(Box<AddContext>),
+}
 
-        /// NATS connection string
-        connection: String,
-    },
+#[derive(Debug, clap::Args)]
+struct AddContext {
+    #[clap(required = true)]
+    /// Name of context to add to local configuration
+    name: String,
+
+    /// NATS connection string
+    connection: String,
+
+    /// NATS credentials file, such as the avena-admin.creds made by `avena-keygen init`
+    #[clap(long)]
+    creds: Option<PathBuf>,
+
+    /// File holding an nkey seed
+    #[clap(long)]
+    nkey: Option<PathBuf>,
+
+    /// User name, used with --password
+    #[clap(long)]
+    user: Option<String>,
+
+    /// Password, used with --user
+    #[clap(long)]
+    password: Option<String>,
+
+    /// Authentication token
+    #[clap(long)]
+    token: Option<String>,
+
+    /// CA certificate used to verify the server
+    #[clap(long)]
+    tls_ca: Option<PathBuf>,
+
+    /// Client certificate, used with --tls-key
+    #[clap(long)]
+    tls_cert: Option<PathBuf>,
+
+    /// Client certificate key, used with --tls-cert
+    #[clap(long)]
+    tls_key: Option<PathBuf>,
+
+    /// JetStream domain of the devices' servers
+    #[clap(long)]
+    js_domain: Option<String>,
 }
 
 pub fn exec(cmd: ContextCommand) -> Result<()> {
@@ -53,6 +94,9 @@ pub fn exec(cmd: ContextCommand) -> Result<()> {
                 .set_header(vec![
                     Cell::new("Name").add_attribute(Attribute::Bold),
                     Cell::new("Connection String").add_attribute(Attribute::Bold),
+                    Cell::new("Auth").add_attribute(Attribute::Bold),
+                    Cell::new("TLS").add_attribute(Attribute::Bold),
+                    Cell::new("JetStream Domain").add_attribute(Attribute::Bold),
                 ]);
 
             for (name, context) in config.context.into_iter() {
@@ -64,7 +108,15 @@ pub fn exec(cmd: ContextCommand) -> Result<()> {
                     Cell::new(name)
                 };
 
-                table.add_row(vec![cell_name, Cell::new(context.connection)]);
+                let tls = context.tls_ca.is_some() || context.tls_cert.is_some();
+
+                table.add_row(vec![
+                    cell_name,
+                    Cell::new(&context.connection),
+                    Cell::new(context.auth()),
+                    Cell::new(if tls { "yes" } else { "" }),
+                    Cell::new(context.js_domain.unwrap_or_default()),
+                ]);
             }
 
             println!("{table}");
@@ -81,12 +133,40 @@ pub fn exec(cmd: ContextCommand) -> Result<()> {
             m.save()?;
         }
 
-        ContextCommands::Add { name, connection } => {
+        ContextCommands::Add(add) => {
+            let AddContext {
+                name,
+                connection,
+                creds,
+                nkey,
+                user,
+                password,
+                token,
+                tls_ca,
+                tls_cert,
+                tls_key,
+                js_domain,
+            } = *add;
+
+            let new_context = Context {
+                creds,
+                nkey,
+                user,
+                password,
+                token,
+                tls_ca,
+                tls_cert,
+                tls_key,
+                js_domain,
+                ..Context::new(&name, &connection)
+            };
+            new_context.validate()?;
+
             let mut m = Manifest::open(CONFIG_PATH.to_path_buf())?;
 
             let context = m.get_section_mut("context");
 
-            context.insert(&name, Context::new(&name, &connection).try_into()?);
+            context.insert(&name, new_context.try_into()?);
 
             // If the next context is the only context, then make it active
             if context.len() == 1 {
@@ -1,8 +1,17 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
 use clap::{Parser, Subcommand};
 use color_eyre::Result;
+use futures::StreamExt;
 
+use avena::fleet::FleetReport;
+use avena::messages::{ImagePull, PullState, WorkloadHealth, WorkloadStatus, WorkloadSync};
+use avena::presence::{Presence, PresenceConfig};
 use avena::Avena;
-use comfy_table::{Attribute, Cell, Table};
+use comfy_table::{Attribute, Cell, Color, Table};
+
+use super::workload::{self, WorkloadCommands};
+use crate::config::Context;
 
 #[derive(Debug, Parser)]
 pub struct DeviceCommand {
@@ -21,14 +30,40 @@ pub enum DevicesCommands {
     /// Add a node to the active context
     Add,
 
-    /// Ping nodes in the active context
-    Ping,
+    /// Print devices as they come online, go stale, or drop offline
+    Watch,
+
+    /// Ping every device in the active context
+    Ping {
+        /// How long to wait for replies, in milliseconds
+        #[clap(long, default_value = "2000")]
+        timeout: u64,
+    },
+
+    /// Show the status of every device in the active context
+    Status {
+        /// How long to wait for replies, in milliseconds
+        #[clap(long, default_value = "2000")]
+        timeout: u64,
+    },
+
+    /// Show the last workload state devices reported, even while offline
+    Reported {
+        /// Show every workload of this device instead of a fleet summary
+        device: Option<String>,
+    },
+
+    /// Inspect and control the workloads running on a device
+    #[clap(subcommand)]
+    Workload(WorkloadCommands),
 }
 
-pub fn exec(a: Avena, nodes: DeviceCommand) -> Result<()> {
+pub async fn exec(a: Avena, context: &Context, nodes: DeviceCommand) -> Result<()> {
     match nodes.command {
         DevicesCommands::Ls => {
-            let devices = a.get_devices();
+            let devices = a.get_devices().await?;
+            let presence = PresenceConfig::default();
+            let now = now_millis();
 
             let mut table = Table::new();
             table
@@ -37,24 +72,298 @@ pub fn exec(a: Avena, nodes: DeviceCommand) -> Result<()> {
                 .set_content_arrangement(comfy_table::ContentArrangement::Dynamic)
                 .set_header(vec![
                     Cell::new("Name").add_attribute(Attribute::Bold),
+                    Cell::new("State").add_attribute(Attribute::Bold),
+                    Cell::new("Last seen").add_attribute(Attribute::Bold),
+                    Cell::new("Uptime").add_attribute(Attribute::Bold),
                     Cell::new("Version").add_attribute(Attribute::Bold),
+                    Cell::new("Pubkey").add_attribute(Attribute::Bold),
                 ]);
 
-            for (name, device) in devices.iter() {
-                table.add_row(vec![name, &device.version]);
+            let mut devices: Vec<_> = devices.into_iter().collect();
+            devices.sort_by(|a, b| a.0.cmp(&b.0));
+
+            for (name, device) in devices {
+                let state = presence.classify(device.last_seen_ms, now);
+                table.add_row(vec![
+                    Cell::new(name),
+                    presence_cell(state),
+                    Cell::new(
+                        device
+                            .last_seen_ms
+                            .map(|seen| {
+                                format!("{} ago", format_duration(now.saturating_sub(seen)))
+                            })
+                            .unwrap_or_else(|| "never".to_string()),
+                    ),
+                    Cell::new(device.uptime_ms.map(format_duration).unwrap_or_default()),
+                    Cell::new(device.version),
+                    Cell::new(device.pubkey.unwrap_or_default()),
+                ]);
             }
 
             println!("{table}");
         }
         DevicesCommands::Rm => todo!(),
         DevicesCommands::Add => todo!(),
-        DevicesCommands::Ping => {
-            println!("Publish Ping command");
-            let r = a.ping("test123");
+        DevicesCommands::Watch => {
+            let mut changes = Box::pin(a.watch_devices(PresenceConfig::default()).await?);
+            while let Some(change) = changes.next().await {
+                let change = change?;
+                match change.previous {
+                    Some(previous) => {
+                        println!("{}: {} -> {}", change.device.id, previous, change.presence)
+                    }
+                    None => println!("{}: {}", change.device.id, change.presence),
+                }
+            }
+        }
+        DevicesCommands::Ping { timeout } => {
+            let report = a.fleet_ping(Duration::from_millis(timeout)).await?;
+
+            let table = fleet_table(&report, &["Version", "Uptime"], |r| {
+                vec![r.avena_version.clone(), format_duration(r.uptime_ms)]
+            });
+
+            println!("{table}");
+        }
+        DevicesCommands::Status { timeout } => {
+            let report = a.fleet_status(Duration::from_millis(timeout)).await?;
+
+            let columns = ["Version", "Uptime", "Workloads", "Unhealthy", "Out of sync"];
+            let table = fleet_table(&report, &columns, |r| {
+                let unhealthy = r
+                    .workloads
+                    .iter()
+                    .filter(|w| w.health == Some(WorkloadHealth::Unhealthy))
+                    .map(|w| w.name.as_str())
+                    .collect::<Vec<_>>();
+                let out_of_sync = r
+                    .workloads
+                    .iter()
+                    .filter(|w| matches!(w.sync, WorkloadSync::OutOfSync | WorkloadSync::Orphaned))
+                    .map(|w| format!("{} ({})", w.name, w.sync))
+                    .collect::<Vec<_>>();
+                vec![
+                    r.avena_version.clone(),
+                    format_duration(r.uptime_ms),
+                    r.workloads.len().to_string(),
+                    unhealthy.join(", "),
+                    out_of_sync.join(", "),
+                ]
+            });
+
+            println!("{table}");
+        }
+        DevicesCommands::Reported { device: None } => {
+            let now = now_millis();
+            let mut table = new_table(&[
+                "Device",
+                "Reported",
+                "Workloads",
+                "Running",
+                "Out of sync",
+                "HLC",
+            ]);
+            for state in a.reported_states().await? {
+                let running = state
+                    .workloads
+                    .iter()
+                    .filter(|w| w.state == WorkloadStatus::Running)
+                    .count();
+                let out_of_sync = state
+                    .workloads
+                    .iter()
+                    .filter(|w| matches!(w.sync, WorkloadSync::OutOfSync | WorkloadSync::Orphaned))
+                    .map(|w| w.name.as_str())
+                    .collect::<Vec<_>>();
+                table.add_row(vec![
+                    Cell::new(&state.device),
+                    reported_age_cell(state.timestamp.wall_time_ms, now),
+                    Cell::new(state.workloads.len()),
+                    Cell::new(running),
+                    Cell::new(out_of_sync.join(", ")),
+                    Cell::new(&state.timestamp),
+                ]);
+            }
+
+            println!("{table}");
+        }
+        DevicesCommands::Reported {
+            device: Some(device),
+        } => {
+            let Some(state) = a.reported_state(&device).await? else {
+                println!("{device} has not reported any state");
+                return Ok(());
+            };
+            let now = now_millis();
+            println!(
+                "{device} reported {} ago ({})",
+                format_duration(now.saturating_sub(state.timestamp.wall_time_ms)),
+                state.timestamp
+            );
+
+            let mut table = new_table(&[
+                "Workload", "State", "Sync", "Health", "Image", "Digest", "Pull", "Restarts", "Up",
+            ]);
+            let mut rollbacks = vec![];
+            for workload in state.workloads {
+                table.add_row(vec![
+                    Cell::new(&workload.name),
+                    Cell::new(&workload.state),
+                    Cell::new(workload.sync),
+                    Cell::new(workload.health.map(|h| h.to_string()).unwrap_or_default()),
+                    Cell::new(&workload.image),
+                    Cell::new(
+                        workload
+                            .digest
+                            .as_deref()
+                            .map(short_digest)
+                            .unwrap_or_default(),
+                    ),
+                    Cell::new(workload.pull.map(pull_summary).unwrap_or_default()),
+                    Cell::new(workload.restart_count),
+                    Cell::new(
+                        workload
+                            .started_at
+                            .filter(|_| workload.state == WorkloadStatus::Running)
+                            .map(|started| format_duration(now.saturating_sub(started)))
+                            .unwrap_or_default(),
+                    ),
+                ]);
+                if let Some(rollback) = workload.rollback {
+                    rollbacks.push((workload.name, rollback));
+                }
+            }
 
-            println!("Recieved response: {:#?}", r);
+            println!("{table}");
+            for (name, rollback) in rollbacks {
+                println!(
+                    "{name} rolled back at {}: {}",
+                    rollback.timestamp, rollback.reason
+                );
+            }
         }
+        DevicesCommands::Workload(command) => workload::exec(a, context, command).await?,
     };
 
     Ok(())
 }
+
+/// The first 12 hex digits of a digest, as podman shows image ids.
+fn short_digest(digest: &str) -> &str {
+    let hex = digest.split_once(':').map_or(digest, |(_, hex)| hex);
+    hex.get(..12).unwrap_or(hex)
+}
+
+fn pull_summary(pull: ImagePull) -> String {
+    let detail = match pull.state {
+        PullState::Failed => pull.error,
+        _ => pull.progress,
+    };
+    match detail {
+        Some(detail) => format!("{} {}: {detail}", pull.state, pull.image),
+        None => format!("{} {}", pull.state, pull.image),
+    }
+}
+
+fn new_table(columns: &[&str]) -> Table {
+    let mut table = Table::new();
+    table
+        .load_preset(comfy_table::presets::UTF8_FULL)
+        .apply_modifier(comfy_table::modifiers::UTF8_ROUND_CORNERS)
+        .set_content_arrangement(comfy_table::ContentArrangement::Dynamic)
+        .set_header(
+            columns
+                .iter()
+                .map(|c| Cell::new(c).add_attribute(Attribute::Bold)),
+        );
+    table
+}
+
+/// How long ago a device reported, colored by how stale the report is.
+fn reported_age_cell(reported_ms: u64, now: u64) -> Cell {
+    let presence = PresenceConfig::default().classify(Some(reported_ms), now);
+    let age = format!("{} ago", format_duration(now.saturating_sub(reported_ms)));
+    match presence {
+        Presence::Online => Cell::new(age),
+        _ => Cell::new(age).fg(Color::Yellow),
+    }
+}
+
+/// One row per device that replied, followed by one per registered device that did not.
+fn fleet_table<T>(
+    report: &FleetReport<T>,
+    columns: &[&str],
+    row: impl Fn(&T) -> Vec<String>,
+) -> Table {
+    let mut header = vec![
+        Cell::new("Device").add_attribute(Attribute::Bold),
+        Cell::new("RTT").add_attribute(Attribute::Bold),
+    ];
+    header.extend(
+        columns
+            .iter()
+            .map(|c| Cell::new(c).add_attribute(Attribute::Bold)),
+    );
+    header.push(Cell::new("HLC").add_attribute(Attribute::Bold));
+
+    let mut table = Table::new();
+    table
+        .load_preset(comfy_table::presets::UTF8_FULL)
+        .apply_modifier(comfy_table::modifiers::UTF8_ROUND_CORNERS)
+        .set_content_arrangement(comfy_table::ContentArrangement::Dynamic)
+        .set_header(header);
+
+    let mut replies: Vec<_> = report.replies.iter().collect();
+    replies.sort_by(|a, b| a.0.cmp(b.0));
+
+    for (device, reply) in replies {
+        let mut cells = vec![
+            Cell::new(device),
+            Cell::new(format!("{:.1}ms", reply.rtt.as_secs_f64() * 1000.0)),
+        ];
+        cells.extend(row(&reply.response).into_iter().map(Cell::new));
+        cells.push(Cell::new(
+            reply
+                .hlc
+                .as_ref()
+                .map(|h| h.to_string())
+                .unwrap_or_default(),
+        ));
+        table.add_row(cells);
+    }
+
+    for device in &report.missing {
+        let mut cells = vec![Cell::new(device), Cell::new("no reply").fg(Color::Red)];
+        cells.extend((0..=columns.len()).map(|_| Cell::new("")));
+        table.add_row(cells);
+    }
+
+    table
+}
+
+fn presence_cell(presence: Presence) -> Cell {
+    let color = match presence {
+        Presence::Online => Color::Green,
+        Presence::Stale => Color::Yellow,
+        Presence::Offline => Color::Red,
+    };
+    Cell::new(presence).fg(color)
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn format_duration(ms: u64) -> String {
+    let secs = ms / 1000;
+    match secs {
+        0..=59 => format!("{secs}s"),
+        60..=3599 => format!("{}m{}s", secs / 60, secs % 60),
+        3600..=86399 => format!("{}h{}m", secs / 3600, (secs % 3600) / 60),
+        _ => format!("{}d{}h", secs / 86400, (secs % 86400) / 3600),
+    }
+}
@@ -0,0 +1,125 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use clap::Parser;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use futures::StreamExt;
+
+use avena::events::EventQuery;
+use avena::hlc::HybridTimestamp;
+use avena::messages::Event;
+use avena::Avena;
+use comfy_table::{Attribute, Cell, Table};
+
+#[derive(Debug, Parser)]
+pub struct EventsCommand {
+    /// Only show events from this device
+    #[clap(long)]
+    device: Option<String>,
+
+    /// Only show events at or after this time: ms since the epoch, or an age like 30s, 15m, 2h, 7d
+    #[clap(long)]
+    since: Option<String>,
+
+    /// Only show events before this time: ms since the epoch, or an age like 30s, 15m, 2h, 7d
+    #[clap(long)]
+    until: Option<String>,
+
+    /// Only show events after this HLC timestamp (wall:counter@node)
+    #[clap(long)]
+    after: Option<HybridTimestamp>,
+
+    /// Only show events before this HLC timestamp (wall:counter@node)
+    #[clap(long)]
+    before: Option<HybridTimestamp>,
+
+    /// Keep printing events as they are recorded
+    #[clap(short, long)]
+    follow: bool,
+}
+
+pub async fn exec(a: Avena, cmd: EventsCommand) -> Result<()> {
+    let query = EventQuery {
+        device: cmd.device,
+        since_ms: cmd.since.as_deref().map(parse_time).transpose()?,
+        until_ms: cmd.until.as_deref().map(parse_time).transpose()?,
+        after: cmd.after,
+        before: cmd.before,
+    };
+
+    if cmd.follow {
+        let mut events = Box::pin(a.follow_events(query).await?);
+        while let Some(event) = events.next().await {
+            let event = event?;
+            println!(
+                "{} {} {} {} {}",
+                event.timestamp,
+                event.device,
+                event.kind,
+                event.issuer.as_deref().unwrap_or("-"),
+                event.payload
+            );
+        }
+        return Ok(());
+    }
+
+    let mut table = Table::new();
+    table
+        .load_preset(comfy_table::presets::UTF8_FULL)
+        .apply_modifier(comfy_table::modifiers::UTF8_ROUND_CORNERS)
+        .set_content_arrangement(comfy_table::ContentArrangement::Dynamic)
+        .set_header(vec![
+            Cell::new("HLC").add_attribute(Attribute::Bold),
+            Cell::new("Device").add_attribute(Attribute::Bold),
+            Cell::new("Event").add_attribute(Attribute::Bold),
+            Cell::new("Issuer").add_attribute(Attribute::Bold),
+            Cell::new("Details").add_attribute(Attribute::Bold),
+        ]);
+
+    for Event {
+        timestamp,
+        device,
+        issuer,
+        kind,
+        payload,
+    } in a.events(&query).await?
+    {
+        table.add_row(vec![
+            Cell::new(timestamp),
+            Cell::new(device),
+            Cell::new(kind),
+            Cell::new(issuer.unwrap_or_default()),
+            Cell::new(if payload.is_null() {
+                String::new()
+            } else {
+                payload.to_string()
+            }),
+        ]);
+    }
+
+    println!("{table}");
+
+    Ok(())
+}
+
+/// Milliseconds since the epoch, either given directly or as an age.
+pub(crate) fn parse_time(s: &str) -> Result<u64> {
+    if let Ok(ms) = s.parse() {
+        return Ok(ms);
+    }
+
+    let (n, unit) = s.split_at(s.len() - s.trim_start_matches(char::is_numeric).len());
+    let n: u64 = n.parse().map_err(|_| eyre!("invalid time {s:?}"))?;
+    let secs = match unit {
+        "s" => n,
+        "m" => n * 60,
+        "h" => n * 60 * 60,
+        "d" => n * 60 * 60 * 24,
+        _ => return Err(eyre!("invalid time {s:?}, expected a unit of s, m, h or d")),
+    };
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    Ok(now.saturating_sub(Duration::from_secs(secs)).as_millis() as u64)
+}
@@ -1,10 +1,17 @@
 pub mod context;
 pub mod devices;
+pub mod events;
+pub mod link;
+pub mod secret;
+pub mod workload;
 
 use clap::Subcommand;
 
 use context::ContextCommand;
 use devices::DeviceCommand;
+use events::EventsCommand;
+use link::LinkCommand;
+use secret::SecretCommand;
 
 #[derive(Subcommand, Debug)]
 pub enum Commands {
@@ -13,4 +20,13 @@ pub enum Commands {
 
     /// Manage Avena fleet devices
     Devices(DeviceCommand),
+
+    /// Show the log of state-changing actions taken by devices
+    Events(EventsCommand),
+
+    /// Manage leaf links between devices
+    Link(LinkCommand),
+
+    /// Manage secrets delivered to device workloads
+    Secret(SecretCommand),
 }
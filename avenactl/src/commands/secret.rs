@@ -0,0 +1,83 @@
+use std::io::Read;
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+
+use avena::Avena;
+use comfy_table::{Attribute, Cell, Table};
+
+#[derive(Debug, Parser)]
+pub struct SecretCommand {
+    #[clap(subcommand)]
+    command: SecretCommands,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SecretCommands {
+    /// Store a secret for a device, encrypted so only that device can read it
+    Create {
+        device: String,
+        name: String,
+
+        /// Read the value from this file instead of stdin
+        #[clap(long)]
+        from_file: Option<PathBuf>,
+    },
+
+    /// List the secrets stored for a device, without their values
+    Ls { device: String },
+
+    /// Delete a secret from a device
+    Rm { device: String, name: String },
+}
+
+pub async fn exec(a: Avena, cmd: SecretCommand) -> Result<()> {
+    match cmd.command {
+        SecretCommands::Create {
+            device,
+            name,
+            from_file,
+        } => {
+            // Values are never taken as arguments, where they would end up
+            // in shell history
+            let value = match from_file {
+                Some(path) => std::fs::read(path)?,
+                None => {
+                    let mut value = vec![];
+                    std::io::stdin().read_to_end(&mut value)?;
+                    value
+                }
+            };
+            a.put_secret(&device, &name, &value).await?;
+            println!("Secret {name} stored for {device}");
+        }
+        SecretCommands::Ls { device } => {
+            let mut table = Table::new();
+            table
+                .load_preset(comfy_table::presets::UTF8_FULL)
+                .apply_modifier(comfy_table::modifiers::UTF8_ROUND_CORNERS)
+                .set_content_arrangement(comfy_table::ContentArrangement::Dynamic)
+                .set_header(vec![
+                    Cell::new("Name").add_attribute(Attribute::Bold),
+                    Cell::new("Updated (HLC)").add_attribute(Attribute::Bold),
+                ]);
+            for secret in a.secrets(&device).await? {
+                table.add_row(vec![
+                    Cell::new(secret.name),
+                    Cell::new(secret.timestamp.map(|t| t.to_string()).unwrap_or_default()),
+                ]);
+            }
+            println!("{table}");
+        }
+        SecretCommands::Rm { device, name } => {
+            if !a.delete_secret(&device, &name).await? {
+                return Err(eyre!("{device} has no secret {name}"));
+            }
+            println!("Secret {name} removed from {device}");
+        }
+    }
+
+    Ok(())
+}
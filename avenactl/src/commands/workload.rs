@@ -0,0 +1,218 @@
+use std::io::{IsTerminal, Read, Write};
+use std::time::Duration;
+
+use clap::Subcommand;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use crossterm::terminal;
+use tokio::sync::mpsc;
+
+use avena::messages::OutputKind;
+use avena::streams::{ExecOptions, LogOptions, OutputStream};
+use avena::Avena;
+
+use super::events::parse_time;
+use crate::config::Context;
+
+#[derive(Debug, Subcommand)]
+pub enum WorkloadCommands {
+    /// Print a workload's logs
+    Logs {
+        device: String,
+        workload: String,
+
+        /// Keep printing new lines until interrupted
+        #[clap(short, long)]
+        follow: bool,
+
+        /// Only show lines logged at or after this time: ms since the epoch, or an age like 30s, 15m, 2h, 7d
+        #[clap(long)]
+        since: Option<String>,
+
+        /// Only show the last this many lines
+        #[clap(short = 'n', long)]
+        tail: Option<u32>,
+    },
+
+    /// Run a command in a workload's container, signed with the context's nkey
+    Exec {
+        device: String,
+        workload: String,
+
+        /// Allocate a TTY and send our input to the command. Ctrl-C is passed
+        /// on; end the session with Ctrl-D or by exiting the command
+        #[clap(short, long)]
+        tty: bool,
+
+        /// Have the device kill the command after this many seconds
+        #[clap(long)]
+        timeout: Option<u64>,
+
+        /// The command and its arguments, after `--`
+        #[clap(required = true, last = true)]
+        command: Vec<String>,
+    },
+}
+
+pub async fn exec(a: Avena, context: &Context, command: WorkloadCommands) -> Result<()> {
+    match command {
+        WorkloadCommands::Logs {
+            device,
+            workload,
+            follow,
+            since,
+            tail,
+        } => {
+            let options = LogOptions {
+                follow,
+                since_ms: since.as_deref().map(parse_time).transpose()?,
+                tail,
+            };
+            let stream = a.workload_logs(&device, &workload, options).await?;
+            match print_stream(stream, false).await? {
+                Some(0) | None => Ok(()),
+                Some(code) => Err(eyre!("reading logs exited with status {code}")),
+            }
+        }
+        WorkloadCommands::Exec {
+            device,
+            workload,
+            tty,
+            timeout,
+            command,
+        } => {
+            let key = context.signing_key()?;
+            let options = ExecOptions {
+                command,
+                tty,
+                timeout: timeout.map(Duration::from_secs),
+            };
+            let stream = a.workload_exec(&device, &workload, options, &key).await?;
+            match print_stream(stream, tty).await? {
+                Some(0) | None => Ok(()),
+                // Exit like the remote command did, as ssh does
+                Some(code) => std::process::exit(code),
+            }
+        }
+    }
+}
+
+/// Copy a device's output to ours until it ends or we are interrupted,
+/// returning the remote exit status. With `interactive`, our terminal is put
+/// in raw mode and our input and Ctrl-C are sent to the remote command
+/// instead.
+async fn print_stream(mut stream: OutputStream, interactive: bool) -> Result<Option<i32>> {
+    let _raw = match interactive {
+        true => RawMode::enable()?,
+        false => None,
+    };
+    let mut input = interactive.then(read_stdin);
+
+    loop {
+        let chunk = tokio::select! {
+            chunk = stream.next() => chunk,
+            data = next_input(&mut input), if input.is_some() => {
+                match data {
+                    Some(data) => stream.send_input(data).await?,
+                    None => {
+                        input = None;
+                        stream.close_input().await?;
+                    }
+                }
+                continue;
+            }
+            _ = tokio::signal::ctrl_c() => {
+                if interactive {
+                    stream.send_input("\u{3}".to_string()).await?;
+                    continue;
+                }
+                stream.cancel().await?;
+                return Ok(None);
+            }
+        };
+        let Some(chunk) = chunk else {
+            return Ok(None);
+        };
+        let chunk = chunk?;
+
+        match chunk.kind {
+            OutputKind::Stderr => {
+                let mut stderr = std::io::stderr().lock();
+                stderr.write_all(chunk.data.as_bytes())?;
+                stderr.flush()?;
+            }
+            _ => {
+                let mut stdout = std::io::stdout().lock();
+                stdout.write_all(chunk.data.as_bytes())?;
+                stdout.flush()?;
+            }
+        }
+
+        if chunk.done {
+            if let Some(error) = chunk.error {
+                return Err(eyre!(error));
+            }
+            return Ok(chunk.exit_code);
+        }
+    }
+}
+
+/// Our terminal in raw mode, so keystrokes reach the remote TTY as they are
+/// typed, until dropped.
+struct RawMode;
+
+impl RawMode {
+    /// Switch to raw mode, unless our input is not a terminal.
+    fn enable() -> Result<Option<RawMode>> {
+        if !std::io::stdin().is_terminal() {
+            return Ok(None);
+        }
+        terminal::enable_raw_mode()?;
+
+        // Restore the terminal before a panic is reported, not after
+        let report = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            let _ = terminal::disable_raw_mode();
+            report(info);
+        }));
+        Ok(Some(RawMode))
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Bytes read from our stdin as they arrive, read on a plain thread so a
+/// pending read does not hold up exit. A UTF-8 sequence split across reads
+/// is held back until it is whole.
+fn read_stdin() -> mpsc::Receiver<String> {
+    let (tx, rx) = mpsc::channel(16);
+    std::thread::spawn(move || {
+        let mut stdin = std::io::stdin().lock();
+        let mut buf = [0; 1024];
+        let mut pending = Vec::new();
+        loop {
+            match stdin.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => pending.extend_from_slice(&buf[..n]),
+            }
+            let whole = match std::str::from_utf8(&pending) {
+                Err(e) if e.error_len().is_none() => e.valid_up_to(),
+                _ => pending.len(),
+            };
+            let data = String::from_utf8_lossy(&pending[..whole]).into_owned();
+            pending.drain(..whole);
+            if !data.is_empty() && tx.blocking_send(data).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+async fn next_input(input: &mut Option<mpsc::Receiver<String>>) -> Option<String> {
+    input.as_mut()?.recv().await
+}
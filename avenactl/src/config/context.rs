@@ -1,4 +1,9 @@
-use color_eyre::eyre::{self, eyre, Result};
+use std::path::PathBuf;
+
+use async_nats::ConnectOptions;
+use avena::Avena;
+use color_eyre::eyre::{self, eyre, Result, WrapErr};
+use nkeys::KeyPair;
 use serde_derive::{Deserialize, Serialize};
 use toml_edit::ser::to_item;
 use toml_edit::Item;
@@ -8,6 +13,32 @@ use toml_edit::Item;
 pub struct Context {
     pub name: String,
     pub connection: String,
+
+    /// NATS credentials file (JWT + nkey seed), e.g. `avena-admin.creds`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub creds: Option<PathBuf>,
+    /// File holding an nkey seed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nkey: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+
+    /// CA certificate used to verify the server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_ca: Option<PathBuf>,
+    /// Client certificate presented to the server; needs `tls_key`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_cert: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_key: Option<PathBuf>,
+
+    /// JetStream domain of the devices' servers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub js_domain: Option<String>,
 }
 
 impl Context {
@@ -15,16 +46,135 @@ impl Context {
         Self {
             name: name.into(),
             connection: connection.into(),
+            creds: None,
+            nkey: None,
+            user: None,
+            password: None,
+            token: None,
+            tls_ca: None,
+            tls_cert: None,
+            tls_key: None,
+            js_domain: None,
+        }
+    }
+
+    /// Short description of how this context authenticates.
+    pub fn auth(&self) -> &'static str {
+        if self.creds.is_some() {
+            "creds"
+        } else if self.nkey.is_some() {
+            "nkey"
+        } else if self.user.is_some() {
+            "user/password"
+        } else if self.token.is_some() {
+            "token"
+        } else {
+            "none"
+        }
+    }
+
+    /// Check the context describes a usable connection.
+    pub fn validate(&self) -> Result<()> {
+        let methods = [
+            self.creds.is_some(),
+            self.nkey.is_some(),
+            self.user.is_some(),
+            self.token.is_some(),
+        ];
+        if methods.iter().filter(|m| **m).count() > 1 {
+            return Err(eyre!(
+                "Context '{}' sets more than one of creds, nkey, user and token.",
+                self.name
+            ));
+        }
+
+        if self.user.is_some() != self.password.is_some() {
+            return Err(eyre!(
+                "Context '{}' needs both a user and a password.",
+                self.name
+            ));
+        }
+
+        if self.tls_cert.is_some() != self.tls_key.is_some() {
+            return Err(eyre!(
+                "Context '{}' needs both a TLS client certificate and key.",
+                self.name
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// The nkey this context signs requests with, from its nkey seed or the
+    /// seed in its creds file.
+    pub fn signing_key(&self) -> Result<KeyPair> {
+        let seed = if let Some(nkey) = &self.nkey {
+            std::fs::read_to_string(nkey)
+                .wrap_err_with(|| format!("Unable to read nkey seed {}", nkey.display()))?
+        } else if let Some(creds) = &self.creds {
+            let contents = std::fs::read_to_string(creds)
+                .wrap_err_with(|| format!("Unable to read creds file {}", creds.display()))?;
+            contents
+                .lines()
+                .map(str::trim)
+                .find(|line| line.starts_with("SU"))
+                .ok_or_else(|| eyre!("No nkey seed in creds file {}", creds.display()))?
+                .to_string()
+        } else {
+            return Err(eyre!(
+                "Context '{}' has no nkey or creds to sign requests with.",
+                self.name
+            ));
+        };
+
+        KeyPair::from_seed(seed.trim()).wrap_err("Invalid nkey seed")
+    }
+
+    /// Connect to the Avena network this context points at.
+    pub async fn connect(&self) -> Result<Avena> {
+        self.validate()?;
+
+        let mut options = ConnectOptions::new();
+
+        if let Some(creds) = &self.creds {
+            options = options
+                .credentials_file(creds)
+                .await
+                .wrap_err_with(|| format!("Unable to read creds file {}", creds.display()))?;
+        }
+        if let Some(nkey) = &self.nkey {
+            let seed = std::fs::read_to_string(nkey)
+                .wrap_err_with(|| format!("Unable to read nkey seed {}", nkey.display()))?;
+            options = options.nkey(seed.trim().to_owned());
+        }
+        if let (Some(user), Some(password)) = (&self.user, &self.password) {
+            options = options.user_and_password(user.clone(), password.clone());
+        }
+        if let Some(token) = &self.token {
+            options = options.token(token.clone());
+        }
+
+        if let Some(ca) = &self.tls_ca {
+            options = options.add_root_certificates(ca.clone()).require_tls(true);
+        }
+        if let (Some(cert), Some(key)) = (&self.tls_cert, &self.tls_key) {
+            options = options
+                .add_client_certificate(cert.clone(), key.clone())
+                .require_tls(true);
         }
+
+        let avena = Avena::connect_with_options(&self.connection, options).await?;
+
+        Ok(match &self.js_domain {
+            Some(domain) => avena.with_jetstream_domain(domain),
+            None => avena,
+        })
     }
 }
 
 impl Default for Context {
     fn default() -> Self {
-        Self {
-            name: "localhost".to_owned(),
-            connection: "localhost".to_owned(),
-        }
+        Self::new("localhost", "localhost")
     }
 }
 
@@ -37,3 +187,115 @@ impl TryInto<Item> for Context {
         })?))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_one_auth_method() {
+        let mut ctx = Context::default();
+        assert!(ctx.validate().is_ok());
+
+        ctx.creds = Some(PathBuf::from("avena-admin.creds"));
+        assert!(ctx.validate().is_ok());
+
+        ctx.token = Some("secret".to_string());
+        assert!(ctx.validate().is_err());
+
+        let ctx = Context {
+            nkey: Some(PathBuf::from("admin.nk")),
+            user: Some("admin".to_string()),
+            password: Some("hunter2".to_string()),
+            ..Context::default()
+        };
+        assert!(ctx.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_user_needs_password() {
+        let mut ctx = Context {
+            user: Some("admin".to_string()),
+            ..Context::default()
+        };
+        assert!(ctx.validate().is_err());
+
+        ctx.password = Some("hunter2".to_string());
+        assert!(ctx.validate().is_ok());
+
+        ctx.user = None;
+        assert!(ctx.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_tls_cert_needs_key() {
+        let mut ctx = Context {
+            tls_ca: Some(PathBuf::from("ca.pem")),
+            ..Context::default()
+        };
+        assert!(ctx.validate().is_ok());
+
+        ctx.tls_cert = Some(PathBuf::from("client.pem"));
+        assert!(ctx.validate().is_err());
+
+        ctx.tls_key = Some(PathBuf::from("client-key.pem"));
+        assert!(ctx.validate().is_ok());
+
+        ctx.tls_cert = None;
+        assert!(ctx.validate().is_err());
+    }
+
+    #[test]
+    fn test_signing_key_from_nkey_seed() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = KeyPair::new_user();
+        let nkey = dir.path().join("admin.nk");
+        std::fs::write(&nkey, format!("{}\n", key.seed().unwrap())).unwrap();
+
+        let ctx = Context {
+            nkey: Some(nkey),
+            ..Context::default()
+        };
+        assert_eq!(ctx.signing_key().unwrap().public_key(), key.public_key());
+    }
+
+    #[test]
+    fn test_signing_key_from_creds_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = KeyPair::new_user();
+        let creds = dir.path().join("admin.creds");
+        std::fs::write(
+            &creds,
+            format!(
+                "-----BEGIN NATS USER JWT-----\neyJ0eXAiOiJKV1QifQ.e30.c2ln\n------END NATS USER JWT------\n\n\
+                 -----BEGIN USER NKEY SEED-----\n{}\n------END USER NKEY SEED------\n",
+                key.seed().unwrap()
+            ),
+        )
+        .unwrap();
+
+        let ctx = Context {
+            creds: Some(creds.clone()),
+            ..Context::default()
+        };
+        assert_eq!(ctx.signing_key().unwrap().public_key(), key.public_key());
+
+        // A creds file holding only the JWT can connect but not sign
+        std::fs::write(
+            &creds,
+            "-----BEGIN NATS USER JWT-----\neyJ0eXAiOiJKV1QifQ.e30.c2ln\n------END NATS USER JWT------\n",
+        )
+        .unwrap();
+        assert!(ctx.signing_key().is_err());
+    }
+
+    #[test]
+    fn test_signing_key_needs_nkey_or_creds() {
+        let ctx = Context {
+            user: Some("admin".to_string()),
+            password: Some("hunter2".to_string()),
+            ..Context::default()
+        };
+        assert!(ctx.signing_key().is_err());
+    }
+}
@@ -39,8 +39,10 @@ impl Config {
     }
 
     pub fn get_active_context(&self) -> Result<&Context> {
-        let context = &self.active_context;
+        self.get_context(&self.active_context)
+    }
 
+    pub fn get_context(&self, context: &str) -> Result<&Context> {
         self.context
             .get(context)
             .ok_or_else(|| eyre!("Non-existent context: {context}"))
@@ -3,7 +3,7 @@ mod config;
 
 use avena::Avena;
 use clap::Parser;
-use config::Config;
+use config::{Config, Context};
 use std::path::PathBuf;
 
 use color_eyre::eyre::{eyre, Result};
@@ -25,11 +25,16 @@ lazy_static! {
 #[clap(author, version, about, long_about = None)]
 /// Manage Avena based devices
 struct Cli {
+    /// Use this context instead of the active one
+    #[clap(long, global = true)]
+    context: Option<String>,
+
     #[clap(subcommand)]
     command: Commands,
 }
 
-fn main() -> Result<()> {
+#[tokio::main]
+async fn main() -> Result<()> {
     // Use color_eyre for applcation error handling
     color_eyre::install()?;
 
@@ -39,14 +44,45 @@ fn main() -> Result<()> {
     // Load Config
     let config = Config::load(CONFIG_PATH.to_path_buf())?;
 
-    // Connect to Avena context
-    let a = Avena::connect(&config.get_active_context()?.connection);
-
     // Pass control the commanded subcommand
     match args.command {
         Commands::Context(context) => commands::context::exec(context),
-        Commands::Devices(node) => commands::devices::exec(a, node),
+        Commands::Devices(node) => {
+            // Connect to Avena context
+            let context = select_context(&config, args.context.as_deref())?;
+            let a = context.connect().await?;
+
+            commands::devices::exec(a, context, node).await
+        }
+        Commands::Events(events) => {
+            let a = connect(&config, args.context.as_deref()).await?;
+
+            commands::events::exec(a, events).await
+        }
+        Commands::Link(link) => {
+            let a = connect(&config, args.context.as_deref()).await?;
+
+            commands::link::exec(a, link).await
+        }
+        Commands::Secret(secret) => {
+            let a = connect(&config, args.context.as_deref()).await?;
+
+            commands::secret::exec(a, secret).await
+        }
     }?;
 
     Ok(())
 }
+
+/// The named context, or the active one.
+fn select_context<'a>(config: &'a Config, context: Option<&str>) -> Result<&'a Context> {
+    match context {
+        Some(name) => config.get_context(name),
+        None => config.get_active_context(),
+    }
+}
+
+/// Connect using the named context, or the active one.
+async fn connect(config: &Config, context: Option<&str>) -> Result<Avena> {
+    select_context(config, context)?.connect().await
+}
@@ -0,0 +1,225 @@
+//! Scoped NATS credentials for workloads.
+//!
+//! A workload whose spec lists NATS permissions gets a user of its own in
+//! the AVENA account, allowed exactly the subjects in its `PermSpec`. The
+//! creds file is mounted read-only into the container at [`CREDS_MOUNT`],
+//! named by the [`CREDS_ENV`] variable. A workload's user is replaced when
+//! its spec changes, and replaced or removed users are revoked in the
+//! account JWT. Users expire after [`CREDS_TTL`] and are renewed well before,
+//! so revocations only need to be kept that long.
+
+use std::collections::BTreeSet;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_nats::jetstream::kv::Store as KvStore;
+use avena::messages::PermSpec;
+use color_eyre::Result;
+use nkeys::KeyPair;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tokio::sync::Mutex;
+
+use crate::nats_jwt::{self, NatsJwtManager};
+
+/// Where a workload's creds file is mounted in its container.
+pub const CREDS_MOUNT: &str = "/run/avena/nats.creds";
+
+/// Environment variable pointing a workload at its creds file.
+pub const CREDS_ENV: &str = "AVENA_NATS_CREDS";
+
+/// How long a workload's user JWT is valid for. Creds are renewed once less
+/// than half of it is left.
+pub const CREDS_TTL: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// How often workloads are reconciled without a change to their spec, so
+/// creds are renewed before they expire.
+pub const RENEW_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// What [`WorkloadCreds::issue`] did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CredsChange {
+    Unchanged,
+    Issued,
+    /// A new user replaced one that is now revoked.
+    Rotated,
+    /// A new user with the same permissions replaced one close to expiry,
+    /// which is left to expire rather than revoked.
+    Renewed,
+}
+
+/// The user last issued to a workload, kept next to its creds file.
+#[derive(Debug, Serialize, Deserialize)]
+struct IssuedUser {
+    pubkey: String,
+    perms: PermSpec,
+    /// When the user's JWT expires, in unix seconds.
+    #[serde(default)]
+    expires: i64,
+}
+
+/// Issues and revokes the NATS users of a device's workloads.
+pub struct WorkloadCreds {
+    dir: PathBuf,
+    nats_cfg_dir: PathBuf,
+    jwt_mgr: Arc<NatsJwtManager>,
+    account_kp: Arc<KeyPair>,
+    reload: Option<(Arc<Mutex<KvStore>>, String)>,
+}
+
+impl WorkloadCreds {
+    /// Keep creds files in `dir`, issuing users of the AVENA account whose
+    /// JWT lives in `nats_cfg_dir`.
+    pub fn new(
+        dir: PathBuf,
+        nats_cfg_dir: PathBuf,
+        jwt_mgr: Arc<NatsJwtManager>,
+        account_kp: Arc<KeyPair>,
+    ) -> Self {
+        WorkloadCreds {
+            dir,
+            nats_cfg_dir,
+            jwt_mgr,
+            account_kp,
+            reload: None,
+        }
+    }
+
+    /// After revoking users, re-render the NATS config with the leaf remotes
+    /// in `links` and reload the server at `nats_url`.
+    pub fn with_nats_reload(mut self, links: Arc<Mutex<KvStore>>, nats_url: String) -> Self {
+        self.reload = Some((links, nats_url));
+        self
+    }
+
+    /// Whether a workload with `perms` gets creds at all.
+    pub fn wanted(perms: &PermSpec) -> bool {
+        !perms.publish.is_empty() || !perms.subscribe.is_empty()
+    }
+
+    /// Host path of the creds file of the workload `name`.
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{name}.creds"))
+    }
+
+    fn user_path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{name}.user"))
+    }
+
+    /// Make sure `name` has creds for `perms`, issuing a new user if it has
+    /// none, its permissions changed, `rotate` is set, or its user expires
+    /// within half of [`CREDS_TTL`]. The user it replaces is revoked, unless
+    /// it was only renewed.
+    pub async fn issue(&self, name: &str, perms: &PermSpec, rotate: bool) -> Result<CredsChange> {
+        let now = unix_now()?;
+        let previous = self.issued(name).await;
+        let mut renew = false;
+        if let Some(user) = &previous {
+            if !rotate && user.perms == *perms && fs::try_exists(self.path(name)).await? {
+                if user.expires - now > CREDS_TTL.as_secs() as i64 / 2 {
+                    return Ok(CredsChange::Unchanged);
+                }
+                renew = true;
+            }
+        }
+
+        let (jwt, user_kp) = self.jwt_mgr.generate_user_jwt_with_expiry(
+            &self.account_kp,
+            name,
+            perms.publish.clone(),
+            perms.subscribe.clone(),
+            Some(CREDS_TTL),
+        )?;
+        let creds = NatsJwtManager::create_creds_file(&jwt, &user_kp)?;
+
+        // The directory keeps other host users out; the file itself must be
+        // readable by whatever user the container runs as
+        fs::create_dir_all(&self.dir).await?;
+        fs::set_permissions(&self.dir, std::fs::Permissions::from_mode(0o700)).await?;
+        write_file(&self.path(name), creds.as_bytes(), 0o644).await?;
+        let user = IssuedUser {
+            pubkey: user_kp.public_key(),
+            perms: perms.clone(),
+            expires: now + CREDS_TTL.as_secs() as i64,
+        };
+        write_file(&self.user_path(name), &serde_json::to_vec(&user)?, 0o600).await?;
+
+        match previous {
+            Some(_) if renew => Ok(CredsChange::Renewed),
+            Some(previous) => {
+                nats_jwt::revoke_user(&self.nats_cfg_dir, &previous.pubkey, CREDS_TTL)?;
+                Ok(CredsChange::Rotated)
+            }
+            None => Ok(CredsChange::Issued),
+        }
+    }
+
+    /// Delete the creds of `name` and revoke its user. Returns whether it had
+    /// any.
+    pub async fn revoke(&self, name: &str) -> Result<bool> {
+        let Some(user) = self.issued(name).await else {
+            return Ok(false);
+        };
+        for path in [self.path(name), self.user_path(name)] {
+            match fs::remove_file(path).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        nats_jwt::revoke_user(&self.nats_cfg_dir, &user.pubkey, CREDS_TTL)?;
+        Ok(true)
+    }
+
+    /// Names of the workloads that hold creds.
+    pub async fn holders(&self) -> Result<BTreeSet<String>> {
+        let mut holders = BTreeSet::new();
+        let mut entries = match fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(holders),
+            Err(e) => return Err(e.into()),
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            let file_name = entry.file_name();
+            if let Some(name) = file_name.to_string_lossy().strip_suffix(".user") {
+                holders.insert(name.to_string());
+            }
+        }
+        Ok(holders)
+    }
+
+    /// Sign the AVENA account again with every revoked user, so the server
+    /// stops accepting them, and reload it if configured to.
+    pub async fn publish_revocations(&self) -> Result<()> {
+        let revocations = nats_jwt::load_revocations(&self.nats_cfg_dir)?;
+        let jwt = self.jwt_mgr.generate_account_jwt_with_revocations(
+            "AVENA",
+            &self.account_kp,
+            true,
+            &revocations,
+        )?;
+        fs::write(self.nats_cfg_dir.join("AVENA.jwt"), jwt).await?;
+
+        if let Some((links, nats_url)) = &self.reload {
+            crate::reconcile_leaves(links, &self.jwt_mgr.operator_pubkey(), nats_url).await?;
+        }
+        Ok(())
+    }
+
+    async fn issued(&self, name: &str) -> Option<IssuedUser> {
+        let data = fs::read(self.user_path(name)).await.ok()?;
+        serde_json::from_slice(&data).ok()
+    }
+}
+
+fn unix_now() -> Result<i64> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64)
+}
+
+async fn write_file(path: &Path, contents: &[u8], mode: u32) -> Result<()> {
+    fs::write(path, contents).await?;
+    fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).await?;
+    Ok(())
+}
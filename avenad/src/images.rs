@@ -0,0 +1,222 @@
+//! Pulling workload images ahead of switching their units.
+//!
+//! Reconcile starts a changed workload's pull in the background while its old
+//! container keeps running, and leaves the workload as it is until the pull
+//! finishes. The next reconcile then pins the new quadlet to the digest the
+//! pull resolved to, so the restart never waits on the registry. Pulls in
+//! progress and pulls that failed are kept here by workload so status can
+//! report them.
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use avena::messages::{ImagePull, PullState};
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+use tokio::sync::Notify;
+use tracing::warn;
+
+use crate::now_millis;
+
+/// How long a failed pull waits before reconcile tries it again.
+pub const PULL_RETRY: Duration = Duration::from_secs(60);
+
+/// The image pulls of a device's workloads, shared between reconcile and
+/// status.
+#[derive(Debug, Clone, Default)]
+pub struct ImagePulls {
+    state: Arc<Mutex<Pulls>>,
+    finished: Arc<Notify>,
+}
+
+#[derive(Debug, Default)]
+struct Pulls {
+    /// Pulls in progress or failed, by workload.
+    current: BTreeMap<String, ImagePull>,
+    /// Finished pulls not deployed yet, by workload, as image and digest.
+    pulled: BTreeMap<String, (String, String)>,
+    /// Workloads whose failed pull reconcile has not reported yet.
+    unreported: BTreeSet<String>,
+}
+
+impl ImagePulls {
+    /// The pull in progress for, or last failed by, the workload `name`.
+    pub fn get(&self, name: &str) -> Option<ImagePull> {
+        self.state.lock().unwrap().current.get(name).cloned()
+    }
+
+    pub fn any_failed(&self) -> bool {
+        self.state
+            .lock()
+            .unwrap()
+            .current
+            .values()
+            .any(|pull| pull.state == PullState::Failed)
+    }
+
+    /// Forget the pulls of workloads not in `names`.
+    pub fn retain(&self, names: &HashSet<String>) {
+        let mut state = self.state.lock().unwrap();
+        state.current.retain(|name, _| names.contains(name));
+        state.pulled.retain(|name, _| names.contains(name));
+        state.unreported.retain(|name| names.contains(name));
+    }
+
+    /// Start pulling `image` for the workload `name` in the background,
+    /// unless it is already being pulled. [`ImagePulls::finished`] resolves
+    /// once it succeeds or fails.
+    pub fn start(&self, name: &str, image: &str) {
+        {
+            let mut state = self.state.lock().unwrap();
+            let pulling = state
+                .current
+                .get(name)
+                .is_some_and(|pull| pull.image == image && pull.state == PullState::Pulling);
+            if pulling {
+                return;
+            }
+            state.pulled.remove(name);
+            state.unreported.remove(name);
+            state.current.insert(
+                name.to_string(),
+                ImagePull {
+                    image: image.to_string(),
+                    state: PullState::Pulling,
+                    progress: None,
+                    error: None,
+                    started_at: now_millis(),
+                },
+            );
+        }
+
+        let pulls = self.clone();
+        let (name, image) = (name.to_string(), image.to_string());
+        tokio::spawn(async move {
+            let result = pulls.pull_image(&name, &image).await;
+            {
+                let mut state = pulls.state.lock().unwrap();
+                // The workload was removed, or moved on to another image
+                let Some(pull) = state.current.get_mut(&name).filter(|p| p.image == image) else {
+                    return;
+                };
+                match result {
+                    Ok(digest) => {
+                        state.current.remove(&name);
+                        state.pulled.insert(name, (image, digest));
+                    }
+                    Err(err) => {
+                        warn!("Image pull of {image} for {name} failed: {err}");
+                        pull.state = PullState::Failed;
+                        pull.error = Some(err.to_string());
+                        state.unreported.insert(name);
+                    }
+                }
+            }
+            pulls.finished.notify_one();
+        });
+    }
+
+    /// The digest `image` resolved to, if a pull of it for the workload
+    /// `name` finished since it was last taken.
+    pub fn take_pulled(&self, name: &str, image: &str) -> Option<String> {
+        let mut state = self.state.lock().unwrap();
+        match state.pulled.get(name) {
+            Some((pulled, _)) if pulled == image => state.pulled.remove(name).map(|(_, d)| d),
+            _ => None,
+        }
+    }
+
+    /// Why the pull of `image` for the workload `name` failed, the first time
+    /// this is asked after it did.
+    pub fn take_failure(&self, name: &str, image: &str) -> Option<String> {
+        let mut state = self.state.lock().unwrap();
+        let error = state
+            .current
+            .get(name)
+            .filter(|pull| pull.image == image && pull.state == PullState::Failed)?
+            .error
+            .clone();
+        state
+            .unreported
+            .remove(name)
+            .then(|| error.unwrap_or_default())
+    }
+
+    /// Wait for a pull to succeed or fail. A pull that finished while nobody
+    /// was waiting wakes the next caller.
+    pub async fn finished(&self) {
+        self.finished.notified().await
+    }
+
+    async fn pull_image(&self, name: &str, image: &str) -> Result<String> {
+        let mut child = Command::new("podman")
+            .args(["pull", image])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+
+        // podman reports progress on stderr, ending with the error if any
+        let mut last = None;
+        if let Some(stderr) = child.stderr.take() {
+            let mut lines = BufReader::new(stderr).lines();
+            while let Some(line) = lines.next_line().await? {
+                let line = line.trim().to_string();
+                if line.is_empty() {
+                    continue;
+                }
+                let mut state = self.state.lock().unwrap();
+                if let Some(pull) = state.current.get_mut(name).filter(|p| p.image == image) {
+                    pull.progress = Some(line.clone());
+                }
+                last = Some(line);
+            }
+        }
+        let status = child.wait().await?;
+        if !status.success() {
+            let reason = last.unwrap_or_else(|| status.to_string());
+            return Err(eyre!("podman pull {image}: {reason}"));
+        }
+
+        let digest = image_digest(image).await?;
+        verify_digest(image, &digest)?;
+        Ok(digest)
+    }
+}
+
+/// The digest of the local image `image`.
+async fn image_digest(image: &str) -> Result<String> {
+    let output = Command::new("podman")
+        .args(["image", "inspect", "--format", "{{.Digest}}", image])
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "podman image inspect {image}: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Check `digest` is a sha256 digest, and the one `image` is pinned to if
+/// its reference includes one.
+pub fn verify_digest(image: &str, digest: &str) -> Result<()> {
+    let valid = digest
+        .strip_prefix("sha256:")
+        .is_some_and(|hex| hex.len() == 64 && hex.bytes().all(|b| b.is_ascii_hexdigit()));
+    if !valid {
+        return Err(eyre!("{image} resolved to invalid digest {digest:?}"));
+    }
+    if let Some((_, pinned)) = image.split_once('@') {
+        if pinned != digest {
+            return Err(eyre!("{image} resolved to {digest}"));
+        }
+    }
+    Ok(())
+}
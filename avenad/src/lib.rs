@@ -1,15 +1,22 @@
 use std::env;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use avena::events::EventLog;
 use avena::hlc::HlcClock;
 use avena::messages::{
-    Announce, LinkRegisterRequest, LinkRegisterResponse, LinkUnregisterRequest,
-    LinkUnregisterResponse, MountSpec, PermSpec, PingResponse, StatusResponse, WorkloadCommand,
+    Announce, ErrorResponse, EventKind, ExecRequest, LinkRegisterRequest, LinkRegisterResponse,
+    LinkUnregisterRequest, LinkUnregisterResponse, LogsRequest, MountSpec, PermSpec, PingRequest,
+    PingResponse, StatusRequest, StatusResponse, StreamOpened, WorkloadCommand,
     WorkloadCommandRequest, WorkloadCommandResponse, WorkloadDesiredState, WorkloadListItem,
-    WorkloadSpec, WorkloadState, WorkloadStatus, WorkloadStatusLite, WorkloadsListResponse,
-    ANNOUNCE_SUBJECT,
+    WorkloadRollback, WorkloadSpec, WorkloadState, WorkloadStatus, WorkloadStatusLite,
+    WorkloadSync, WorkloadsListRequest, WorkloadsListResponse, ANNOUNCE_SUBJECT,
 };
+use avena::presence::PresenceConfig;
+use avena::reported::StateReporter;
+use avena::rpc::{RequestContext, RpcServer};
+use avena::streams::StreamSink;
+use color_eyre::eyre::eyre;
 use color_eyre::Result;
 use futures::StreamExt;
 use std::sync::Arc;
@@ -19,16 +26,26 @@ use zbus::Connection;
 use async_nats::jetstream::kv::Store as KvStore;
 use async_nats::Client;
 use tokio::fs;
-use avena::messages::PortSpec;
+use avena::messages::{PortSpec, ResourceSpec, ResourceUsage, VolumeRetention, VolumeSpec};
 use tracing::{info, warn, error};
+pub mod creds;
 pub mod device;
+pub mod images;
 pub mod link;
 pub mod nats_jwt;
+pub mod output;
+pub mod rollback;
+pub mod secrets;
 pub mod workload;
 pub mod systemd;
+use crate::creds::{CredsChange, WorkloadCreds, RENEW_INTERVAL};
 use crate::device::DeviceIdentity;
+use crate::images::{ImagePulls, PULL_RETRY};
+use crate::rollback::{KnownGood, Trial, UnitCheck, Verdict};
+use crate::secrets::SecretChange;
 use crate::systemd::manager::Systemd1ManagerProxy;
-use crate::workload::WorkloadDeployment;
+use crate::systemd::service_unit::ServiceUnitProxy;
+use crate::workload::{ReconcilePlan, UnitChange, WorkloadDeployment};
 use serde::{Deserialize, Serialize};
 use askama::Template;
 
@@ -68,6 +85,7 @@ struct NatsServerConfTemplateLeafNodeRemote<'a> {
 }
 
 /// Handle link register requests (store remote targets in KV).
+#[allow(clippy::too_many_arguments)]
 pub async fn serve_link_register(
     nc: Client,
     subject: String,
@@ -76,50 +94,68 @@ pub async fn serve_link_register(
     issuer_pub_key: String,
     device: DeviceIdentity,
     hlc: Arc<HlcClock>,
+    events: EventLog,
 ) -> Result<()> {
-    let mut sub = nc.subscribe(subject).await?;
-    while let Some(msg) = sub.next().await {
-        hlc.extract_and_merge(msg.headers.as_ref());
-
-        if let Some(reply) = msg.reply {
-            let req: LinkRegisterRequest = serde_json::from_slice(&msg.payload)?;
-            let ok = link_offer_handshake(&req.remote_url, &device, &issuer_pub_key, &nats_url, &kv).await?;
-
-            let mut headers = async_nats::HeaderMap::new();
-            hlc.attach_to_headers(&mut headers);
-
-            if ok {
-                let guard = kv.lock().await;
-                let _ = guard
-                    .put(
-                        format!("link:{}", req.remote_url),
-                        serde_json::to_vec(&LinkEntry {
-                            url: req.remote_url.clone(),
-                            creds_path: None,
-                            inline_creds: None,
-                        })?
-                        .into(),
-                    )
-                    .await;
+    RpcServer::new(nc, hlc)
+        .serve(subject, |req: LinkRegisterRequest, ctx| {
+            link_register(req, ctx, &kv, &nats_url, &issuer_pub_key, &device, &events)
+        })
+        .await?;
 
-                let resp = LinkRegisterResponse {
-                    ok: true,
-                    message: "stored link request".to_string(),
-                };
-                nc.publish_with_headers(reply, headers, Vec::from(resp).into()).await?;
+    Ok(())
+}
 
-                let _ = reconcile_leaves(&kv, &issuer_pub_key, &nats_url).await;
-            } else {
-                let resp = LinkRegisterResponse {
-                    ok: false,
-                    message: "link offer failed".to_string(),
-                };
-                nc.publish_with_headers(reply, headers, Vec::from(resp).into()).await?;
-            }
-        }
+async fn link_register(
+    req: LinkRegisterRequest,
+    ctx: RequestContext,
+    kv: &Arc<Mutex<KvStore>>,
+    nats_url: &str,
+    issuer_pub_key: &str,
+    device: &DeviceIdentity,
+    events: &EventLog,
+) -> std::result::Result<LinkRegisterResponse, ErrorResponse> {
+    let ok = link_offer_handshake(&req.remote_url, device, issuer_pub_key, nats_url, kv)
+        .await
+        .map_err(ErrorResponse::internal)?;
+
+    if !ok {
+        return Ok(LinkRegisterResponse {
+            ok: false,
+            message: "link offer failed".to_string(),
+        });
     }
 
-    Ok(())
+    let entry = serde_json::to_vec(&LinkEntry {
+        url: req.remote_url.clone(),
+        creds_path: None,
+        inline_creds: None,
+    })
+    .map_err(ErrorResponse::internal)?;
+    let guard = kv.lock().await;
+    let _ = guard
+        .put(format!("link:{}", req.remote_url), entry.into())
+        .await;
+    drop(guard);
+    record_event(
+        events,
+        EventKind::LinkRegistered,
+        claimed_issuer(&ctx),
+        serde_json::json!({ "remote_url": req.remote_url }),
+    )
+    .await;
+
+    // Reload NATS after the reply is on its way
+    let kv = kv.clone();
+    let issuer_pub_key = issuer_pub_key.to_string();
+    let nats_url = nats_url.to_string();
+    tokio::spawn(async move {
+        let _ = reconcile_leaves(&kv, &issuer_pub_key, &nats_url).await;
+    });
+
+    Ok(LinkRegisterResponse {
+        ok: true,
+        message: "stored link request".to_string(),
+    })
 }
 
 /// Handle link unregister requests (remove link from KV and reload NATS).
@@ -130,43 +166,75 @@ pub async fn serve_link_unregister(
     nats_url: String,
     issuer_pub_key: String,
     hlc: Arc<HlcClock>,
+    events: EventLog,
 ) -> Result<()> {
-    let mut sub = nc.subscribe(subject).await?;
-    while let Some(msg) = sub.next().await {
-        hlc.extract_and_merge(msg.headers.as_ref());
+    RpcServer::new(nc, hlc)
+        .serve(subject, |req: LinkUnregisterRequest, ctx| {
+            link_unregister(req, ctx, &kv, &nats_url, &issuer_pub_key, &events)
+        })
+        .await?;
 
-        if let Some(reply) = msg.reply {
-            let req: LinkUnregisterRequest = serde_json::from_slice(&msg.payload)?;
-            let key = format!("link:{}", req.remote_url);
+    Ok(())
+}
 
-            let guard = kv.lock().await;
-            let existed = guard.get(&key).await?.is_some();
-            if existed {
-                let _ = guard.delete(&key).await;
-            }
-            drop(guard);
+async fn link_unregister(
+    req: LinkUnregisterRequest,
+    ctx: RequestContext,
+    kv: &Arc<Mutex<KvStore>>,
+    nats_url: &str,
+    issuer_pub_key: &str,
+    events: &EventLog,
+) -> std::result::Result<LinkUnregisterResponse, ErrorResponse> {
+    let key = format!("link:{}", req.remote_url);
 
-            let mut headers = async_nats::HeaderMap::new();
-            hlc.attach_to_headers(&mut headers);
+    let guard = kv.lock().await;
+    let existed = guard
+        .get(&key)
+        .await
+        .map_err(ErrorResponse::internal)?
+        .is_some();
+    if existed {
+        let _ = guard.delete(&key).await;
+    }
+    drop(guard);
 
-            if existed {
-                let _ = reconcile_leaves(&kv, &issuer_pub_key, &nats_url).await;
-                let resp = LinkUnregisterResponse {
-                    ok: true,
-                    message: format!("removed link to {}", req.remote_url),
-                };
-                nc.publish_with_headers(reply, headers, Vec::from(resp).into()).await?;
-            } else {
-                let resp = LinkUnregisterResponse {
-                    ok: false,
-                    message: format!("no link found for {}", req.remote_url),
-                };
-                nc.publish_with_headers(reply, headers, Vec::from(resp).into()).await?;
-            }
-        }
+    if existed {
+        record_event(
+            events,
+            EventKind::LinkUnregistered,
+            claimed_issuer(&ctx),
+            serde_json::json!({ "remote_url": req.remote_url }),
+        )
+        .await;
+        let _ = reconcile_leaves(kv, issuer_pub_key, nats_url).await;
+        Ok(LinkUnregisterResponse {
+            ok: true,
+            message: format!("removed link to {}", req.remote_url),
+        })
+    } else {
+        Ok(LinkUnregisterResponse {
+            ok: false,
+            message: format!("no link found for {}", req.remote_url),
+        })
     }
+}
 
-    Ok(())
+/// The HLC node id whoever sent an RPC claims, for event records. It is
+/// self-declared and unverified.
+fn claimed_issuer(ctx: &RequestContext) -> Option<String> {
+    ctx.caller.as_ref().map(|ts| ts.node_id.clone())
+}
+
+/// Append to the event log, logging instead of failing the action recorded.
+async fn record_event(
+    events: &EventLog,
+    kind: EventKind,
+    issuer: Option<String>,
+    payload: serde_json::Value,
+) {
+    if let Err(err) = events.record(kind, issuer, payload).await {
+        warn!("Unable to record {kind} event: {err}");
+    }
 }
 
 /// Reply to ping requests on the given subject.
@@ -178,53 +246,43 @@ pub async fn serve_ping(
     started: Instant,
     hlc: Arc<HlcClock>,
 ) -> Result<()> {
-    let mut sub = nc.subscribe(subject).await?;
-
-    while let Some(message) = sub.next().await {
-        hlc.extract_and_merge(message.headers.as_ref());
-
-        if let Some(reply) = message.reply {
+    RpcServer::new(nc, hlc)
+        .serve(subject, |_: PingRequest, _| {
             let resp = PingResponse {
                 device: device_id.clone(),
                 avena_version: env!("CARGO_PKG_VERSION").to_string(),
                 uptime_ms: started.elapsed().as_millis() as u64,
                 nats_name: nats_name.clone(),
             };
-            let mut headers = async_nats::HeaderMap::new();
-            hlc.attach_to_headers(&mut headers);
-            nc.publish_with_headers(reply, headers, Vec::from(resp).into()).await?;
-        }
-    }
+            async move { Ok(resp) }
+        })
+        .await?;
 
     Ok(())
 }
 
 /// Reply to status requests on the given subject.
+#[allow(clippy::too_many_arguments)]
 pub async fn serve_status(
     nc: async_nats::Client,
     subject: String,
     started: Instant,
     device: DeviceIdentity,
     hlc: Arc<HlcClock>,
+    kv: Arc<Mutex<KvStore>>,
+    systemd_dir: std::path::PathBuf,
+    pulls: ImagePulls,
 ) -> Result<()> {
-    let mut sub = nc.subscribe(subject).await?;
-
-    while let Some(message) = sub.next().await {
-        hlc.extract_and_merge(message.headers.as_ref());
-
-        if let Some(reply) = message.reply {
-            let resp = StatusResponse {
+    RpcServer::new(nc, hlc)
+        .serve(subject, |_: StatusRequest, _| async {
+            Ok(StatusResponse {
                 device: device.id.clone(),
                 avena_version: env!("CARGO_PKG_VERSION").to_string(),
                 uptime_ms: started.elapsed().as_millis() as u64,
-                workloads: current_workloads().await,
-            };
-
-            let mut headers = async_nats::HeaderMap::new();
-            hlc.attach_to_headers(&mut headers);
-            nc.publish_with_headers(reply, headers, Vec::from(resp).into()).await?;
-        }
-    }
+                workloads: device_workloads(&kv, &device.id, &systemd_dir, &pulls).await,
+            })
+        })
+        .await?;
 
     Ok(())
 }
@@ -235,48 +293,30 @@ pub async fn serve_workloads_list(
     subject: String,
     device: DeviceIdentity,
     hlc: Arc<HlcClock>,
+    kv: Arc<Mutex<KvStore>>,
+    systemd_dir: std::path::PathBuf,
+    pulls: ImagePulls,
 ) -> Result<()> {
-    let mut sub = nc.subscribe(subject).await?;
-
-    while let Some(message) = sub.next().await {
-        hlc.extract_and_merge(message.headers.as_ref());
-
-        if let Some(reply) = message.reply {
-            let resp = WorkloadsListResponse {
+    RpcServer::new(nc, hlc)
+        .serve(subject, |_: WorkloadsListRequest, _| async {
+            Ok(WorkloadsListResponse {
                 device: device.id.clone(),
-                workloads: current_workloads()
+                workloads: device_workloads(&kv, &device.id, &systemd_dir, &pulls)
                     .await
                     .into_iter()
                     .map(|state| WorkloadListItem {
-                        name: state.name.clone(),
-                        spec: WorkloadSpec {
-                            image: state.image.clone(),
-                            tag: None,
-                            cmd: None,
-                            args: vec![],
-                            env: vec![],
-                            mounts: vec![],
-                            devices: vec![],
-                            perms: PermSpec {
-                                publish: vec![],
-                                subscribe: vec![],
-                            },
-                            ports: vec![],
-                            volumes: vec![],
-                        },
+                        name: state.name,
+                        spec: state.spec,
                         state: WorkloadStatusLite {
-                            status: state.state.clone(),
+                            status: state.state,
                             since: state.started_at,
+                            sync: state.sync,
                         },
                     })
                     .collect(),
-            };
-
-            let mut headers = async_nats::HeaderMap::new();
-            hlc.attach_to_headers(&mut headers);
-            nc.publish_with_headers(reply, headers, Vec::from(resp).into()).await?;
-        }
-    }
+            })
+        })
+        .await?;
 
     Ok(())
 }
@@ -286,29 +326,40 @@ pub async fn serve_workload_command(
     nc: async_nats::Client,
     subject: String,
     hlc: Arc<HlcClock>,
+    events: EventLog,
 ) -> Result<()> {
-    let mut sub = nc.subscribe(subject).await?;
-
-    while let Some(message) = sub.next().await {
-        hlc.extract_and_merge(message.headers.as_ref());
-
-        if let Some(reply) = message.reply {
-            let req: WorkloadCommandRequest = serde_json::from_slice(&message.payload)?;
-            info!("Workload command: {:?} for {}", req.command, req.workload);
-            let resp =
-                handle_workload_command(req)
+    RpcServer::new(nc, hlc)
+        .serve(subject, |req: WorkloadCommandRequest, ctx| {
+            let events = &events;
+            async move {
+                info!("Workload command: {:?} for {}", req.command, req.workload);
+                let kind = match req.command {
+                    WorkloadCommand::Start => Some(EventKind::WorkloadStarted),
+                    WorkloadCommand::Stop => Some(EventKind::WorkloadStopped),
+                    WorkloadCommand::Restart => Some(EventKind::WorkloadRestarted),
+                    WorkloadCommand::Logs { .. } => None,
+                };
+                let workload = req.workload.clone();
+                let resp = handle_workload_command(req)
                     .await
                     .unwrap_or_else(|e| WorkloadCommandResponse {
                         ok: false,
                         message: format!("{e:?}"),
                         logs: None,
                     });
-
-            let mut headers = async_nats::HeaderMap::new();
-            hlc.attach_to_headers(&mut headers);
-            nc.publish_with_headers(reply, headers, Vec::from(resp).into()).await?;
-        }
-    }
+                if let (Some(kind), true) = (kind, resp.ok) {
+                    record_event(
+                        events,
+                        kind,
+                        claimed_issuer(&ctx),
+                        serde_json::json!({ "workload": workload }),
+                    )
+                    .await;
+                }
+                Ok(resp)
+            }
+        })
+        .await?;
 
     Ok(())
 }
@@ -366,6 +417,175 @@ async fn handle_workload_command(req: WorkloadCommandRequest) -> Result<Workload
         }
     }
 }
+
+/// Stream workload logs to callers, following them if asked.
+pub async fn serve_workload_logs(
+    nc: async_nats::Client,
+    subject: String,
+    hlc: Arc<HlcClock>,
+) -> Result<()> {
+    RpcServer::new(nc.clone(), hlc)
+        .serve(subject, |req: LogsRequest, _ctx| {
+            let nc = nc.clone();
+            async move {
+                workload::check_file_stem(&req.workload).map_err(ErrorResponse::bad_request)?;
+                let child = output::spawn_piped(&mut logs_command(&req), false)
+                    .map_err(ErrorResponse::internal)?;
+                let sink = StreamSink::open(nc, req.inbox.clone(), req.window)
+                    .await
+                    .map_err(ErrorResponse::internal)?;
+                let control = sink.control_subject().to_string();
+                info!("Streaming logs of {} (follow: {})", req.workload, req.follow);
+                tokio::spawn(output::pump(sink, child, None));
+                Ok(StreamOpened { control })
+            }
+        })
+        .await?;
+
+    Ok(())
+}
+
+fn logs_command(req: &LogsRequest) -> Command {
+    let mut cmd = Command::new("journalctl");
+    cmd.arg("-u")
+        .arg(format!("{}.service", workload::unit_stem(&req.workload)))
+        .arg("--no-pager");
+    if req.follow {
+        cmd.arg("--follow");
+    }
+    if let Some(since_ms) = req.since_ms {
+        cmd.arg("--since").arg(format!("@{}", since_ms / 1000));
+    }
+    if let Some(lines) = req.tail {
+        cmd.arg("-n").arg(lines.to_string());
+    }
+    cmd
+}
+
+/// How far the time an exec request was signed may be from ours.
+const EXEC_MAX_SKEW_MS: u64 = 60_000;
+
+/// Nonces of the exec requests a device has run, kept until the requests
+/// would have expired anyway.
+#[derive(Debug, Default)]
+pub struct ExecNonces(std::sync::Mutex<HashMap<String, u64>>);
+
+impl ExecNonces {
+    /// Record `nonce` as used by a request signed at `issued_ms`, returning
+    /// false if it already was.
+    fn claim(&self, nonce: &str, issued_ms: u64, now_ms: u64) -> bool {
+        let mut seen = self.0.lock().unwrap();
+        seen.retain(|_, issued| now_ms.abs_diff(*issued) <= EXEC_MAX_SKEW_MS);
+        match seen.contains_key(nonce) {
+            true => false,
+            false => {
+                seen.insert(nonce.to_string(), issued_ms);
+                true
+            }
+        }
+    }
+}
+
+/// Run commands in workload containers for callers whose key is in
+/// `exec_keys`, streaming their output.
+pub async fn serve_workload_exec(
+    nc: async_nats::Client,
+    subject: String,
+    device_id: String,
+    exec_keys: Vec<String>,
+    hlc: Arc<HlcClock>,
+    events: EventLog,
+) -> Result<()> {
+    let nonces = ExecNonces::default();
+    RpcServer::new(nc.clone(), hlc)
+        .serve(subject, |req: ExecRequest, _ctx| {
+            let nc = nc.clone();
+            let (device_id, exec_keys, nonces, events) = (&device_id, &exec_keys, &nonces, &events);
+            async move {
+                if let Err(err) = authorize_exec(&req, device_id, exec_keys, nonces, now_millis()) {
+                    warn!("Refused exec in {} by {}: {}", req.workload, req.caller, err.message);
+                    return Err(err);
+                }
+                let child = output::spawn_piped(&mut exec_command(&req), req.tty)
+                    .map_err(ErrorResponse::internal)?;
+                let sink = StreamSink::open(nc, req.inbox.clone(), req.window)
+                    .await
+                    .map_err(ErrorResponse::internal)?;
+                let control = sink.control_subject().to_string();
+
+                info!("Exec in {} by {}: {:?}", req.workload, req.caller, req.command);
+                record_event(
+                    events,
+                    EventKind::WorkloadExec,
+                    // Verified by authorize_exec, unlike the HLC node id
+                    Some(req.caller.clone()),
+                    serde_json::json!({
+                        "workload": req.workload,
+                        "command": req.command,
+                        "tty": req.tty,
+                        "caller": req.caller,
+                    }),
+                )
+                .await;
+
+                let timeout = req.timeout_secs.map(Duration::from_secs);
+                tokio::spawn(output::pump(sink, child, timeout));
+                Ok(StreamOpened { control })
+            }
+        })
+        .await?;
+
+    Ok(())
+}
+
+/// Check an exec request names a valid workload and a command and was signed
+/// within the last minute, for this device, by a key in `exec_keys`, and
+/// that its nonce is not in `nonces` already.
+pub fn authorize_exec(
+    req: &ExecRequest,
+    device_id: &str,
+    exec_keys: &[String],
+    nonces: &ExecNonces,
+    now_ms: u64,
+) -> std::result::Result<(), ErrorResponse> {
+    workload::check_file_stem(&req.workload).map_err(ErrorResponse::bad_request)?;
+    if req.command.is_empty() {
+        return Err(ErrorResponse::bad_request("command is required"));
+    }
+    if req.nonce.is_empty() {
+        return Err(ErrorResponse::bad_request("nonce is required"));
+    }
+    if !exec_keys.contains(&req.caller) {
+        return Err(ErrorResponse::unauthorized(format!(
+            "{} may not exec on {device_id}",
+            req.caller
+        )));
+    }
+    let signed = DeviceIdentity::verify(&req.caller, &req.signed_payload(device_id), &req.signature)
+        .unwrap_or(false);
+    if !signed {
+        return Err(ErrorResponse::unauthorized("exec request signature is invalid"));
+    }
+    if now_ms.abs_diff(req.issued_ms) > EXEC_MAX_SKEW_MS {
+        return Err(ErrorResponse::unauthorized("exec request has expired"));
+    }
+    // Only signed requests are recorded, so others can not fill the table
+    if !nonces.claim(&req.nonce, req.issued_ms, now_ms) {
+        return Err(ErrorResponse::unauthorized("exec request was already run"));
+    }
+    Ok(())
+}
+
+fn exec_command(req: &ExecRequest) -> Command {
+    let mut cmd = Command::new("podman");
+    cmd.arg("exec");
+    if req.tty {
+        cmd.arg("--interactive").arg("--tty");
+    }
+    cmd.arg(workload::unit_stem(&req.workload)).args(&req.command);
+    cmd
+}
+
 /// Periodically publish device announces.
 pub async fn serve_announce(
     nc: async_nats::Client,
@@ -392,13 +612,14 @@ pub async fn serve_announce(
         let _ = guard
             .put(
                 device.id.clone(),
-                serde_json::to_vec(&avena::messages::Device {
+                Vec::from(avena::messages::Device {
                     id: device.id.clone(),
                     version: env!("CARGO_PKG_VERSION").to_string(),
                     last_seen_ms: Some(now_millis()),
+                    uptime_ms: Some(started.elapsed().as_millis() as u64),
                     nats_name: Some(nats_name.clone()),
                     pubkey: Some(device.pubkey.clone()),
-                })?
+                })
                 .into(),
             )
             .await;
@@ -421,13 +642,14 @@ pub async fn serve_announce(
             let _ = guard
                 .put(
                     device.id.clone(),
-                    serde_json::to_vec(&avena::messages::Device {
+                    Vec::from(avena::messages::Device {
                         id: device.id.clone(),
                         version: env!("CARGO_PKG_VERSION").to_string(),
                         last_seen_ms: Some(now_millis()),
+                        uptime_ms: Some(started.elapsed().as_millis() as u64),
                         nats_name: Some(nats_name.clone()),
                         pubkey: Some(device.pubkey.clone()),
-                    })?
+                    })
                     .into(),
                 )
                 .await;
@@ -447,13 +669,14 @@ pub async fn observe_announces(
             let _ = guard
                 .put(
                     announce.device.clone(),
-                    serde_json::to_vec(&avena::messages::Device {
+                    Vec::from(avena::messages::Device {
                         id: announce.device.clone(),
                         version: announce.avena_version.clone(),
                         last_seen_ms: Some(now_millis()),
+                        uptime_ms: Some(announce.uptime_ms),
                         nats_name: Some(announce.nats_name.clone()),
                         pubkey: announce.pubkey.clone(),
-                    })?
+                    })
                     .into(),
                 )
                 .await;
@@ -463,6 +686,46 @@ pub async fn observe_announces(
     Ok(())
 }
 
+/// Periodically delete registry entries for devices that have been gone too long.
+pub async fn expire_devices(
+    kv: Arc<Mutex<KvStore>>,
+    presence: PresenceConfig,
+    events: EventLog,
+) -> Result<()> {
+    let mut ticker = tokio::time::interval(Duration::from_secs(60));
+    loop {
+        ticker.tick().await;
+
+        let guard = kv.lock().await;
+        let mut keys = guard.keys().await?;
+        while let Some(key) = keys.next().await {
+            let Ok(key) = key else { continue };
+            let Ok(Some(val)) = guard.get(&key).await else {
+                continue;
+            };
+            let Ok(device) = avena::messages::Device::try_from(val.as_ref()) else {
+                continue;
+            };
+
+            if presence.is_expired(device.last_seen_ms, now_millis()) {
+                info!("Expiring device {key} from the registry");
+                match guard.delete(&key).await {
+                    Ok(()) => {
+                        record_event(
+                            &events,
+                            EventKind::DeviceExpired,
+                            None,
+                            serde_json::json!({ "device": key, "last_seen_ms": device.last_seen_ms }),
+                        )
+                        .await
+                    }
+                    Err(e) => warn!("Failed to expire device {key}: {e}"),
+                }
+            }
+        }
+    }
+}
+
 pub fn now_millis() -> u64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -470,7 +733,70 @@ pub fn now_millis() -> u64 {
         .unwrap_or(0)
 }
 
-async fn current_workloads() -> Vec<WorkloadState> {
+/// Keep this device's entry in the reported-state bucket current, so its
+/// last known workload states stay readable while it is offline.
+pub async fn report_workloads(
+    kv: Arc<Mutex<KvStore>>,
+    device_id: String,
+    systemd_dir: std::path::PathBuf,
+    reporter: StateReporter,
+    interval_secs: u64,
+    pulls: ImagePulls,
+) -> Result<()> {
+    let mut ticker = tokio::time::interval(Duration::from_secs(interval_secs));
+    loop {
+        ticker.tick().await;
+
+        let workloads = device_workloads(&kv, &device_id, &systemd_dir, &pulls).await;
+        if let Err(err) = reporter.report(workloads).await {
+            warn!("Unable to report workload state: {err}");
+        }
+    }
+}
+
+/// Observed state of this device's workloads, matched against their
+/// desired specs in KV.
+async fn device_workloads(
+    kv: &Arc<Mutex<KvStore>>,
+    device_id: &str,
+    systemd_dir: &std::path::Path,
+    pulls: &ImagePulls,
+) -> Vec<WorkloadState> {
+    let desired = match desired_workloads(kv, device_id).await {
+        Ok(desired) => Some(desired),
+        Err(err) => {
+            warn!("Workload status: {err}");
+            None
+        }
+    };
+    current_workloads(desired.as_ref(), systemd_dir, pulls).await
+}
+
+/// Every avena unit systemd knows about, plus desired workloads that have
+/// no unit yet. Without `desired`, specs and sync state are left unknown.
+async fn current_workloads(
+    desired: Option<&BTreeMap<String, WorkloadSpec>>,
+    systemd_dir: &std::path::Path,
+    pulls: &ImagePulls,
+) -> Vec<WorkloadState> {
+    let mut pending: BTreeMap<String, WorkloadDeployment> = BTreeMap::new();
+    for (name, spec) in desired.into_iter().flatten() {
+        let name = workload::unit_stem(name);
+        // Creds paths are only known to reconcile; compare with the one
+        // deployed so a workload that wants creds and has them is in sync
+        let creds = match WorkloadCreds::wanted(&spec.perms) {
+            true => workload::deployed_creds(systemd_dir, &name).await,
+            false => None,
+        };
+        let deployment = WorkloadDeployment {
+            name: name.clone(),
+            spec: spec.clone(),
+            creds,
+            digest: workload::deployed_digest(systemd_dir, &name, &workload::image_ref(spec)).await,
+        };
+        pending.insert(name, deployment);
+    }
+
     let conn = match Connection::session().await {
         Ok(c) => c,
         Err(_) => return vec![],
@@ -487,27 +813,156 @@ async fn current_workloads() -> Vec<WorkloadState> {
                 continue;
             }
             let name = unit.name.trim_end_matches(".service").to_string();
+            let deployment = pending.remove(&name);
+            // Quadlet's volume units are not workloads
+            if deployment.is_none() && name.ends_with("-volume") {
+                continue;
+            }
             let state = match unit.active_state.as_str() {
                 "active" => WorkloadStatus::Running,
                 "inactive" => WorkloadStatus::Stopped,
                 "failed" => WorkloadStatus::Error,
                 _ => WorkloadStatus::Unknown,
             };
+            let running = state == WorkloadStatus::Running;
+            let inspect = match running {
+                true => workload::inspect_container(&name).await,
+                false => workload::ContainerInspect::default(),
+            };
+            let service = ServiceUnitProxy::builder(&conn)
+                .path(unit.object_path.clone())
+                .ok()
+                .map(|builder| builder.build());
+            let service = match service {
+                Some(service) => service.await.ok(),
+                None => None,
+            };
+            let (limits, usage) = match &service {
+                Some(service) => unit_resources(service, running).await,
+                None => (ResourceSpec::default(), None),
+            };
+            let runtime = match &service {
+                Some(service) => unit_runtime(service, running).await,
+                None => UnitRuntime::default(),
+            };
+            let sync = match (&deployment, desired) {
+                (_, None) => WorkloadSync::Unknown,
+                (None, Some(_)) => WorkloadSync::Orphaned,
+                (Some(deployment), Some(_)) => {
+                    match deployment.diff(systemd_dir).await {
+                        Ok(UnitChange::Unchanged) if running => WorkloadSync::InSync,
+                        _ => WorkloadSync::OutOfSync,
+                    }
+                }
+            };
 
+            let pull = pulls.get(&name);
+            let rollback = rollback::load_state(systemd_dir, &name).await.rollback;
             workloads.push(WorkloadState {
                 name,
                 state,
-                exit_code: None,
-                restart_count: 0,
-                started_at: None,
-                image: "unknown".to_string(),
+                exit_code: runtime.exit_code,
+                restart_count: runtime.restart_count,
+                started_at: runtime.started_at,
+                pid: runtime.pid,
+                image: deployment
+                    .as_ref()
+                    .map(|d| workload::image_ref(&d.spec))
+                    .unwrap_or_else(|| "unknown".to_string()),
+                health: inspect.health,
+                limits,
+                usage,
+                spec: deployment.map(|d| d.spec),
+                sync,
+                digest: inspect.digest,
+                pull,
+                rollback,
             });
         }
     }
 
+    // Desired but never deployed, e.g. held back by a dependency
+    for (name, deployment) in pending {
+        let pull = pulls.get(&name);
+        let rollback = rollback::load_state(systemd_dir, &name).await.rollback;
+        workloads.push(WorkloadState {
+            name,
+            state: WorkloadStatus::Stopped,
+            exit_code: None,
+            restart_count: 0,
+            started_at: None,
+            pid: None,
+            image: workload::image_ref(&deployment.spec),
+            health: None,
+            limits: ResourceSpec::default(),
+            usage: None,
+            spec: Some(deployment.spec),
+            sync: WorkloadSync::OutOfSync,
+            digest: None,
+            pull,
+            rollback,
+        });
+    }
+    workloads.sort_by(|a, b| a.name.cmp(&b.name));
+
     workloads
 }
 
+/// Runtime details systemd keeps for a unit's main process.
+#[derive(Default)]
+struct UnitRuntime {
+    exit_code: Option<i32>,
+    restart_count: u32,
+    started_at: Option<u64>,
+    pid: Option<u32>,
+}
+
+async fn unit_runtime(service: &ServiceUnitProxy<'_>, running: bool) -> UnitRuntime {
+    // systemd reports timestamps in epoch microseconds, 0 when unset
+    let timestamp = |value: zbus::Result<u64>| value.ok().filter(|t| *t != 0);
+    let exited = timestamp(service.exec_main_exit_timestamp().await).is_some();
+
+    UnitRuntime {
+        exit_code: match !running && exited {
+            true => service.exec_main_status().await.ok(),
+            false => None,
+        },
+        restart_count: service.n_restarts().await.unwrap_or(0),
+        started_at: timestamp(service.exec_main_start_timestamp().await).map(|t| t / 1000),
+        pid: match running {
+            true => service.exec_main_pid().await.ok().filter(|pid| *pid != 0),
+            false => None,
+        },
+    }
+}
+
+/// Limits in force on a unit and, while it runs, what it is using.
+async fn unit_resources(
+    service: &ServiceUnitProxy<'_>,
+    running: bool,
+) -> (ResourceSpec, Option<ResourceUsage>) {
+    // systemd reports unset limits and disabled accounting as u64::MAX
+    let set = |value: zbus::Result<u64>| value.ok().filter(|v| *v != u64::MAX);
+    let limits = ResourceSpec {
+        memory_max: set(service.memory_max().await),
+        cpu_quota_percent: set(service.cpu_quota_per_sec_usec().await)
+            .map(|usec| (usec / 10_000) as u32),
+        cpu_weight: set(service.cpu_weight().await).map(|w| w as u32),
+        pids_max: set(service.tasks_max().await),
+        io_weight: set(service.io_weight().await).map(|w| w as u32),
+    };
+    let usage = if running {
+        Some(ResourceUsage {
+            memory_bytes: set(service.memory_current().await),
+            cpu_ns: set(service.cpu_usage_n_sec().await),
+        })
+    } else {
+        None
+    };
+
+    (limits, usage)
+}
+
 pub async fn reconcile_leaves(
     kv: &Arc<Mutex<KvStore>>,
     issuer_pub_key: &str,
@@ -532,80 +987,345 @@ pub async fn reconcile_leaves(
     Ok(())
 }
 
-pub async fn reconcile_workloads(
+/// Desired workload specs for this device from KV, plus the workloads every
+/// device runs.
+pub async fn desired_workloads(
     kv: &Arc<Mutex<KvStore>>,
     device_id: &str,
-    systemd_dir: &std::path::Path,
-) -> Result<()> {
+) -> Result<BTreeMap<String, WorkloadSpec>> {
     let prefix = format!("device/{device_id}/");
     let guard = kv.lock().await;
     let mut keys = match tokio::time::timeout(Duration::from_secs(5), guard.keys()).await {
         Ok(Ok(k)) => k,
-        Ok(Err(err)) => {
-            warn!("Workload reconcile: unable to list KV keys: {err:?}");
-            return Ok(());
-        }
-        Err(_) => {
-            warn!("Workload reconcile: list KV keys timed out");
-            return Ok(());
-        }
+        Ok(Err(err)) => return Err(eyre!("unable to list KV keys: {err:?}")),
+        Err(_) => return Err(eyre!("list KV keys timed out")),
     };
-    info!("Workload reconcile: scanning KV with prefix {prefix}");
-    let mut desired: HashMap<String, WorkloadSpec> = HashMap::new();
+    let mut desired: BTreeMap<String, WorkloadSpec> = BTreeMap::new();
     while let Some(key) = tokio::time::timeout(Duration::from_secs(2), keys.next()).await.unwrap_or(None) {
         let key = key?;
         if !key.starts_with(&prefix) {
             continue;
         }
         if let Some(val) = guard.get(&key).await? {
-            if let Ok(entry) = serde_json::from_slice::<WorkloadDesiredState>(val.as_ref()) {
+            if let Ok(entry) = WorkloadDesiredState::try_from(val.as_ref()) {
                 desired.insert(entry.name, entry.spec);
             }
         }
     }
+    drop(guard);
     for req in required_workloads() {
         desired.entry(req.name.clone()).or_insert(req.spec);
     }
+    Ok(desired)
+}
+
+pub async fn reconcile_workloads(
+    kv: &Arc<Mutex<KvStore>>,
+    device_id: &str,
+    systemd_dir: &std::path::Path,
+    creds: Option<&WorkloadCreds>,
+    pulls: &ImagePulls,
+    trials: &mut BTreeMap<String, Trial>,
+    events: &EventLog,
+) -> Result<ReconcilePlan> {
+    let mut desired = match desired_workloads(kv, device_id).await {
+        Ok(desired) => desired,
+        Err(err) => {
+            warn!("Workload reconcile: {err}");
+            return Ok(ReconcilePlan::default());
+        }
+    };
     info!("Workload reconcile: desired entries {}", desired.len());
-    drop(guard);
 
-    let conn = Connection::session().await?;
-    let manager = Systemd1ManagerProxy::new(&conn).await?;
+    // Workloads held back by their dependencies keep whatever is deployed
+    let (order, rejected) = workload::resolve_dependencies(&desired);
+    let volumes_in_use: HashSet<String> = desired
+        .values()
+        .flat_map(|spec| spec.volumes.iter().map(|volume| volume.name.clone()))
+        .collect();
+    let units: HashSet<String> = desired.keys().map(|name| workload::unit_stem(name)).collect();
+    pulls.retain(&units);
+    trials.retain(|name, _| units.contains(name));
+    let mut plan = ReconcilePlan::default();
+    for (name, err) in rejected {
+        error!("Workload reconcile: not deploying {name}: {err}");
+        record_event(
+            events,
+            EventKind::WorkloadRejected,
+            None,
+            serde_json::json!({ "workload": name, "reason": err.to_string() }),
+        )
+        .await;
+        plan.rejected
+            .push(format!("{}.service", workload::unit_stem(&name)));
+    }
 
-    // Deploy/update desired workloads
-    let mut active: HashSet<String> = HashSet::new();
-    for (name, spec) in desired {
-        let unit_name = if name.starts_with("avena-") {
-            name.clone()
-        } else {
-            format!("avena-{name}")
+    // Only write the quadlets whose rendered content differs from disk,
+    // dependencies first
+    let mut changed = Vec::new();
+    let mut unchanged = Vec::new();
+    let mut with_creds = HashSet::new();
+    let mut revoked = false;
+    for name in order {
+        let Some(spec) = desired.remove(&name) else {
+            continue;
         };
-        let deployment = workload::WorkloadDeployment {
-            name: unit_name.clone(),
-            spec: spec.clone(),
+        let mut deployment = workload::WorkloadDeployment {
+            name: workload::unit_stem(&name),
+            spec,
+            creds: None,
+            digest: None,
         };
-        deployment.deploy(systemd_dir).await?;
+        let creds = creds.filter(|_| WorkloadCreds::wanted(&deployment.spec.perms));
+        if let Some(creds) = creds {
+            deployment.creds = Some(creds.path(&deployment.name));
+        }
+        // An update that was rolled back is held back until the desired spec
+        // changes
+        let mut state = rollback::load_state(systemd_dir, &deployment.name).await;
+        if state.rolled_back(&deployment.spec) {
+            info!("Workload reconcile: holding back {name}, its update was rolled back");
+            plan.rejected.push(deployment.unit_name());
+            continue;
+        }
+        if state.rollback.take().is_some() {
+            if let Err(err) = rollback::save_state(systemd_dir, &deployment.name, &state).await {
+                warn!("Workload reconcile: unable to clear rollback of {name}: {err}");
+            }
+        }
+        // The pinned digest carries over for as long as the image is the same
+        let image = workload::image_ref(&deployment.spec);
+        deployment.digest = workload::deployed_digest(systemd_dir, &deployment.name, &image).await;
+        let mut change = deployment.diff(systemd_dir).await?;
+
+        // Pull a new image in the background while the old container keeps
+        // running, so the restart once it is done only swaps it. A failed
+        // pull leaves it running
+        if change != UnitChange::Unchanged && deployment.digest.is_none() {
+            if let Some(digest) = pulls.take_pulled(&deployment.name, &image) {
+                info!("Workload reconcile: pulled {image} as {digest}");
+                deployment.digest = Some(digest);
+                change = deployment.diff(systemd_dir).await?;
+            } else {
+                if let Some(err) = pulls.take_failure(&deployment.name, &image) {
+                    error!("Workload reconcile: not deploying {name}: {err}");
+                    record_event(
+                        events,
+                        EventKind::WorkloadRejected,
+                        None,
+                        serde_json::json!({
+                            "workload": name,
+                            "reason": format!("image pull failed: {err}"),
+                        }),
+                    )
+                    .await;
+                } else {
+                    info!("Workload reconcile: pulling {image} for {name}");
+                    pulls.start(&deployment.name, &image);
+                }
+                plan.rejected.push(deployment.unit_name());
+                continue;
+            }
+        }
+        plan.record(deployment.unit_name(), change);
+
+        // A redeployed workload gets a fresh user, so creds copied out of
+        // its old container stop working
+        if let Some(creds) = creds {
+            let rotate = change != UnitChange::Unchanged;
+            match creds.issue(&deployment.name, &deployment.spec.perms, rotate).await {
+                Ok(CredsChange::Unchanged) => {}
+                Ok(CredsChange::Issued) => info!("Workload reconcile: issued NATS creds for {name}"),
+                Ok(CredsChange::Renewed) => info!("Workload reconcile: renewed NATS creds for {name}"),
+                Ok(CredsChange::Rotated) => {
+                    info!("Workload reconcile: rotated NATS creds for {name}");
+                    revoked = true;
+                }
+                Err(err) => warn!("Workload reconcile: unable to issue NATS creds for {name}: {err}"),
+            }
+            with_creds.insert(deployment.name.clone());
+        }
+
+        // Watch updates through their stabilization period, and workloads
+        // deployed before they had a known-good deployment too
+        let files = deployment.render()?;
+        let known_good = state.known_good.is_some_and(|good| good.files == files);
+        match rollback::stabilize_period(&deployment.spec) {
+            Some(period)
+                if change != UnitChange::Unchanged
+                    || (!known_good && !trials.contains_key(&deployment.name)) =>
+            {
+                let trial = Trial::new(
+                    name.clone(),
+                    deployment.spec.clone(),
+                    files,
+                    period,
+                    Instant::now(),
+                );
+                trials.insert(deployment.name.clone(), trial);
+            }
+            Some(_) => {}
+            None => {
+                trials.remove(&deployment.name);
+            }
+        }
+
+        if change != UnitChange::Unchanged {
+            deployment.deploy(systemd_dir).await?;
+            changed.push((name, deployment, change));
+        } else {
+            unchanged.push(deployment);
+        }
+    }
+
+    let conn = Connection::session().await?;
+    let manager = Systemd1ManagerProxy::new(&conn).await?;
+    if plan.needs_reload() {
         manager.reload().await?;
-        let _ = manager.restart_unit(&format!("{unit_name}.service"), "replace").await;
-        active.insert(format!("{unit_name}.service"));
-        info!("Workload reconcile: deployed {unit_name}");
     }
 
-    // Stop workloads no longer desired
+    for (name, deployment, change) in changed {
+        let unit = deployment.unit_name();
+        let result = match change {
+            UnitChange::Created => manager.start_unit(&unit, "replace").await,
+            _ => manager.restart_unit(&unit, "replace").await,
+        };
+        if let Err(err) = result {
+            warn!("Workload reconcile: unable to start {unit}: {err}");
+        }
+        info!("Workload reconcile: {change} {unit}");
+        record_event(
+            events,
+            EventKind::WorkloadDeployed,
+            None,
+            serde_json::json!({
+                "workload": name,
+                "change": change.to_string(),
+                "spec": deployment.spec,
+            }),
+        )
+        .await;
+    }
+
+    // systemd restarts dependents along with a dependency, but does not
+    // start them again once a missing dependency comes back
+    for deployment in unchanged {
+        let waiting = deployment.spec.depends_on.iter().any(|dependency| {
+            let unit = format!("{}.service", workload::unit_stem(&dependency.workload));
+            plan.created.contains(&unit)
+        });
+        if waiting {
+            let unit = deployment.unit_name();
+            if let Err(err) = manager.start_unit(&unit, "replace").await {
+                warn!("Workload reconcile: unable to start {unit}: {err}");
+            }
+        }
+    }
+
+    // Stop workloads no longer desired, then delete their quadlets so the
+    // units do not come back on the next reload
+    let stale = workload::removed_workloads(systemd_dir, &plan).await?;
+    let mut stopped: BTreeSet<String> = stale.iter().map(|w| w.unit_name()).collect();
     if let Ok(units) = manager.list_units().await {
         for unit in units {
             if unit.name.starts_with("avena-")
                 && unit.name.ends_with(".service")
-                && !active.contains(&unit.name)
+                && unit.active_state != "inactive"
+                && !plan.is_desired(&unit.name)
                 && !is_required_unit(&unit.name)
             {
-                let _ = manager.stop_unit(&unit.name, "replace").await;
-                info!("Workload reconcile: stopped {}", unit.name);
+                stopped.insert(unit.name);
             }
         }
     }
+    for unit in &stopped {
+        if let Err(err) = manager.stop_unit(unit, "replace").await {
+            // Units whose quadlet was never loaded are not an error
+            info!("Workload reconcile: unable to stop {unit}: {err}");
+        } else {
+            info!("Workload reconcile: stopped {unit}");
+        }
+    }
 
-    Ok(())
+    let mut cleanups = BTreeMap::new();
+    for removed in &stale {
+        let cleanup = match removed.remove_files(systemd_dir, &volumes_in_use).await {
+            Ok(cleanup) => cleanup,
+            Err(err) => {
+                warn!("Workload reconcile: unable to remove {}: {err}", removed.name);
+                continue;
+            }
+        };
+        for volume in cleanup.retained.iter().chain(&cleanup.purged) {
+            let _ = manager
+                .stop_unit(&format!("{volume}-volume.service"), "replace")
+                .await;
+        }
+        if let Err(err) = rollback::forget(systemd_dir, &removed.name).await {
+            warn!("Workload reconcile: unable to remove rollback state of {}: {err}", removed.name);
+        }
+        cleanups.insert(removed.unit_name(), cleanup);
+    }
+    if cleanups.values().any(|cleanup| !cleanup.files.is_empty()) {
+        manager.reload().await?;
+    }
+
+    for unit in stopped {
+        let payload = match cleanups.remove(&unit) {
+            Some(cleanup) => {
+                let (mut purged, mut failed) = (vec![], vec![]);
+                for volume in cleanup.purged {
+                    match workload::purge_volume(&volume).await {
+                        Ok(()) => purged.push(volume),
+                        Err(err) => {
+                            warn!("Workload reconcile: {err}");
+                            failed.push(volume);
+                        }
+                    }
+                }
+                info!(
+                    "Workload reconcile: removed {unit}, deleted {}",
+                    cleanup.files.join(", ")
+                );
+                serde_json::json!({
+                    "unit": unit,
+                    "files": cleanup.files,
+                    "volumes_retained": cleanup.retained,
+                    "volumes_purged": purged,
+                    "volumes_failed": failed,
+                })
+            }
+            None => serde_json::json!({ "unit": unit }),
+        };
+        record_event(events, EventKind::WorkloadRemoved, None, payload).await;
+        plan.removed.push(unit);
+    }
+
+    // Revoke the users of workloads that are gone or no longer want one.
+    // Workloads held back keep theirs along with the rest of their deployment
+    if let Some(creds) = creds {
+        for name in creds.holders().await? {
+            if with_creds.contains(&name) || plan.rejected.contains(&format!("{name}.service")) {
+                continue;
+            }
+            match creds.revoke(&name).await {
+                Ok(true) => {
+                    info!("Workload reconcile: revoked NATS creds for {name}");
+                    revoked = true;
+                }
+                Ok(false) => {}
+                Err(err) => warn!("Workload reconcile: unable to revoke NATS creds for {name}: {err}"),
+            }
+        }
+        if revoked {
+            if let Err(err) = creds.publish_revocations().await {
+                warn!("Workload reconcile: unable to publish revoked NATS users: {err}");
+            }
+        }
+    }
+
+    info!("Workload reconcile: {plan}");
+    Ok(plan)
 }
 
 pub fn required_workloads() -> Vec<WorkloadDeployment> {
@@ -618,6 +1338,8 @@ pub fn required_workloads() -> Vec<WorkloadDeployment> {
 
     vec![WorkloadDeployment {
         name: "avena-nats".to_string(),
+        creds: None,
+        digest: None,
         spec: WorkloadSpec {
             image: "docker.io/library/nats".to_string(),
             tag: Some("2.12.2".to_string()),
@@ -645,7 +1367,18 @@ pub fn required_workloads() -> Vec<WorkloadDeployment> {
                 container: 4222,
                 host: 4222,
             }],
-            volumes: vec!["avena-nats-js".to_string()],
+            volumes: vec![VolumeSpec {
+                name: "avena-nats-js".to_string(),
+                path: "/data".to_string(),
+                readonly: false,
+            }],
+            health: None,
+            resources: ResourceSpec::default(),
+            depends_on: vec![],
+            volume_retention: VolumeRetention::default(),
+            secrets: vec![],
+            // Ships with avenad, so there is no other version to roll back to
+            stabilize_secs: Some(0),
         },
     }]
 }
@@ -658,6 +1391,9 @@ pub async fn observe_workloads(
     kv: Arc<Mutex<KvStore>>,
     device_id: String,
     systemd_dir: std::path::PathBuf,
+    creds: Option<Arc<WorkloadCreds>>,
+    pulls: ImagePulls,
+    events: EventLog,
 ) -> Result<()> {
     let prefix = format!("device/{device_id}/");
     let pattern = format!("{prefix}>");
@@ -666,16 +1402,300 @@ pub async fn observe_workloads(
         guard.watch(pattern).await?
     };
 
-    while let Some(_update) = watcher.next().await {
-        info!("Workload watch: change detected");
-        if let Err(err) = reconcile_workloads(&kv, &device_id, &systemd_dir).await {
+    // Without any change creds still need renewing before they expire
+    let mut renew = tokio::time::interval_at(
+        tokio::time::Instant::now() + RENEW_INTERVAL,
+        RENEW_INTERVAL,
+    );
+    let mut trials = BTreeMap::new();
+    let mut retry_at = tokio::time::Instant::now() + PULL_RETRY;
+    loop {
+        // Failed pulls are tried again even if nothing changes in KV
+        let retry = pulls.any_failed();
+        let reconcile = tokio::select! {
+            update = watcher.next() => {
+                if update.is_none() {
+                    break;
+                }
+                info!("Workload watch: change detected");
+                true
+            }
+            _ = renew.tick(), if creds.is_some() => true,
+            _ = pulls.finished() => {
+                info!("Workload watch: image pull finished");
+                true
+            }
+            _ = tokio::time::sleep_until(retry_at), if retry => {
+                info!("Workload watch: retrying failed image pulls");
+                true
+            }
+            _ = tokio::time::sleep(rollback::CHECK_INTERVAL), if !trials.is_empty() => false,
+        };
+
+        if !reconcile {
+            let result =
+                check_rollouts(&mut trials, &systemd_dir, creds.as_deref(), &events).await;
+            if let Err(err) = result {
+                error!("Workload rollout check error: {err:?}");
+            }
+            continue;
+        }
+        let result = reconcile_workloads(
+            &kv,
+            &device_id,
+            &systemd_dir,
+            creds.as_deref(),
+            &pulls,
+            &mut trials,
+            &events,
+        )
+        .await;
+        if let Err(err) = result {
             error!("Workload reconcile error: {err:?}");
         }
+        retry_at = tokio::time::Instant::now() + PULL_RETRY;
     }
 
     Ok(())
 }
 
+/// Check the workloads in their stabilization period, keeping the ones that
+/// stayed up as known-good and rolling back the ones that failed.
+pub async fn check_rollouts(
+    trials: &mut BTreeMap<String, Trial>,
+    systemd_dir: &std::path::Path,
+    creds: Option<&WorkloadCreds>,
+    events: &EventLog,
+) -> Result<()> {
+    if trials.is_empty() {
+        return Ok(());
+    }
+    let conn = Connection::session().await?;
+    let manager = Systemd1ManagerProxy::new(&conn).await?;
+    let units: Vec<String> = trials.keys().map(|name| format!("{name}.service")).collect();
+    let listings = manager
+        .list_units_by_names(units.iter().map(String::as_str).collect())
+        .await?;
+
+    let now = Instant::now();
+    let mut verdicts = vec![];
+    for unit in listings {
+        let name = unit.name.trim_end_matches(".service").to_string();
+        let Some(trial) = trials.get_mut(&name) else {
+            continue;
+        };
+        let service = ServiceUnitProxy::builder(&conn)
+            .path(unit.object_path.clone())?
+            .build()
+            .await?;
+        let health = match unit.active_state == "active" {
+            true => workload::inspect_container(&name).await.health,
+            false => None,
+        };
+        let check = UnitCheck {
+            active_state: unit.active_state,
+            restarts: service.n_restarts().await.unwrap_or(0),
+            health,
+        };
+        match trial.judge(&check, now) {
+            Verdict::Pending => {}
+            verdict => verdicts.push((name, verdict)),
+        }
+    }
+
+    for (name, verdict) in verdicts {
+        let Some(trial) = trials.remove(&name) else {
+            continue;
+        };
+        match verdict {
+            Verdict::Stable => {
+                let mut state = rollback::load_state(systemd_dir, &name).await;
+                state.known_good = Some(KnownGood {
+                    spec: trial.spec,
+                    files: trial.files,
+                    since: now_millis(),
+                });
+                match rollback::save_state(systemd_dir, &name, &state).await {
+                    Ok(()) => info!("Workload rollout: {name} is stable, keeping it as known-good"),
+                    Err(err) => warn!("Workload rollout: unable to keep {name} as known-good: {err}"),
+                }
+            }
+            Verdict::Failed(reason) => {
+                let result =
+                    roll_back(&manager, systemd_dir, &name, trial, reason, creds, events).await;
+                if let Err(err) = result {
+                    error!("Workload rollout: unable to roll {name} back: {err}");
+                }
+            }
+            Verdict::Abandoned => info!("Workload rollout: {name} stopped, no longer watching it"),
+            Verdict::Pending => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Put the known-good deployment of the workload `name` back in place of
+/// the update `trial` watched, and record why.
+async fn roll_back(
+    manager: &Systemd1ManagerProxy<'_>,
+    systemd_dir: &std::path::Path,
+    name: &str,
+    trial: Trial,
+    reason: String,
+    creds: Option<&WorkloadCreds>,
+    events: &EventLog,
+) -> Result<()> {
+    let mut state = rollback::load_state(systemd_dir, name).await;
+    let Some(good) = state.known_good.clone().filter(|good| good.files != trial.files) else {
+        warn!("Workload rollout: {name} {reason}, with no known-good deployment to roll back to");
+        return Ok(());
+    };
+
+    for file in &good.files {
+        fs::write(systemd_dir.join(&file.file_name), &file.contents).await?;
+    }
+    // Files only the update rendered, e.g. a volume it added, would
+    // otherwise outlive it
+    for file in &trial.files {
+        if good.files.iter().any(|g| g.file_name == file.file_name) {
+            continue;
+        }
+        match fs::remove_file(systemd_dir.join(&file.file_name)).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+    }
+    // The restored quadlet mounts the same creds file, which must carry the
+    // known-good permissions again
+    if let Some(creds) = creds.filter(|_| WorkloadCreds::wanted(&good.spec.perms)) {
+        match creds.issue(name, &good.spec.perms, true).await {
+            Ok(CredsChange::Rotated) => {
+                if let Err(err) = creds.publish_revocations().await {
+                    warn!("Workload rollout: unable to publish revoked NATS users: {err}");
+                }
+            }
+            Ok(_) => {}
+            Err(err) => warn!("Workload rollout: unable to issue NATS creds for {name}: {err}"),
+        }
+    }
+    manager.reload().await?;
+    let unit = format!("{name}.service");
+    if let Err(err) = manager.restart_unit(&unit, "replace").await {
+        warn!("Workload rollout: unable to restart {unit}: {err}");
+    }
+    warn!("Workload rollout: rolled {name} back, {reason}");
+
+    let payload = serde_json::json!({
+        "workload": trial.workload,
+        "reason": reason,
+        "spec": trial.spec,
+        "restored": good.spec,
+    });
+    let kind = EventKind::WorkloadRolledBack;
+    let timestamp = match events.record(kind, None, payload).await {
+        Ok(event) => event.timestamp,
+        Err(err) => {
+            warn!("Unable to record {kind} event: {err}");
+            events.tick()
+        }
+    };
+    state.rollback = Some(WorkloadRollback {
+        timestamp,
+        reason,
+        failed_spec: trial.spec,
+    });
+    rollback::save_state(systemd_dir, name, &state).await
+}
+
+/// Keep podman's secrets in line with the ones sealed to this device in
+/// `bucket`, restarting the workloads that use a created or changed secret
+/// so they see its value.
+pub async fn observe_secrets(
+    bucket: KvStore,
+    kv: Arc<Mutex<KvStore>>,
+    identity: DeviceIdentity,
+    events: EventLog,
+) -> Result<()> {
+    let mut watcher = avena::secrets::watch_device_secrets(&bucket, &identity.id).await?;
+    apply_secrets(&bucket, &kv, &identity, &events).await;
+
+    while let Some(_update) = watcher.next().await {
+        info!("Secrets watch: change detected");
+        apply_secrets(&bucket, &kv, &identity, &events).await;
+    }
+
+    Ok(())
+}
+
+async fn apply_secrets(
+    bucket: &KvStore,
+    kv: &Arc<Mutex<KvStore>>,
+    identity: &DeviceIdentity,
+    events: &EventLog,
+) {
+    let changes = match secrets::sync_secrets(bucket, identity).await {
+        Ok(changes) => changes,
+        Err(err) => {
+            warn!("Secrets sync: {err}");
+            return;
+        }
+    };
+    for (name, change) in &changes {
+        info!("Secrets sync: {change} {name}");
+        record_event(
+            events,
+            EventKind::SecretChanged,
+            None,
+            serde_json::json!({ "secret": name, "change": change.to_string() }),
+        )
+        .await;
+    }
+
+    // A removed secret stays in the containers that already have it
+    let refreshed: HashSet<&String> = changes
+        .iter()
+        .filter(|(_, change)| **change != SecretChange::Removed)
+        .map(|(name, _)| name)
+        .collect();
+    if refreshed.is_empty() {
+        return;
+    }
+    let desired = match desired_workloads(kv, &identity.id).await {
+        Ok(desired) => desired,
+        Err(err) => {
+            warn!("Secrets sync: {err}");
+            return;
+        }
+    };
+    let units: Vec<String> = desired
+        .iter()
+        .filter(|(_, spec)| spec.secrets.iter().any(|s| refreshed.contains(&s.name)))
+        .map(|(name, _)| format!("{}.service", workload::unit_stem(name)))
+        .collect();
+    if units.is_empty() {
+        return;
+    }
+
+    let manager = match Connection::session().await {
+        Ok(conn) => Systemd1ManagerProxy::new(&conn).await,
+        Err(err) => Err(err),
+    };
+    let manager = match manager {
+        Ok(manager) => manager,
+        Err(err) => {
+            warn!("Secrets sync: unable to reach systemd: {err}");
+            return;
+        }
+    };
+    for unit in units {
+        match manager.restart_unit(&unit, "replace").await {
+            Ok(_) => info!("Secrets sync: restarted {unit}"),
+            Err(err) => warn!("Secrets sync: unable to restart {unit}: {err}"),
+        }
+    }
+}
+
 pub async fn render_nats_conf(_issuer_pub_key: &str, remotes: Vec<(String, String)>) -> Result<()> {
     let nats_cfg_dir = directories::ProjectDirs::from("", "", "avena")
         .map(|d| d.config_dir().join("nats"))
@@ -1,5 +1,6 @@
 mod systemd;
 
+use std::path::Path;
 use std::time::Duration;
 
 use data_encoding::BASE64URL_NOPAD;
@@ -8,6 +9,8 @@ use jsonwebtoken::{decode_header, Algorithm, Validation};
 use serde::{Deserialize, Serialize};
 use tokio::fs;
 
+use avena::messages::{MountSpec, PermSpec, PortSpec, ResourceSpec, VolumeRetention, WorkloadSpec};
+use avenad::workload::WorkloadDeployment;
 use color_print::cprintln;
 use systemd::manager::{self, Systemd1ManagerProxy};
 
@@ -20,34 +23,6 @@ use askama::Template;
 
 use futures::StreamExt;
 
-#[derive(Template)]
-#[template(path = "podman/template.service", escape = "none")]
-struct PodmanServiceTemplate<'a> {
-    description: &'a str,
-    name: &'a str,
-    image: &'a str,
-    tag: &'a str,
-    exec: &'a str,
-    ports: Vec<PodmanServiceTemplatePort>,
-    bind_mounts: Vec<PodmanServiceTemplateBindMount<'a>>,
-    volume_mounts: Vec<PodmanServiceTemplateVolumeMount<'a>>,
-}
-
-struct PodmanServiceTemplatePort {
-    inside: u16,
-    outside: u16,
-}
-
-struct PodmanServiceTemplateBindMount<'a> {
-    source: &'a str,
-    path: &'a str,
-}
-
-struct PodmanServiceTemplateVolumeMount<'a> {
-    name: &'a str,
-    path: &'a str,
-}
-
 #[derive(Template)]
 #[template(path = "nats/server.conf", escape = "none")]
 struct NatsServerConfTemplate<'a> {
@@ -271,21 +246,35 @@ async fn connect_to_systemd<'a>() -> Result<Systemd1ManagerProxy<'a>> {
 
 async fn start_nats<'a>(systemd: Systemd1ManagerProxy<'a>, issuer_pub_key: &'a str) -> Result<()> {
     // FIXME: Move to transient service?
-    let nats_service = PodmanServiceTemplate {
-        description: "Avena's node-local NATS",
-        name: "avena-nats",
-        image: "docker.io/library/nats",
-        tag: "2.10.20",
-        exec: "--config /server.conf",
-        ports: vec![PodmanServiceTemplatePort {
-            inside: 4222,
-            outside: 4222,
-        }],
-        bind_mounts: vec![PodmanServiceTemplateBindMount {
-            source: "./server.conf",
-            path: "/server.conf",
-        }],
-        volume_mounts: vec![],
+    let nats_service = WorkloadDeployment {
+        name: "avena-nats".to_string(),
+        creds: None,
+        digest: None,
+        spec: WorkloadSpec {
+            image: "docker.io/library/nats".to_string(),
+            tag: Some("2.10.20".to_string()),
+            cmd: Some("--config /server.conf".to_string()),
+            args: vec![],
+            env: vec![],
+            mounts: vec![MountSpec {
+                host: "./server.conf".to_string(),
+                container: "/server.conf".to_string(),
+                readonly: false,
+            }],
+            devices: vec![],
+            perms: PermSpec::default(),
+            ports: vec![PortSpec {
+                container: 4222,
+                host: 4222,
+            }],
+            volumes: vec![],
+            health: None,
+            resources: ResourceSpec::default(),
+            depends_on: vec![],
+            volume_retention: VolumeRetention::default(),
+            secrets: vec![],
+            stabilize_secs: None,
+        },
     };
 
     cprintln!("<g>Before</g>");
@@ -297,16 +286,10 @@ async fn start_nats<'a>(systemd: Systemd1ManagerProxy<'a>, issuer_pub_key: &'a s
     }
 
     // FIXME: Change to /run/containers/systemd/* when podman > 5.2.2 is out
-    fs::create_dir_all("/etc/containers/systemd/nats").await?;
     println!("Create nats folder");
-    fs::write(
-        format!(
-            "/etc/containers/systemd/nats/{0}.container",
-            nats_service.name
-        ),
-        nats_service.render()?,
-    )
-    .await?;
+    nats_service
+        .deploy(Path::new("/etc/containers/systemd/nats"))
+        .await?;
 
     let nats_conf = NatsServerConfTemplate {
         hostname: "avena",
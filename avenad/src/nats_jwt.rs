@@ -3,6 +3,7 @@ use nkeys::KeyPair;
 use serde::{Deserialize, Serialize};
 use std::path::Path;
 use std::collections::HashMap;
+use std::time::Duration;
 use tokio::fs;
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -44,6 +45,10 @@ pub struct AccountNats {
     pub limits: Option<AccountLimits>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub default_permissions: Option<Permissions>,
+    /// Revoked user public keys, with the time (unix seconds) before which
+    /// their JWTs are no longer accepted.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub revocations: HashMap<String, i64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -95,6 +100,8 @@ pub struct PermissionRules {
 pub struct UserClaims {
     pub jti: String,
     pub iat: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exp: Option<i64>,
     pub iss: String,
     pub name: String,
     pub sub: String,
@@ -184,6 +191,18 @@ impl NatsJwtManager {
     }
 
     pub fn generate_account_jwt(&self, name: &str, account_kp: &KeyPair, enable_jetstream: bool) -> Result<String> {
+        self.generate_account_jwt_with_revocations(name, account_kp, enable_jetstream, &HashMap::new())
+    }
+
+    /// Like [`Self::generate_account_jwt`], but no longer accepting the users
+    /// in `revocations`.
+    pub fn generate_account_jwt_with_revocations(
+        &self,
+        name: &str,
+        account_kp: &KeyPair,
+        enable_jetstream: bool,
+        revocations: &HashMap<String, i64>,
+    ) -> Result<String> {
         let now = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)?
             .as_secs() as i64;
@@ -234,6 +253,7 @@ impl NatsJwtManager {
                 version: 2,
                 limits,
                 default_permissions: None,
+                revocations: revocations.clone(),
             },
         };
 
@@ -247,6 +267,19 @@ impl NatsJwtManager {
         name: &str,
         pub_allow: Vec<String>,
         sub_allow: Vec<String>,
+    ) -> Result<(String, KeyPair)> {
+        self.generate_user_jwt_with_expiry(account_kp, name, pub_allow, sub_allow, None)
+    }
+
+    /// Like [`NatsJwtManager::generate_user_jwt`], but the JWT stops being
+    /// accepted `ttl` after it was issued.
+    pub fn generate_user_jwt_with_expiry(
+        &self,
+        account_kp: &KeyPair,
+        name: &str,
+        pub_allow: Vec<String>,
+        sub_allow: Vec<String>,
+        ttl: Option<Duration>,
     ) -> Result<(String, KeyPair)> {
         let user_kp = KeyPair::new_user();
         let now = std::time::SystemTime::now()
@@ -259,6 +292,7 @@ impl NatsJwtManager {
         let claims = UserClaims {
             jti: uuid::Uuid::new_v4().to_string(),
             iat: now,
+            exp: ttl.map(|ttl| now + ttl.as_secs() as i64),
             iss: issuer,
             name: name.to_string(),
             sub: pubkey,
@@ -311,6 +345,40 @@ impl NatsJwtManager {
     }
 }
 
+const REVOCATIONS_FILE: &str = "AVENA.revocations.json";
+
+/// Users revoked from the AVENA account in `cfg_dir`, by public key, with the
+/// time (unix seconds) they were revoked.
+pub fn load_revocations(cfg_dir: &Path) -> Result<HashMap<String, i64>> {
+    let path = cfg_dir.join(REVOCATIONS_FILE);
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+    Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+}
+
+/// Revoke the AVENA account user `pubkey`. Takes effect once the account JWT
+/// is signed again.
+///
+/// Revoked users' JWTs are valid for at most `user_ttl`, so revocations
+/// older than that name users that expired anyway and are dropped, keeping
+/// the account JWT from growing with every rotation.
+pub fn revoke_user(cfg_dir: &Path, pubkey: &str, user_ttl: Duration) -> Result<()> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs() as i64;
+
+    let mut revocations = load_revocations(cfg_dir)?;
+    revocations.retain(|_, revoked_at| *revoked_at + user_ttl.as_secs() as i64 > now);
+    revocations.insert(pubkey.to_string(), now);
+    std::fs::create_dir_all(cfg_dir)?;
+    std::fs::write(
+        cfg_dir.join(REVOCATIONS_FILE),
+        serde_json::to_string_pretty(&revocations)?,
+    )?;
+    Ok(())
+}
+
 pub async fn setup_operator_mode(cfg_dir: &Path) -> Result<NatsJwtManager> {
     let mgr = NatsJwtManager::load_or_generate(cfg_dir)?;
 
@@ -347,7 +415,12 @@ pub async fn setup_operator_mode(cfg_dir: &Path) -> Result<NatsJwtManager> {
         fs::write(&avena_seed_path, kp.seed()?).await?;
         kp
     };
-    let avena_jwt = mgr.generate_account_jwt("AVENA", &avena_kp, true)?;
+    let avena_jwt = mgr.generate_account_jwt_with_revocations(
+        "AVENA",
+        &avena_kp,
+        true,
+        &load_revocations(cfg_dir)?,
+    )?;
     fs::write(cfg_dir.join("AVENA.jwt"), &avena_jwt).await?;
 
     let (avena_admin_jwt, avena_admin_kp) = mgr.generate_user_jwt(
@@ -0,0 +1,193 @@
+//! Streams a child process's output to a caller through a [`StreamSink`].
+
+use std::process::Stdio;
+use std::time::Duration;
+
+use avena::messages::OutputKind;
+use avena::streams::{StreamSink, IDLE_TIMEOUT, KEEPALIVE_INTERVAL, MAX_CHUNK_BYTES};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::process::{Child, ChildStdin, Command};
+use tracing::{debug, warn};
+
+/// How long output may sit in a buffer before it is sent as a partial chunk.
+const FLUSH_INTERVAL: Duration = Duration::from_millis(100);
+
+type Reader = Box<dyn AsyncRead + Send + Unpin>;
+
+/// Output of one stream of the child, waiting for credit to be sent.
+struct Pending {
+    kind: OutputKind,
+    reader: Option<Reader>,
+    buffer: Vec<u8>,
+}
+
+impl Pending {
+    fn new(kind: OutputKind, reader: Option<Reader>) -> Self {
+        Pending {
+            kind,
+            reader,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Whether more may be read without growing past one chunk.
+    fn wants_more(&self) -> bool {
+        self.reader.is_some() && self.buffer.len() < MAX_CHUNK_BYTES
+    }
+
+    /// Read what the child wrote next, closing the reader at its end.
+    ///
+    /// Cancel safe: nothing is lost if another branch wins the select.
+    async fn read(&mut self) {
+        let Some(reader) = self.reader.as_mut() else {
+            return;
+        };
+        let mut buf = [0; 8192];
+        match reader.read(&mut buf).await {
+            Ok(0) | Err(_) => self.reader = None,
+            Ok(n) => self.buffer.extend_from_slice(&buf[..n]),
+        }
+    }
+
+    /// Send as much of the buffer as the caller has credit for. A character
+    /// split across reads waits for its remaining bytes.
+    async fn flush(&mut self, sink: &mut StreamSink) -> Result<(), avena::Error> {
+        while !self.buffer.is_empty() && sink.has_credit() {
+            let end = self.buffer.len().min(MAX_CHUNK_BYTES);
+            let take = match std::str::from_utf8(&self.buffer[..end]) {
+                Err(e) if e.error_len().is_none() && self.reader.is_some() => e.valid_up_to(),
+                _ => end,
+            };
+            if take == 0 {
+                break;
+            }
+            let data = String::from_utf8_lossy(&self.buffer[..take]).into_owned();
+            self.buffer.drain(..take);
+            sink.send(self.kind, data).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Spawn `cmd` with its stdout and stderr piped, killed when dropped. Its
+/// stdin is piped too if `input` is set.
+pub fn spawn_piped(cmd: &mut Command, input: bool) -> std::io::Result<Child> {
+    cmd.stdin(if input { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+}
+
+/// Stream `child`'s stdout and stderr through `sink` until both close, then
+/// finish the stream with its exit status. Input the caller sends is written
+/// to the child's stdin, if it was piped.
+///
+/// Output is read only while the caller has credit, so a slow caller makes
+/// the child block on its pipes instead of buffering here. The child is
+/// killed if the caller cancels or stops sending keepalives, or once
+/// `timeout` has passed.
+pub async fn pump(mut sink: StreamSink, mut child: Child, timeout: Option<Duration>) {
+    let stdout = child.stdout.take().map(|out| Box::new(out) as Reader);
+    let stderr = child.stderr.take().map(|err| Box::new(err) as Reader);
+    let mut stdin = child.stdin.take();
+    let mut out = Pending::new(OutputKind::Stdout, stdout);
+    let mut err = Pending::new(OutputKind::Stderr, stderr);
+
+    let mut flush = tokio::time::interval(FLUSH_INTERVAL);
+    let mut keepalive = tokio::time::interval(KEEPALIVE_INTERVAL);
+    let deadline = async {
+        match timeout {
+            Some(timeout) => tokio::time::sleep(timeout).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::pin!(deadline);
+    let mut timed_out = false;
+
+    while out.reader.is_some() || err.reader.is_some() {
+        let credit = sink.has_credit();
+        let sent = tokio::select! {
+            control = sink.control() => {
+                let Some(control) = control else {
+                    debug!("Output stream cancelled");
+                    return;
+                };
+                if let Some(input) = control.input {
+                    write_input(&mut stdin, input.as_bytes()).await;
+                }
+                if control.close_input {
+                    stdin = None;
+                }
+                Ok(())
+            }
+            _ = out.read(), if credit && out.wants_more() => Ok(()),
+            _ = err.read(), if credit && err.wants_more() => Ok(()),
+            _ = flush.tick() => match out.flush(&mut sink).await {
+                Ok(()) => err.flush(&mut sink).await,
+                Err(e) => Err(e),
+            },
+            _ = keepalive.tick() => {
+                if sink.caller_gone() {
+                    debug!("Output stream caller went away");
+                    return;
+                }
+                sink.keepalive().await
+            }
+            _ = &mut deadline, if !timed_out => {
+                // Its pipes close once it is gone, ending the loop
+                timed_out = true;
+                let _ = child.start_kill();
+                Ok(())
+            }
+        };
+        if let Err(e) = sent {
+            warn!("Unable to stream output: {e}");
+            return;
+        }
+    }
+
+    // Drain what is left as the caller acknowledges it
+    loop {
+        if let Err(e) = out.flush(&mut sink).await {
+            warn!("Unable to stream output: {e}");
+            return;
+        }
+        if let Err(e) = err.flush(&mut sink).await {
+            warn!("Unable to stream output: {e}");
+            return;
+        }
+        if out.buffer.is_empty() && err.buffer.is_empty() {
+            break;
+        }
+        match tokio::time::timeout(IDLE_TIMEOUT, sink.control()).await {
+            Ok(Some(_)) => {}
+            _ => return,
+        }
+    }
+
+    let (exit_code, error) = match child.wait().await {
+        Ok(_) if timed_out => (
+            None,
+            Some(format!(
+                "timed out after {}s",
+                timeout.unwrap_or_default().as_secs()
+            )),
+        ),
+        Ok(status) => (status.code(), None),
+        Err(e) => (None, Some(e.to_string())),
+    };
+    if let Err(e) = sink.finish(exit_code, error).await {
+        warn!("Unable to finish output stream: {e}");
+    }
+}
+
+/// Write caller input to the child, dropping it if the child stopped reading.
+async fn write_input(stdin: &mut Option<ChildStdin>, input: &[u8]) {
+    let Some(pipe) = stdin.as_mut() else {
+        return;
+    };
+    if pipe.write_all(input).await.is_err() || pipe.flush().await.is_err() {
+        *stdin = None;
+    }
+}
@@ -0,0 +1,166 @@
+//! Rolling failed workload updates back to the last known-good deployment.
+//!
+//! Once a workload's unit is created or updated, reconcile watches it for the
+//! spec's stabilization period. If it stays up, and healthy when it has a
+//! health check, its spec and rendered quadlet files become its known-good
+//! deployment. If it fails, restarts or turns unhealthy first, the known-good
+//! files are written back and the unit restarted, and the spec rolled back
+//! from is held back until the desired spec changes.
+//!
+//! Each workload's known-good deployment and rollback in effect are kept as
+//! JSON in [`STATE_DIR`] under the quadlet directory, which quadlet ignores.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use avena::messages::{WorkloadHealth, WorkloadRollback, WorkloadSpec, DEFAULT_STABILIZE_SECS};
+use color_eyre::Result;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::workload::QuadletFile;
+
+/// Directory under the quadlet directory holding rollback state.
+pub const STATE_DIR: &str = "avena-rollback";
+
+/// How often workloads in their stabilization period are checked.
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// What a workload can be rolled back to, and the rollback in effect.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RollbackState {
+    #[serde(default)]
+    pub known_good: Option<KnownGood>,
+    #[serde(default)]
+    pub rollback: Option<WorkloadRollback>,
+}
+
+impl RollbackState {
+    /// Whether `spec` is the update the rollback in effect replaced.
+    pub fn rolled_back(&self, spec: &WorkloadSpec) -> bool {
+        self.rollback
+            .as_ref()
+            .is_some_and(|rollback| rollback.failed_spec == *spec)
+    }
+}
+
+/// A deployment that stayed up through its stabilization period.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KnownGood {
+    pub spec: WorkloadSpec,
+    pub files: Vec<QuadletFile>,
+    /// Epoch milliseconds it was found stable.
+    pub since: u64,
+}
+
+fn state_path(systemd_dir: &Path, name: &str) -> PathBuf {
+    systemd_dir.join(STATE_DIR).join(format!("{name}.json"))
+}
+
+/// The rollback state of the workload `name`, empty if it has none or it
+/// cannot be read.
+pub async fn load_state(systemd_dir: &Path, name: &str) -> RollbackState {
+    match fs::read(state_path(systemd_dir, name)).await {
+        Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_default(),
+        Err(_) => RollbackState::default(),
+    }
+}
+
+pub async fn save_state(systemd_dir: &Path, name: &str, state: &RollbackState) -> Result<()> {
+    fs::create_dir_all(systemd_dir.join(STATE_DIR)).await?;
+    fs::write(state_path(systemd_dir, name), serde_json::to_vec(state)?).await?;
+    Ok(())
+}
+
+/// Delete the rollback state of a removed workload.
+pub async fn forget(systemd_dir: &Path, name: &str) -> Result<()> {
+    match fs::remove_file(state_path(systemd_dir, name)).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// How long an update of `spec` is watched, `None` if it is never rolled
+/// back.
+pub fn stabilize_period(spec: &WorkloadSpec) -> Option<Duration> {
+    match spec.stabilize_secs.unwrap_or(DEFAULT_STABILIZE_SECS) {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    }
+}
+
+/// A unit's state at one check of its stabilization period.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnitCheck {
+    /// systemd's `ActiveState`.
+    pub active_state: String,
+    pub restarts: u32,
+    pub health: Option<WorkloadHealth>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Pending,
+    /// Up for the whole period; keep it as known-good.
+    Stable,
+    /// Roll back, for this reason.
+    Failed(String),
+    /// Stopped without failing, e.g. by hand; stop watching it.
+    Abandoned,
+}
+
+/// A deployment being watched through its stabilization period.
+#[derive(Debug, Clone)]
+pub struct Trial {
+    /// The workload's name in its desired state.
+    pub workload: String,
+    pub spec: WorkloadSpec,
+    pub files: Vec<QuadletFile>,
+    started: Instant,
+    period: Duration,
+    /// Restarts systemd had counted at the first check.
+    baseline: Option<u32>,
+}
+
+impl Trial {
+    pub fn new(
+        workload: String,
+        spec: WorkloadSpec,
+        files: Vec<QuadletFile>,
+        period: Duration,
+        started: Instant,
+    ) -> Self {
+        Trial {
+            workload,
+            spec,
+            files,
+            started,
+            period,
+            baseline: None,
+        }
+    }
+
+    pub fn judge(&mut self, check: &UnitCheck, now: Instant) -> Verdict {
+        match check.active_state.as_str() {
+            "failed" => return Verdict::Failed("unit failed".to_string()),
+            "inactive" => return Verdict::Abandoned,
+            _ => {}
+        }
+        let baseline = *self.baseline.get_or_insert(check.restarts);
+        if check.restarts > baseline {
+            let restarts = check.restarts - baseline;
+            return Verdict::Failed(format!("unit restarted {restarts} times"));
+        }
+        if check.health == Some(WorkloadHealth::Unhealthy) {
+            return Verdict::Failed("health check failed".to_string());
+        }
+
+        // A health check still starting keeps the trial going
+        let up = check.active_state == "active"
+            && matches!(check.health, None | Some(WorkloadHealth::Healthy));
+        match now.duration_since(self.started) >= self.period && up {
+            true => Verdict::Stable,
+            false => Verdict::Pending,
+        }
+    }
+}
@@ -0,0 +1,186 @@
+//! Podman secrets for workloads, opened from the `avena_secrets` bucket.
+//!
+//! Each secret sealed to this device becomes the podman secret
+//! `avena-secret-{name}`, labelled with a hash of its ciphertext so it is
+//! only written again when its entry changes. Workloads reference secrets by
+//! name in their spec; see [`crate::workload`] for how they are rendered.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::process::Stdio;
+
+use async_nats::jetstream::kv::Store as KvStore;
+use avena::messages::SealedSecret;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tracing::warn;
+
+use crate::device::DeviceIdentity;
+use crate::workload::content_hash;
+
+const SECRET_PREFIX: &str = "avena-secret-";
+const HASH_LABEL: &str = "avena.hash";
+
+/// The podman secret holding the device secret `name`.
+pub fn podman_secret(name: &str) -> String {
+    format!("{SECRET_PREFIX}{name}")
+}
+
+/// What [`sync_secrets`] did to one podman secret.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecretChange {
+    Created,
+    Updated,
+    Removed,
+}
+
+impl fmt::Display for SecretChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SecretChange::Created => "created",
+            SecretChange::Updated => "updated",
+            SecretChange::Removed => "removed",
+        })
+    }
+}
+
+/// Hash a sealed secret is labelled with once written to podman.
+pub fn sealed_hash(sealed: &SealedSecret) -> String {
+    content_hash(sealed.ciphertext.as_bytes())
+}
+
+/// The changes that turn the podman secrets in `existing` into `desired`,
+/// both mapping secret names to hashes.
+pub fn secret_changes(
+    desired: &BTreeMap<String, String>,
+    existing: &BTreeMap<String, String>,
+) -> BTreeMap<String, SecretChange> {
+    let mut changes = BTreeMap::new();
+    for (name, hash) in desired {
+        match existing.get(name) {
+            Some(current) if current == hash => {}
+            Some(_) => {
+                changes.insert(name.clone(), SecretChange::Updated);
+            }
+            None => {
+                changes.insert(name.clone(), SecretChange::Created);
+            }
+        }
+    }
+    for name in existing.keys() {
+        if !desired.contains_key(name) {
+            changes.insert(name.clone(), SecretChange::Removed);
+        }
+    }
+    changes
+}
+
+/// Bring podman's secrets in line with the ones sealed to `identity` in
+/// `kv`, returning what changed. A secret that cannot be opened is left as
+/// it is.
+pub async fn sync_secrets(
+    kv: &KvStore,
+    identity: &DeviceIdentity,
+) -> Result<BTreeMap<String, SecretChange>> {
+    let sealed: BTreeMap<String, SealedSecret> = avena::secrets::device_secrets(kv, &identity.id)
+        .await?
+        .into_iter()
+        .map(|secret| (secret.name.clone(), secret))
+        .collect();
+    let desired = sealed
+        .iter()
+        .map(|(name, secret)| (name.clone(), sealed_hash(secret)))
+        .collect();
+    let existing = podman_secrets().await?;
+
+    let mut applied = BTreeMap::new();
+    for (name, change) in secret_changes(&desired, &existing) {
+        let result = match change {
+            SecretChange::Removed => remove_secret(&name).await,
+            _ => {
+                let secret = &sealed[&name];
+                match avena::secrets::open(secret, &identity.seed) {
+                    Ok(value) => create_secret(&name, &value, &sealed_hash(secret)).await,
+                    Err(err) => Err(err.into()),
+                }
+            }
+        };
+        match result {
+            Ok(()) => {
+                applied.insert(name, change);
+            }
+            Err(err) => warn!("Secrets: unable to apply {change} secret {name}: {err}"),
+        }
+    }
+    Ok(applied)
+}
+
+/// Secrets avena created in podman, by name, with their hash label.
+async fn podman_secrets() -> Result<BTreeMap<String, String>> {
+    let output = Command::new("podman")
+        .args(["secret", "ls", "--format", "{{.Name}}"])
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "podman secret ls failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    let mut secrets = BTreeMap::new();
+    for podman_name in String::from_utf8_lossy(&output.stdout).lines() {
+        let Some(name) = podman_name.strip_prefix(SECRET_PREFIX) else {
+            continue;
+        };
+        let format = format!("{{{{index .Spec.Labels \"{HASH_LABEL}\"}}}}");
+        let output = Command::new("podman")
+            .args(["secret", "inspect", "--format", &format, podman_name])
+            .output()
+            .await?;
+        let hash = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        secrets.insert(name.to_string(), hash);
+    }
+    Ok(secrets)
+}
+
+/// Create or replace the podman secret for `name`, passing the value on
+/// stdin so it never appears in a process list.
+async fn create_secret(name: &str, value: &[u8], hash: &str) -> Result<()> {
+    let mut child = Command::new("podman")
+        .args(["secret", "create", "--replace", "--label"])
+        .arg(format!("{HASH_LABEL}={hash}"))
+        .arg(podman_secret(name))
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(value).await?;
+    }
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "podman secret create failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+async fn remove_secret(name: &str) -> Result<()> {
+    let output = Command::new("podman")
+        .args(["secret", "rm", &podman_secret(name)])
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "podman secret rm failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
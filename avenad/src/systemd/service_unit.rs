@@ -47,4 +47,25 @@ pub trait ServiceUnit {
 
     #[dbus_proxy(property, name = "ExecMainPID")]
     fn exec_main_pid(&self) -> zbus::Result<u32>;
+
+    #[dbus_proxy(property)]
+    fn exec_main_status(&self) -> zbus::Result<i32>;
+
+    #[dbus_proxy(property, name = "NRestarts")]
+    fn n_restarts(&self) -> zbus::Result<u32>;
+
+    #[dbus_proxy(property)]
+    fn memory_max(&self) -> zbus::Result<u64>;
+
+    #[dbus_proxy(property, name = "CPUQuotaPerSecUSec")]
+    fn cpu_quota_per_sec_usec(&self) -> zbus::Result<u64>;
+
+    #[dbus_proxy(property, name = "CPUWeight")]
+    fn cpu_weight(&self) -> zbus::Result<u64>;
+
+    #[dbus_proxy(property)]
+    fn tasks_max(&self) -> zbus::Result<u64>;
+
+    #[dbus_proxy(property, name = "IOWeight")]
+    fn io_weight(&self) -> zbus::Result<u64>;
 }
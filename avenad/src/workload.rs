@@ -1,59 +1,714 @@
-use avena::messages::WorkloadSpec;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use askama::Template;
+use avena::messages::{
+    DependencyKind, ResourceSpec, VolumeRetention, WorkloadHealth, WorkloadSpec,
+};
+use avena::secrets::check_secret_name;
+use color_eyre::eyre::eyre;
 use color_eyre::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use tokio::fs;
-use std::path::Path;
+use tokio::process::Command;
+
+use crate::creds::{CREDS_ENV, CREDS_MOUNT};
+use crate::secrets::podman_secret;
 
 pub struct WorkloadDeployment {
     pub name: String,
     pub spec: WorkloadSpec,
+    /// Host path of the workload's NATS creds file, mounted read-only.
+    pub creds: Option<PathBuf>,
+    /// Digest the image was resolved to when it was pulled, pinned in the
+    /// quadlet so restarts run exactly that image.
+    pub digest: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "podman/template.service", escape = "none")]
+struct ContainerQuadlet<'a> {
+    name: &'a str,
+    image: String,
+    exec: Option<String>,
+    env: Vec<String>,
+    labels: Vec<String>,
+    dependencies: Vec<UnitDependency>,
+    creds: Option<String>,
+    secrets: Vec<String>,
+    spec: &'a WorkloadSpec,
+}
+
+struct UnitDependency {
+    unit: String,
+    requires: bool,
+}
+
+/// The image reference a spec names, with its tag if it has one.
+pub fn image_ref(spec: &WorkloadSpec) -> String {
+    match &spec.tag {
+        Some(tag) => format!("{}:{}", spec.image, tag),
+        None => spec.image.clone(),
+    }
+}
+
+/// The quadlet and unit name prefix of the workload called `name`.
+pub fn unit_stem(name: &str) -> String {
+    if name.starts_with("avena-") {
+        name.to_string()
+    } else {
+        format!("avena-{name}")
+    }
+}
+
+mod filters {
+    use std::fmt::Display;
+
+    pub fn value<T: Display>(s: T) -> askama::Result<String> {
+        Ok(super::escape_value(&s.to_string()))
+    }
+
+    pub fn quote<T: Display>(s: T) -> askama::Result<String> {
+        Ok(super::quote(&s.to_string()))
+    }
+}
+
+/// Make `s` safe as a single-line unit file value: control characters
+/// become spaces, so a newline cannot start a new directive, a trailing
+/// backslash cannot continue onto the next line, and `%` is doubled so
+/// systemd does not expand it as a specifier.
+fn escape_value(s: &str) -> String {
+    let s: String = s
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect();
+    s.trim_end_matches('\\').replace('%', "%%")
+}
+
+/// Quote `s` as one word for keys systemd splits on whitespace (`Exec=`,
+/// `Environment=`, `Label=`).
+fn quote(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            '%' => quoted.push_str("%%"),
+            c if c.is_control() => quoted.push(' '),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Workload and volume names become file names under the systemd directory,
+/// and unit names that must not carry journalctl or systemctl globs.
+pub(crate) fn check_file_stem(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(eyre!("invalid workload or volume name {name:?}"))
+    }
+}
+
+fn check_env_name(name: &str) -> Result<()> {
+    let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(eyre!("invalid environment variable name {name:?}"))
+    }
+}
+
+/// systemd ignores out of range values with only a warning in its log, which
+/// would leave the workload unlimited without anyone noticing.
+fn check_resources(resources: &ResourceSpec) -> Result<()> {
+    for (name, weight) in [
+        ("cpu_weight", resources.cpu_weight),
+        ("io_weight", resources.io_weight),
+    ] {
+        if let Some(weight) = weight {
+            if !(1..=10_000).contains(&weight) {
+                return Err(eyre!("{name} must be between 1 and 10000, got {weight}"));
+            }
+        }
+    }
+    if resources.cpu_quota_percent == Some(0) {
+        return Err(eyre!("cpu_quota_percent must be greater than 0"));
+    }
+    Ok(())
+}
+
+/// One file quadlet turns into systemd units.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuadletFile {
+    pub file_name: String,
+    pub contents: String,
+}
+
+impl QuadletFile {
+    pub fn hash(&self) -> String {
+        content_hash(self.contents.as_bytes())
+    }
+}
+
+/// Hex SHA-256 of a quadlet file, used to tell whether a unit changed.
+pub fn content_hash(contents: &[u8]) -> String {
+    data_encoding::HEXLOWER.encode(&Sha256::digest(contents))
+}
+
+/// How a unit's rendered quadlet compares to the one on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnitChange {
+    Created,
+    Updated,
+    Unchanged,
+}
+
+impl fmt::Display for UnitChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            UnitChange::Created => "created",
+            UnitChange::Updated => "updated",
+            UnitChange::Unchanged => "unchanged",
+        })
+    }
 }
 
 impl WorkloadDeployment {
+    /// The systemd service quadlet generates for this workload.
+    pub fn unit_name(&self) -> String {
+        format!("{}.service", self.name)
+    }
+
+    pub fn render(&self) -> Result<Vec<QuadletFile>> {
+        check_file_stem(&self.name)?;
+        for volume in &self.spec.volumes {
+            check_file_stem(&volume.name)?;
+        }
+        for dependency in &self.spec.depends_on {
+            check_file_stem(&dependency.workload)?;
+        }
+        check_resources(&self.spec.resources)?;
+        let secrets = self.secrets()?;
+
+        let quadlet = ContainerQuadlet {
+            name: &self.name,
+            image: self.image(),
+            exec: self.exec(),
+            env: self
+                .spec
+                .env
+                .iter()
+                .map(|(key, value)| format!("{key}={value}"))
+                .chain(
+                    self.creds
+                        .as_ref()
+                        .map(|_| format!("{CREDS_ENV}={CREDS_MOUNT}")),
+                )
+                .collect(),
+            labels: self.labels(),
+            dependencies: self
+                .spec
+                .depends_on
+                .iter()
+                .map(|dependency| UnitDependency {
+                    unit: format!("{}.service", unit_stem(&dependency.workload)),
+                    requires: dependency.kind == DependencyKind::Requires,
+                })
+                .collect(),
+            creds: self
+                .creds
+                .as_ref()
+                .map(|path| format!("{}:{CREDS_MOUNT}:ro", path.display())),
+            secrets,
+            spec: &self.spec,
+        };
+
+        // askama drops the template's final newline
+        let mut contents = quadlet.render()?;
+        contents.push('\n');
+
+        let mut files = vec![QuadletFile {
+            file_name: format!("{}.container", self.name),
+            contents,
+        }];
+        for volume in &self.spec.volumes {
+            files.push(QuadletFile {
+                file_name: format!("{}.volume", volume.name),
+                contents: "[Volume]\n".to_string(),
+            });
+        }
+
+        Ok(files)
+    }
+
+    /// `cmd` is a command line of its own; each of `args` is one argument.
+    fn exec(&self) -> Option<String> {
+        let mut words: Vec<String> = self.spec.cmd.iter().map(|c| escape_value(c)).collect();
+        words.extend(self.spec.args.iter().map(|a| quote(a)));
+        (!words.is_empty()).then(|| words.join(" "))
+    }
+
+    /// `Secret=` values: mounted at `/run/secrets/{name}` unless an
+    /// environment variable is named. Names are checked so they cannot add
+    /// options of their own.
+    fn secrets(&self) -> Result<Vec<String>> {
+        self.spec
+            .secrets
+            .iter()
+            .map(|secret| {
+                check_secret_name(&secret.name).map_err(|e| eyre!("{e}"))?;
+                let podman_name = podman_secret(&secret.name);
+                match &secret.env {
+                    Some(var) => {
+                        check_env_name(var)?;
+                        Ok(format!("{podman_name},type=env,target={var}"))
+                    }
+                    None => Ok(format!("{podman_name},type=mount,target={}", secret.name)),
+                }
+            })
+            .collect()
+    }
+
+    /// The NATS permissions the workload was deployed with, for inspection
+    /// with `podman inspect`, and its volume retention, for cleanup after
+    /// the spec is gone.
+    fn labels(&self) -> Vec<String> {
+        let mut labels = vec![];
+        if self.spec.volume_retention == VolumeRetention::Purge {
+            labels.push(PURGE_VOLUMES_LABEL.to_string());
+        }
+        if !self.spec.perms.publish.is_empty() {
+            labels.push(format!(
+                "avena.nats.publish={}",
+                self.spec.perms.publish.join(",")
+            ));
+        }
+        if !self.spec.perms.subscribe.is_empty() {
+            labels.push(format!(
+                "avena.nats.subscribe={}",
+                self.spec.perms.subscribe.join(",")
+            ));
+        }
+        labels
+    }
+
+    /// Compare the rendered quadlet files with the ones in `systemd_dir`.
+    pub async fn diff(&self, systemd_dir: &Path) -> Result<UnitChange> {
+        let files = self.render()?;
+        if !fs::try_exists(systemd_dir.join(&files[0].file_name)).await? {
+            return Ok(UnitChange::Created);
+        }
+
+        for file in &files {
+            match fs::read(systemd_dir.join(&file.file_name)).await {
+                Ok(existing) if content_hash(&existing) == file.hash() => {}
+                Ok(_) => return Ok(UnitChange::Updated),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    return Ok(UnitChange::Updated)
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(UnitChange::Unchanged)
+    }
+
+    /// The image the container runs, pinned to the pulled digest unless the
+    /// spec already pins one.
+    fn image(&self) -> String {
+        let image = image_ref(&self.spec);
+        match &self.digest {
+            Some(digest) if !image.contains('@') => format!("{image}@{digest}"),
+            _ => image,
+        }
+    }
+
     pub async fn deploy(&self, systemd_dir: &Path) -> Result<()> {
         fs::create_dir_all(systemd_dir).await?;
 
-        let mut quadlet = format!(
-            "[Unit]\nDescription={}\n\n[Container]\nContainerName={}\nImage={}",
-            self.name,
-            self.name,
-            if let Some(tag) = &self.spec.tag {
-                format!("{}:{}", self.spec.image, tag)
-            } else {
-                self.spec.image.clone()
+        for file in self.render()? {
+            fs::write(systemd_dir.join(&file.file_name), file.contents).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The creds file mounted into the workload `name` deployed to
+/// `systemd_dir`, if any.
+pub async fn deployed_creds(systemd_dir: &Path, name: &str) -> Option<PathBuf> {
+    let contents = fs::read_to_string(systemd_dir.join(format!("{name}.container")))
+        .await
+        .ok()?;
+    let suffix = format!(":{CREDS_MOUNT}:ro");
+    contents.lines().find_map(|line| {
+        let path = line.strip_prefix("Volume=")?.strip_suffix(&suffix)?;
+        Some(PathBuf::from(path.replace("%%", "%")))
+    })
+}
+
+/// The digest the workload `name` deployed to `systemd_dir` is pinned to,
+/// if it still runs `image`.
+pub async fn deployed_digest(systemd_dir: &Path, name: &str, image: &str) -> Option<String> {
+    let contents = fs::read_to_string(systemd_dir.join(format!("{name}.container")))
+        .await
+        .ok()?;
+    contents.lines().find_map(|line| {
+        let digest = line
+            .strip_prefix("Image=")?
+            .strip_prefix(image)?
+            .strip_prefix('@')?;
+        Some(digest.to_string())
+    })
+}
+
+const PURGE_VOLUMES_LABEL: &str = "avena.volumes=purge";
+
+/// A workload whose quadlet is still on disk after its spec was removed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemovedWorkload {
+    pub name: String,
+    pub volumes: Vec<String>,
+    pub retention: VolumeRetention,
+}
+
+/// Files and volumes deleted while removing a workload.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Cleanup {
+    pub files: Vec<String>,
+    pub retained: Vec<String>,
+    pub purged: Vec<String>,
+}
+
+impl RemovedWorkload {
+    /// Recover what was deployed from a `.container` quadlet rendered by
+    /// [`WorkloadDeployment::render`].
+    pub fn from_quadlet(name: &str, contents: &str) -> Self {
+        let mut volumes = vec![];
+        let mut retention = VolumeRetention::Retain;
+        for line in contents.lines() {
+            if let Some(volume) = line.strip_prefix("Volume=") {
+                let source = volume.split(':').next().unwrap_or_default();
+                if let Some(volume) = source.strip_suffix(".volume") {
+                    volumes.push(volume.to_string());
+                }
+            } else if line == format!("Label={}", quote(PURGE_VOLUMES_LABEL)) {
+                retention = VolumeRetention::Purge;
             }
-        );
+        }
 
-        if let Some(cmd) = &self.spec.cmd {
-            quadlet.push_str(&format!("\nExec={}", cmd));
+        RemovedWorkload {
+            name: name.to_string(),
+            volumes,
+            retention,
         }
+    }
 
-        for port in &self.spec.ports {
-            quadlet.push_str(&format!(
-                "\nPublishPort={}:{}",
-                port.host, port.container
-            ));
+    pub fn unit_name(&self) -> String {
+        format!("{}.service", self.name)
+    }
+
+    /// Delete the workload's quadlet files. Volumes still named in
+    /// `in_use` by another workload keep their file.
+    pub async fn remove_files(
+        &self,
+        systemd_dir: &Path,
+        in_use: &HashSet<String>,
+    ) -> Result<Cleanup> {
+        let mut cleanup = Cleanup::default();
+        let mut files = vec![format!("{}.container", self.name)];
+        for volume in &self.volumes {
+            if in_use.contains(volume) {
+                continue;
+            }
+            files.push(format!("{volume}.volume"));
+            match self.retention {
+                VolumeRetention::Retain => cleanup.retained.push(volume.clone()),
+                VolumeRetention::Purge => cleanup.purged.push(volume.clone()),
+            }
         }
 
-        for mount in &self.spec.mounts {
-            let readonly_flag = if mount.readonly { "ro" } else { "z" };
-            quadlet.push_str(&format!("\nVolume={}:{}:{}", mount.host, mount.container, readonly_flag));
+        for file in files {
+            match fs::remove_file(systemd_dir.join(&file)).await {
+                Ok(()) => cleanup.files.push(file),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
         }
+        Ok(cleanup)
+    }
+}
 
-        for vol_name in &self.spec.volumes {
-            quadlet.push_str(&format!("\nVolume={}.volume:/data", vol_name));
+/// Workloads avena deployed to `systemd_dir` that `plan` no longer wants.
+pub async fn removed_workloads(
+    systemd_dir: &Path,
+    plan: &ReconcilePlan,
+) -> Result<Vec<RemovedWorkload>> {
+    let mut entries = match fs::read_dir(systemd_dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut removed = vec![];
+    while let Some(entry) = entries.next_entry().await? {
+        let file_name = entry.file_name();
+        let Some(name) = file_name
+            .to_str()
+            .and_then(|f| f.strip_suffix(".container"))
+            .filter(|n| n.starts_with("avena-"))
+        else {
+            continue;
+        };
+        if plan.is_desired(&format!("{name}.service")) {
+            continue;
         }
+        let contents = fs::read_to_string(entry.path()).await?;
+        removed.push(RemovedWorkload::from_quadlet(name, &contents));
+    }
+    removed.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(removed)
+}
+
+/// Delete the podman volume quadlet created for the `.volume` file `name`.
+pub async fn purge_volume(name: &str) -> Result<()> {
+    let output = Command::new("podman")
+        .args(["volume", "rm", &format!("systemd-{name}")])
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "podman volume rm systemd-{name}: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+/// What `podman container inspect` tells about a running container.
+#[derive(Debug, Default)]
+pub struct ContainerInspect {
+    /// `None` if the container has no health check.
+    pub health: Option<WorkloadHealth>,
+    /// Digest of the image the container was created from.
+    pub digest: Option<String>,
+}
 
-        quadlet.push_str("\n\n[Service]\nRestart=on-failure\n");
+pub async fn inspect_container(container: &str) -> ContainerInspect {
+    let output = Command::new("podman")
+        .args(["container", "inspect", container])
+        .output()
+        .await;
+    match output {
+        Ok(output) if output.status.success() => ContainerInspect {
+            health: health_from_inspect(&output.stdout),
+            digest: digest_from_inspect(&output.stdout),
+        },
+        _ => ContainerInspect::default(),
+    }
+}
 
-        fs::write(systemd_dir.join(format!("{}.container", self.name)), quadlet).await?;
+/// Read the health status out of `podman container inspect` output.
+pub fn health_from_inspect(inspect: &[u8]) -> Option<WorkloadHealth> {
+    let containers: Vec<serde_json::Value> = serde_json::from_slice(inspect).ok()?;
+    let state = &containers.first()?["State"];
+    // Podman 4 reports `Healthcheck`, podman 5 `Health`
+    let status = state["Health"]["Status"]
+        .as_str()
+        .or_else(|| state["Healthcheck"]["Status"].as_str())?;
 
-        for vol_name in &self.spec.volumes {
-            fs::write(
-                systemd_dir.join(format!("{}.volume", vol_name)),
-                "[Volume]\n",
-            ).await?;
+    match status {
+        "" => None,
+        "starting" => Some(WorkloadHealth::Starting),
+        "healthy" => Some(WorkloadHealth::Healthy),
+        "unhealthy" => Some(WorkloadHealth::Unhealthy),
+        _ => Some(WorkloadHealth::Unknown),
+    }
+}
+
+/// Read the image digest out of `podman container inspect` output.
+pub fn digest_from_inspect(inspect: &[u8]) -> Option<String> {
+    let containers: Vec<serde_json::Value> = serde_json::from_slice(inspect).ok()?;
+    containers.first()?["ImageDigest"]
+        .as_str()
+        .filter(|digest| !digest.is_empty())
+        .map(str::to_string)
+}
+
+/// Why a desired workload is left out of a reconcile pass.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DependencyError {
+    /// The workload is part of a dependency cycle, listed from the workload
+    /// back to itself.
+    Cycle(Vec<String>),
+    /// A workload it requires is not deployed on this device.
+    Unavailable(String),
+}
+
+impl fmt::Display for DependencyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DependencyError::Cycle(cycle) => {
+                write!(f, "dependency cycle {}", cycle.join(" -> "))
+            }
+            DependencyError::Unavailable(dependency) => {
+                write!(f, "requires {dependency}, which is not deployed")
+            }
         }
+    }
+}
+
+impl std::error::Error for DependencyError {}
+
+enum Visit {
+    Active,
+    Done(bool),
+}
+
+/// Order `workloads` so every workload comes after the ones it depends on.
+///
+/// Workloads in a cycle, and workloads that require one that is missing or
+/// itself rejected, are left out of the order and returned with the reason.
+/// A missing `wants` dependency does not hold a workload back.
+pub fn resolve_dependencies(
+    workloads: &BTreeMap<String, WorkloadSpec>,
+) -> (Vec<String>, BTreeMap<String, DependencyError>) {
+    let mut visits = HashMap::new();
+    let mut order = vec![];
+    let mut rejected = BTreeMap::new();
+    for name in workloads.keys() {
+        visit(
+            name,
+            workloads,
+            &mut visits,
+            &mut vec![],
+            &mut order,
+            &mut rejected,
+        );
+    }
+    (order, rejected)
+}
 
+fn visit(
+    name: &str,
+    workloads: &BTreeMap<String, WorkloadSpec>,
+    visits: &mut HashMap<String, Visit>,
+    path: &mut Vec<String>,
+    order: &mut Vec<String>,
+    rejected: &mut BTreeMap<String, DependencyError>,
+) -> bool {
+    match visits.get(name) {
+        Some(Visit::Done(ok)) => return *ok,
+        Some(Visit::Active) => {
+            let start = path.iter().position(|n| n == name).unwrap_or(0);
+            for (i, member) in path[start..].iter().enumerate() {
+                // Each member reports the cycle starting from itself
+                let mut cycle = path[start + i..].to_vec();
+                cycle.extend_from_slice(&path[start..=start + i]);
+                rejected
+                    .entry(member.clone())
+                    .or_insert(DependencyError::Cycle(cycle));
+            }
+            return false;
+        }
+        None => {}
+    }
+
+    visits.insert(name.to_string(), Visit::Active);
+    path.push(name.to_string());
+    for dependency in &workloads[name].depends_on {
+        let available = workloads.contains_key(&dependency.workload)
+            && visit(
+                &dependency.workload,
+                workloads,
+                visits,
+                path,
+                order,
+                rejected,
+            );
+        if !available && dependency.kind == DependencyKind::Requires {
+            rejected
+                .entry(name.to_string())
+                .or_insert_with(|| DependencyError::Unavailable(dependency.workload.clone()));
+        }
+    }
+    path.pop();
+
+    let ok = !rejected.contains_key(name);
+    visits.insert(name.to_string(), Visit::Done(ok));
+    if ok {
+        order.push(name.to_string());
+    }
+    ok
+}
+
+/// Units touched by one reconcile pass, grouped by what happened to them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReconcilePlan {
+    pub created: Vec<String>,
+    pub updated: Vec<String>,
+    pub unchanged: Vec<String>,
+    pub removed: Vec<String>,
+    /// Desired units left as they are because of their dependencies, or
+    /// because their image is still being pulled or failed to pull.
+    pub rejected: Vec<String>,
+}
+
+impl ReconcilePlan {
+    pub fn record(&mut self, unit: String, change: UnitChange) {
+        match change {
+            UnitChange::Created => self.created.push(unit),
+            UnitChange::Updated => self.updated.push(unit),
+            UnitChange::Unchanged => self.unchanged.push(unit),
+        }
+    }
+
+    /// Whether systemd has new quadlet files to pick up.
+    pub fn needs_reload(&self) -> bool {
+        !self.created.is_empty() || !self.updated.is_empty()
+    }
+
+    /// Whether `unit` is still desired.
+    pub fn is_desired(&self, unit: &str) -> bool {
+        [
+            &self.created,
+            &self.updated,
+            &self.unchanged,
+            &self.rejected,
+        ]
+        .iter()
+        .any(|units| units.iter().any(|u| u == unit))
+    }
+}
+
+impl fmt::Display for ReconcilePlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} created, {} updated, {} unchanged, {} removed",
+            self.created.len(),
+            self.updated.len(),
+            self.unchanged.len(),
+            self.removed.len()
+        )?;
+        if !self.rejected.is_empty() {
+            write!(f, ", {} rejected", self.rejected.len())?;
+        }
         Ok(())
     }
 }
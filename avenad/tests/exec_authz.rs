@@ -0,0 +1,128 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use avena::messages::{ErrorCode, ExecRequest};
+use avenad::{authorize_exec, ExecNonces};
+use data_encoding::BASE64URL_NOPAD;
+use nkeys::KeyPair;
+
+const DEVICE: &str = "dev1";
+const NOW_MS: u64 = 1_700_000_000_000;
+
+static NONCES: AtomicU64 = AtomicU64::new(0);
+
+fn signed(key: &KeyPair, device: &str, issued_ms: u64) -> ExecRequest {
+    let mut req = ExecRequest {
+        workload: "nginx".to_string(),
+        inbox: "_INBOX.abc".to_string(),
+        command: vec!["sh".to_string()],
+        tty: true,
+        timeout_secs: Some(60),
+        window: 16,
+        caller: key.public_key(),
+        issued_ms,
+        nonce: NONCES.fetch_add(1, Ordering::Relaxed).to_string(),
+        signature: String::new(),
+    };
+    let signature = key.sign(&req.signed_payload(device)).unwrap();
+    req.signature = BASE64URL_NOPAD.encode(&signature);
+    req
+}
+
+fn code(result: Result<(), avena::messages::ErrorResponse>) -> Option<ErrorCode> {
+    result.err().map(|err| err.code)
+}
+
+#[test]
+fn allowed_key_may_exec() {
+    let key = KeyPair::new_user();
+    let allowed = [key.public_key()];
+    let nonces = ExecNonces::default();
+    let req = signed(&key, DEVICE, NOW_MS - 5_000);
+
+    assert!(authorize_exec(&req, DEVICE, &allowed, &nonces, NOW_MS).is_ok());
+}
+
+#[test]
+fn exec_is_refused_without_a_valid_fresh_signature() {
+    let key = KeyPair::new_user();
+    let allowed = [key.public_key()];
+    let nonces = ExecNonces::default();
+
+    let other = KeyPair::new_user();
+    let req = signed(&other, DEVICE, NOW_MS);
+    assert_eq!(
+        code(authorize_exec(&req, DEVICE, &allowed, &nonces, NOW_MS)),
+        Some(ErrorCode::Unauthorized),
+        "key not allowed"
+    );
+
+    let req = signed(&key, "dev2", NOW_MS);
+    assert_eq!(
+        code(authorize_exec(&req, DEVICE, &allowed, &nonces, NOW_MS)),
+        Some(ErrorCode::Unauthorized),
+        "signed for another device"
+    );
+
+    let mut req = signed(&key, DEVICE, NOW_MS);
+    req.command = vec!["rm".to_string(), "-rf".to_string(), "/".to_string()];
+    assert_eq!(
+        code(authorize_exec(&req, DEVICE, &allowed, &nonces, NOW_MS)),
+        Some(ErrorCode::Unauthorized),
+        "command changed after signing"
+    );
+
+    let req = signed(&key, DEVICE, NOW_MS - 10 * 60_000);
+    assert_eq!(
+        code(authorize_exec(&req, DEVICE, &allowed, &nonces, NOW_MS)),
+        Some(ErrorCode::Unauthorized),
+        "expired"
+    );
+
+    let mut req = signed(&key, DEVICE, NOW_MS);
+    req.command.clear();
+    assert_eq!(
+        code(authorize_exec(&req, DEVICE, &allowed, &nonces, NOW_MS)),
+        Some(ErrorCode::BadRequest)
+    );
+
+    let mut req = signed(&key, DEVICE, NOW_MS);
+    req.workload = "*".to_string();
+    assert_eq!(
+        code(authorize_exec(&req, DEVICE, &allowed, &nonces, NOW_MS)),
+        Some(ErrorCode::BadRequest),
+        "workload glob"
+    );
+}
+
+#[test]
+fn exec_requests_run_only_once() {
+    let key = KeyPair::new_user();
+    let allowed = [key.public_key()];
+    let nonces = ExecNonces::default();
+
+    let req = signed(&key, DEVICE, NOW_MS);
+    assert!(authorize_exec(&req, DEVICE, &allowed, &nonces, NOW_MS).is_ok());
+    assert_eq!(
+        code(authorize_exec(
+            &req,
+            DEVICE,
+            &allowed,
+            &nonces,
+            NOW_MS + 1_000
+        )),
+        Some(ErrorCode::Unauthorized),
+        "replayed"
+    );
+
+    // The nonce is signed, so a replay can not swap it for a fresh one
+    let mut replay = req.clone();
+    replay.nonce = "fresh".to_string();
+    assert_eq!(
+        code(authorize_exec(&replay, DEVICE, &allowed, &nonces, NOW_MS)),
+        Some(ErrorCode::Unauthorized),
+        "nonce changed after signing"
+    );
+
+    let other = signed(&key, DEVICE, NOW_MS);
+    assert!(authorize_exec(&other, DEVICE, &allowed, &nonces, NOW_MS).is_ok());
+}
@@ -136,3 +136,47 @@ async fn test_hlc_concurrent_events() {
         );
     }
 }
+
+#[test]
+fn test_hlc_causal_chain_with_skewed_clocks() {
+    let mut net = avena_test::clocks::ClockNet::new();
+    net.add_node("ahead", 1_010_000);
+    net.add_node("behind", 1_000_000);
+    net.add_node("frozen", 990_000);
+
+    // ahead -> behind -> frozen, with no wall time passing anywhere
+    let (sent, at_behind) = net.send("ahead", "behind");
+    let at_behind = at_behind.unwrap();
+    assert_eq!(at_behind.wall_time_ms, sent.wall_time_ms);
+    assert_eq!(at_behind.counter, sent.counter + 1);
+
+    let (relayed, at_frozen) = net.send("behind", "frozen");
+    let at_frozen = at_frozen.unwrap();
+    assert!(relayed > sent);
+    assert!(at_frozen > relayed);
+    assert_eq!(at_frozen.wall_time_ms, 1_010_000);
+
+    // The frozen node keeps ordering its own events after what it has seen
+    let local = net.hlc("frozen").tick();
+    assert!(local > at_frozen);
+    assert_eq!(local.wall_time_ms, 1_010_000);
+}
+
+#[test]
+fn test_hlc_jumping_clock_is_bounded() {
+    let mut net = avena_test::clocks::ClockNet::new();
+    net.add_node("node1", 1_000_000);
+    net.add_node("node2", 1_000_000);
+
+    // node1's clock jumps an hour ahead; node2 refuses to follow it
+    net.clock("node1").advance(Duration::from_secs(3600));
+    let (_, received) = net.send("node1", "node2");
+    assert!(received.is_err());
+    assert_eq!(net.hlc("node2").current().wall_time_ms, 1_000_000);
+    assert_eq!(net.hlc("node2").stats().rejected, 1);
+
+    // Once node2 catches up the same gap is accepted
+    net.clock("node2").advance(Duration::from_secs(3600));
+    let (sent, received) = net.send("node1", "node2");
+    assert!(received.unwrap() > sent);
+}
@@ -0,0 +1,101 @@
+//! Background image pulls, and pinning workloads to the digest a pull
+//! resolved to.
+
+use avena::messages::WorkloadSpec;
+use std::time::Duration;
+
+use avenad::images::{verify_digest, ImagePulls};
+use avenad::workload::{deployed_digest, image_ref, UnitChange, WorkloadDeployment};
+
+const DIGEST: &str = "sha256:4c0fdaa8b6341bfdeca5f18f7837462c80cff90527ee35ef185571e1c327beac";
+
+fn deployment(image: &str, tag: Option<&str>) -> WorkloadDeployment {
+    WorkloadDeployment {
+        name: "avena-nginx".to_string(),
+        creds: None,
+        digest: None,
+        spec: WorkloadSpec {
+            tag: tag.map(str::to_string),
+            ..WorkloadSpec::new(image)
+        },
+    }
+}
+
+#[tokio::test]
+async fn pulled_digest_is_pinned_until_the_image_changes() {
+    let systemd_dir = tempfile::tempdir().unwrap();
+    let mut deployment = deployment("docker.io/library/nginx", Some("1.27"));
+    deployment.digest = Some(DIGEST.to_string());
+
+    let files = deployment.render().unwrap();
+    assert!(files[0]
+        .contents
+        .contains(&format!("Image=docker.io/library/nginx:1.27@{DIGEST}\n")));
+
+    deployment.deploy(systemd_dir.path()).await.unwrap();
+    let image = image_ref(&deployment.spec);
+    assert_eq!(
+        deployed_digest(systemd_dir.path(), "avena-nginx", &image).await,
+        Some(DIGEST.to_string())
+    );
+    // Reading the digest back leaves the deployment in sync
+    deployment.digest = deployed_digest(systemd_dir.path(), "avena-nginx", &image).await;
+    assert_eq!(
+        deployment.diff(systemd_dir.path()).await.unwrap(),
+        UnitChange::Unchanged
+    );
+
+    // A new tag needs a new pull
+    assert_eq!(
+        deployed_digest(
+            systemd_dir.path(),
+            "avena-nginx",
+            "docker.io/library/nginx:1.28"
+        )
+        .await,
+        None
+    );
+}
+
+#[test]
+fn images_pinned_in_the_spec_are_not_pinned_again() {
+    let image = format!("docker.io/library/nginx@{DIGEST}");
+    let mut deployment = deployment(&image, None);
+    deployment.digest = Some(DIGEST.to_string());
+
+    let files = deployment.render().unwrap();
+    assert!(files[0].contents.contains(&format!("Image={image}\n")));
+}
+
+#[test]
+fn pulled_digests_are_verified() {
+    verify_digest("docker.io/library/nginx:1.27", DIGEST).unwrap();
+    verify_digest(&format!("docker.io/library/nginx@{DIGEST}"), DIGEST).unwrap();
+
+    assert!(verify_digest("docker.io/library/nginx", "").is_err());
+    assert!(verify_digest("docker.io/library/nginx", "sha256:abc").is_err());
+    let other = format!("docker.io/library/nginx@sha256:{}", "0".repeat(64));
+    assert!(verify_digest(&other, DIGEST).is_err());
+}
+
+/// A failed pull is reported to reconcile once, and stays visible to status.
+#[tokio::test]
+async fn failed_pulls_are_reported_once() {
+    let pulls = ImagePulls::default();
+    let image = "avena.invalid/no such image";
+    pulls.start("avena-broken", image);
+    pulls.start("avena-broken", image);
+
+    tokio::time::timeout(Duration::from_secs(30), pulls.finished())
+        .await
+        .expect("pull finishes");
+    assert!(pulls.any_failed());
+    assert!(pulls.take_pulled("avena-broken", image).is_none());
+    assert!(pulls.take_failure("avena-broken", "other").is_none());
+    assert!(pulls.take_failure("avena-broken", image).is_some());
+    assert!(pulls.take_failure("avena-broken", image).is_none());
+    assert!(pulls.get("avena-broken").unwrap().error.is_some());
+
+    pulls.retain(&Default::default());
+    assert!(pulls.get("avena-broken").is_none());
+}
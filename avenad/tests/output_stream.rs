@@ -0,0 +1,215 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use avena::hlc::HlcClock;
+use avena::messages::{ErrorCode, LogsRequest, OutputKind, StreamOpened};
+use avena::rpc::RpcServer;
+use avena::streams::{StreamSink, MAX_CHUNK_BYTES};
+use avena::test_utils::start_nats_server;
+use avena::{Avena, Error};
+use avenad::output;
+use tokio::process::Command;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+const SUBJECT: &str = "test.output";
+
+/// Serve streams of `sh -c <script>` on [`SUBJECT`], handing each pump task
+/// to the returned channel.
+fn serve_script(
+    nc: async_nats::Client,
+    script: &'static str,
+    input: bool,
+) -> mpsc::UnboundedReceiver<JoinHandle<()>> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        let server = RpcServer::new(nc.clone(), Arc::new(HlcClock::new("test-device")));
+        server
+            .serve(SUBJECT.to_string(), |req: LogsRequest, _| {
+                let nc = nc.clone();
+                let tx = tx.clone();
+                async move {
+                    let child =
+                        output::spawn_piped(Command::new("sh").arg("-c").arg(script), input)
+                            .expect("spawn sh");
+                    let sink = StreamSink::open(nc, req.inbox, req.window)
+                        .await
+                        .expect("open sink");
+                    let control = sink.control_subject().to_string();
+                    let _ = tx.send(tokio::spawn(output::pump(sink, child, None)));
+                    Ok(StreamOpened { control })
+                }
+            })
+            .await
+            .unwrap();
+    });
+    rx
+}
+
+fn request(a: &Avena, window: u32) -> (LogsRequest, String) {
+    let inbox = a.nc().new_inbox();
+    let req = LogsRequest {
+        workload: "test".to_string(),
+        inbox: inbox.clone(),
+        follow: false,
+        since_ms: None,
+        tail: None,
+        window,
+    };
+    (req, inbox)
+}
+
+/// All output arrives in order, split into bounded chunks, through a window
+/// much smaller than the output.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn streams_output_and_exit_code_through_window() {
+    let nats = match start_nats_server() {
+        Ok(n) => n,
+        Err(err) => {
+            eprintln!("Skipping test: failed to start nats-server ({err})");
+            return;
+        }
+    };
+    let a = Avena::connect_with_auth(&nats.url, "auth", "auth")
+        .await
+        .expect("connect avena");
+    let _pumps = serve_script(a.nc(), "seq 1 50000; echo oops >&2; exit 3", false);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let (req, inbox) = request(&a, 2);
+    let mut stream = a.open_stream(SUBJECT, &req, inbox).await.expect("open");
+
+    let (mut stdout, mut stderr) = (String::new(), String::new());
+    let mut chunks = 0;
+    let exit_code = loop {
+        let chunk = tokio::time::timeout(Duration::from_secs(10), stream.next())
+            .await
+            .expect("chunk in time")
+            .expect("stream ends with a done chunk")
+            .expect("valid chunk");
+        assert!(chunk.data.len() <= MAX_CHUNK_BYTES);
+        match chunk.kind {
+            OutputKind::Stderr => stderr.push_str(&chunk.data),
+            _ => stdout.push_str(&chunk.data),
+        }
+        if chunk.done {
+            break chunk.exit_code;
+        }
+        chunks += 1;
+    };
+
+    let expected: String = (1..=50000).map(|n| format!("{n}\n")).collect();
+    assert_eq!(stdout, expected);
+    assert_eq!(stderr, "oops\n");
+    assert_eq!(exit_code, Some(3));
+    assert!(
+        chunks > 2,
+        "output should span several chunks, got {chunks}"
+    );
+    assert!(stream.next().await.is_none());
+}
+
+/// Cancelling a stream kills a process that would otherwise run forever.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn cancel_stops_the_process() {
+    let nats = match start_nats_server() {
+        Ok(n) => n,
+        Err(err) => {
+            eprintln!("Skipping test: failed to start nats-server ({err})");
+            return;
+        }
+    };
+    let a = Avena::connect_with_auth(&nats.url, "auth", "auth")
+        .await
+        .expect("connect avena");
+    let mut pumps = serve_script(a.nc(), "while true; do echo tick; sleep 0.01; done", false);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let (req, inbox) = request(&a, 4);
+    let mut stream = a.open_stream(SUBJECT, &req, inbox).await.expect("open");
+    let chunk = stream.next().await.expect("chunk").expect("valid chunk");
+    assert!(chunk.data.starts_with("tick\n"));
+    stream.cancel().await.expect("cancel");
+
+    let pump = pumps.recv().await.expect("pump task");
+    tokio::time::timeout(Duration::from_secs(5), pump)
+        .await
+        .expect("pump stops after cancel")
+        .unwrap();
+}
+
+/// Workload names reach journalctl as unit names, so globs are refused
+/// before anything is spawned.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn logs_reject_invalid_workload_names() {
+    let nats = match start_nats_server() {
+        Ok(n) => n,
+        Err(err) => {
+            eprintln!("Skipping test: failed to start nats-server ({err})");
+            return;
+        }
+    };
+    let a = Avena::connect_with_auth(&nats.url, "auth", "auth")
+        .await
+        .expect("connect avena");
+    let nc = a.nc();
+    tokio::spawn(async move {
+        avenad::serve_workload_logs(
+            nc,
+            SUBJECT.to_string(),
+            Arc::new(HlcClock::new("test-device")),
+        )
+        .await
+        .unwrap();
+    });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    for name in ["", "*", "web?", "[a-z]*", "../etc"] {
+        let (mut req, inbox) = request(&a, 2);
+        req.workload = name.to_string();
+        match a.open_stream(SUBJECT, &req, inbox).await {
+            Err(Error::Remote { code, .. }) => assert_eq!(code, ErrorCode::BadRequest, "{name:?}"),
+            Err(other) => panic!("expected a bad request for {name:?}, got {other:?}"),
+            Ok(_) => panic!("expected a bad request for {name:?}"),
+        }
+    }
+}
+
+/// Input sent on the control subject reaches the process, and closing it
+/// ends a process reading to the end of its input.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn input_reaches_the_process() {
+    let nats = match start_nats_server() {
+        Ok(n) => n,
+        Err(err) => {
+            eprintln!("Skipping test: failed to start nats-server ({err})");
+            return;
+        }
+    };
+    let a = Avena::connect_with_auth(&nats.url, "auth", "auth")
+        .await
+        .expect("connect avena");
+    let _pumps = serve_script(a.nc(), "tr a-z A-Z", true);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let (req, inbox) = request(&a, 4);
+    let mut stream = a.open_stream(SUBJECT, &req, inbox).await.expect("open");
+    stream
+        .send_input("hello\n".to_string())
+        .await
+        .expect("input");
+    stream.close_input().await.expect("close input");
+
+    let mut stdout = String::new();
+    while let Some(chunk) = tokio::time::timeout(Duration::from_secs(10), stream.next())
+        .await
+        .expect("chunk in time")
+    {
+        let chunk = chunk.expect("valid chunk");
+        stdout.push_str(&chunk.data);
+        if chunk.done {
+            assert_eq!(chunk.exit_code, Some(0));
+        }
+    }
+    assert_eq!(stdout, "HELLO\n");
+}
@@ -2,10 +2,15 @@ use std::sync::Arc;
 use std::time::Duration;
 
 use avena::hlc::HlcClock;
-use avena::messages::{subject_ping, subject_status, PingRequest, PingResponse, StatusResponse};
+use avena::messages::{
+    subject_ping, subject_status, Device, PingRequest, PingResponse, StatusResponse,
+    BROADCAST_PING_SUBJECT, BROADCAST_STATUS_SUBJECT,
+};
 use avena::test_utils::start_nats_server;
+use tokio::sync::Mutex;
 use tokio::task::JoinHandle;
 use avenad::device::DeviceIdentity;
+use avenad::images::ImagePulls;
 
 /// Spin up the avenad request handlers (ping/status) against an ephemeral NATS and assert round-trips.
 #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
@@ -35,6 +40,15 @@ async fn ping_and_status_roundtrip() {
         network_token: None,
     };
     let hlc = Arc::new(HlcClock::new(device_id));
+    let kv = async_nats::jetstream::new(nc.clone())
+        .create_key_value(async_nats::jetstream::kv::Config {
+            bucket: "workloads".to_string(),
+            ..Default::default()
+        })
+        .await
+        .expect("create workloads bucket");
+    let kv = Arc::new(Mutex::new(kv));
+    let systemd_dir = tempfile::tempdir().unwrap();
 
     let mut handles: Vec<JoinHandle<()>> = Vec::new();
 
@@ -53,10 +67,20 @@ async fn ping_and_status_roundtrip() {
     {
         let nc = nc.clone();
         let hlc = hlc.clone();
+        let systemd_dir = systemd_dir.path().to_path_buf();
         handles.push(tokio::spawn(async move {
-            avenad::serve_status(nc, status_subject, started, identity, hlc)
-                .await
-                .unwrap();
+            avenad::serve_status(
+                nc,
+                status_subject,
+                started,
+                identity,
+                hlc,
+                kv,
+                systemd_dir,
+                ImagePulls::default(),
+            )
+            .await
+            .unwrap();
         }));
     }
 
@@ -84,3 +108,185 @@ async fn ping_and_status_roundtrip() {
         handle.abort();
     }
 }
+
+/// Broadcast a fleet ping and check replies are matched against the device registry.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn fleet_ping_reports_missing_devices() {
+    let nats = match start_nats_server() {
+        Ok(n) => n,
+        Err(err) => {
+            eprintln!("Skipping test: failed to start nats-server ({err})");
+            return;
+        }
+    };
+    let device_id = "test-device";
+
+    let nc = async_nats::ConnectOptions::with_user_and_password("auth".into(), "auth".into())
+        .connect(&nats.url)
+        .await
+        .expect("connect nats");
+
+    let kv = async_nats::jetstream::new(nc.clone())
+        .create_key_value(async_nats::jetstream::kv::Config {
+            bucket: "avena_devices".to_string(),
+            ..Default::default()
+        })
+        .await
+        .expect("create devices bucket");
+    for id in [device_id, "ghost"] {
+        let device = Device {
+            id: id.to_string(),
+            version: "0.1.0".to_string(),
+            last_seen_ms: None,
+            uptime_ms: None,
+            nats_name: None,
+            pubkey: None,
+        };
+        kv.put(id, Vec::from(device).into()).await.expect("put device");
+    }
+
+    let handle = {
+        let nc = nc.clone();
+        let hlc = Arc::new(HlcClock::new(device_id));
+        tokio::spawn(async move {
+            avenad::serve_ping(
+                nc,
+                BROADCAST_PING_SUBJECT.to_string(),
+                device_id.to_string(),
+                "test-nats".to_string(),
+                std::time::Instant::now(),
+                hlc,
+            )
+            .await
+            .unwrap();
+        })
+    };
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let client = avena::Avena::connect_with_auth(&nats.url, "auth", "auth")
+        .await
+        .expect("connect avena");
+    let report = client
+        .fleet_ping(Duration::from_millis(500))
+        .await
+        .expect("fleet ping");
+
+    let reply = &report.replies[device_id];
+    assert_eq!(reply.response.device, device_id);
+    assert_eq!(reply.hlc.as_ref().map(|h| h.node_id.as_str()), Some(device_id));
+    assert_eq!(report.missing, vec!["ghost".to_string()]);
+
+    handle.abort();
+}
+
+/// Broadcast a fleet status request and check replies are matched against the device registry.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn fleet_status_reports_missing_devices() {
+    let nats = match start_nats_server() {
+        Ok(n) => n,
+        Err(err) => {
+            eprintln!("Skipping test: failed to start nats-server ({err})");
+            return;
+        }
+    };
+    let device_id = "test-device";
+
+    let nc = async_nats::ConnectOptions::with_user_and_password("auth".into(), "auth".into())
+        .connect(&nats.url)
+        .await
+        .expect("connect nats");
+
+    let kv = async_nats::jetstream::new(nc.clone())
+        .create_key_value(async_nats::jetstream::kv::Config {
+            bucket: "avena_devices".to_string(),
+            ..Default::default()
+        })
+        .await
+        .expect("create devices bucket");
+    for id in [device_id, "ghost"] {
+        let device = Device {
+            id: id.to_string(),
+            version: "0.1.0".to_string(),
+            last_seen_ms: None,
+            uptime_ms: None,
+            nats_name: None,
+            pubkey: None,
+        };
+        kv.put(id, Vec::from(device).into()).await.expect("put device");
+    }
+
+    let identity = DeviceIdentity {
+        id: device_id.to_string(),
+        pubkey: "PUB".to_string(),
+        seed: "S".to_string(),
+        network_token: None,
+    };
+    let workloads = async_nats::jetstream::new(nc.clone())
+        .create_key_value(async_nats::jetstream::kv::Config {
+            bucket: "workloads".to_string(),
+            ..Default::default()
+        })
+        .await
+        .expect("create workloads bucket");
+    let workloads = Arc::new(Mutex::new(workloads));
+    let systemd_dir = tempfile::tempdir().unwrap();
+    let handle = {
+        let nc = nc.clone();
+        let hlc = Arc::new(HlcClock::new(device_id));
+        let systemd_dir = systemd_dir.path().to_path_buf();
+        tokio::spawn(async move {
+            avenad::serve_status(
+                nc,
+                BROADCAST_STATUS_SUBJECT.to_string(),
+                std::time::Instant::now(),
+                identity,
+                hlc,
+                workloads,
+                systemd_dir,
+                ImagePulls::default(),
+            )
+            .await
+            .unwrap();
+        })
+    };
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let client = avena::Avena::connect_with_auth(&nats.url, "auth", "auth")
+        .await
+        .expect("connect avena");
+    let report = client
+        .fleet_status(Duration::from_millis(500))
+        .await
+        .expect("fleet status");
+
+    assert_eq!(report.replies.len(), 1);
+    let reply = &report.replies[device_id];
+    assert_eq!(reply.response.device, device_id);
+    assert_eq!(reply.hlc.as_ref().map(|h| h.node_id.as_str()), Some(device_id));
+    assert_eq!(report.missing, vec!["ghost".to_string()]);
+
+    handle.abort();
+}
+
+/// Without a device registry yet, a fleet ping expects no device to answer.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn fleet_ping_without_a_registry_misses_no_device() {
+    let nats = match start_nats_server() {
+        Ok(n) => n,
+        Err(err) => {
+            eprintln!("Skipping test: failed to start nats-server ({err})");
+            return;
+        }
+    };
+
+    let client = avena::Avena::connect_with_auth(&nats.url, "auth", "auth")
+        .await
+        .expect("connect avena");
+    let report = client
+        .fleet_ping(Duration::from_millis(200))
+        .await
+        .expect("fleet ping");
+
+    assert!(report.replies.is_empty());
+    assert!(report.missing.is_empty());
+}
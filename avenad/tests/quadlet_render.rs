@@ -0,0 +1,264 @@
+//! Snapshot tests for the quadlet files rendered from a `WorkloadSpec`.
+//!
+//! Snapshots live in `tests/snapshots/`. After an intended change to the
+//! template, rerun with `UPDATE_SNAPSHOTS=1` and review the diff.
+
+use std::path::Path;
+
+use avena::messages::{
+    DependencyKind, DependencySpec, HealthCheckSpec, MountSpec, PermSpec, PortSpec, ResourceSpec,
+    SecretRef, VolumeRetention, VolumeSpec, WorkloadSpec,
+};
+use avenad::workload::WorkloadDeployment;
+
+fn assert_snapshot(name: &str, actual: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/snapshots")
+        .join(name);
+    if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        std::fs::write(&path, actual).unwrap();
+        return;
+    }
+
+    let expected = std::fs::read_to_string(&path).unwrap_or_else(|_| {
+        panic!(
+            "missing snapshot {}; rerun with UPDATE_SNAPSHOTS=1",
+            path.display()
+        )
+    });
+    assert_eq!(
+        actual, expected,
+        "snapshot {name} changed; rerun with UPDATE_SNAPSHOTS=1 to accept"
+    );
+}
+
+fn render(name: &str, spec: WorkloadSpec) -> Vec<(String, String)> {
+    WorkloadDeployment {
+        name: name.to_string(),
+        creds: None,
+        digest: None,
+        spec,
+    }
+    .render()
+    .unwrap()
+    .into_iter()
+    .map(|f| (f.file_name, f.contents))
+    .collect()
+}
+
+#[test]
+fn minimal_spec() {
+    let files = render(
+        "avena-hello",
+        WorkloadSpec::new("docker.io/library/hello-world"),
+    );
+    assert_eq!(files.len(), 1);
+    assert_eq!(files[0].0, "avena-hello.container");
+    assert_snapshot("minimal.container", &files[0].1);
+}
+
+#[test]
+fn full_spec() {
+    let files = render(
+        "avena-nginx",
+        WorkloadSpec {
+            tag: Some("1.27".to_string()),
+            cmd: Some("nginx".to_string()),
+            args: vec!["-g".to_string(), "daemon off;".to_string()],
+            env: vec![
+                ("MODE".to_string(), "field".to_string()),
+                ("GREETING".to_string(), "hello \"world\"".to_string()),
+            ],
+            mounts: vec![
+                MountSpec {
+                    host: "/srv/www".to_string(),
+                    container: "/usr/share/nginx/html".to_string(),
+                    readonly: true,
+                },
+                MountSpec {
+                    host: "/srv/logs".to_string(),
+                    container: "/var/log/nginx".to_string(),
+                    readonly: false,
+                },
+            ],
+            devices: vec!["/dev/ttyUSB0".to_string()],
+            perms: PermSpec {
+                publish: vec!["sensors.>".to_string(), "status.nginx".to_string()],
+                subscribe: vec!["config.nginx".to_string()],
+            },
+            ports: vec![PortSpec {
+                container: 80,
+                host: 8080,
+            }],
+            volumes: vec![
+                VolumeSpec {
+                    name: "nginx-cache".to_string(),
+                    path: "/var/cache/nginx".to_string(),
+                    readonly: false,
+                },
+                VolumeSpec {
+                    name: "nginx-certs".to_string(),
+                    path: "/etc/nginx/certs".to_string(),
+                    readonly: true,
+                },
+            ],
+            health: Some(HealthCheckSpec {
+                cmd: "curl -f http://localhost/".to_string(),
+                interval_ms: Some(30_000),
+                timeout_ms: Some(5_000),
+                retries: Some(3),
+                start_period_ms: Some(10_000),
+            }),
+            resources: ResourceSpec {
+                memory_max: Some(256 * 1024 * 1024),
+                cpu_quota_percent: Some(50),
+                cpu_weight: Some(200),
+                pids_max: Some(512),
+                io_weight: Some(50),
+            },
+            depends_on: vec![
+                DependencySpec {
+                    workload: "postgres".to_string(),
+                    kind: DependencyKind::Requires,
+                },
+                DependencySpec {
+                    workload: "avena-metrics".to_string(),
+                    kind: DependencyKind::Wants,
+                },
+            ],
+            volume_retention: VolumeRetention::Purge,
+            secrets: vec![
+                SecretRef {
+                    name: "nginx-tls-key".to_string(),
+                    env: None,
+                },
+                SecretRef {
+                    name: "api-token".to_string(),
+                    env: Some("API_TOKEN".to_string()),
+                },
+            ],
+            ..WorkloadSpec::new("docker.io/library/nginx")
+        },
+    );
+
+    let names: Vec<_> = files.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(
+        names,
+        [
+            "avena-nginx.container",
+            "nginx-cache.volume",
+            "nginx-certs.volume"
+        ]
+    );
+    assert_snapshot("full.container", &files[0].1);
+}
+
+#[test]
+fn values_cannot_inject_directives() {
+    let files = render(
+        "avena-evil",
+        WorkloadSpec {
+            cmd: Some("sh\nExecStartPre=/bin/rm -rf /".to_string()),
+            args: vec!["50%\n[Service]".to_string(), "trailing\\".to_string()],
+            env: vec![(
+                "PAYLOAD".to_string(),
+                "x\nPodmanArgs=--privileged".to_string(),
+            )],
+            devices: vec!["/dev/null\nAddCapability=ALL".to_string()],
+            ..WorkloadSpec::new("docker.io/library/busybox\nNetwork=host")
+        },
+    );
+    let quadlet = &files[0].1;
+
+    for line in quadlet.lines() {
+        for key in ["ExecStartPre=", "PodmanArgs=", "AddCapability=", "Network="] {
+            assert!(!line.starts_with(key), "injected {line:?}");
+        }
+    }
+    assert_eq!(quadlet.matches("[Service]\n").count(), 1);
+    assert_snapshot("escaped.container", quadlet);
+}
+
+#[test]
+fn rejects_out_of_range_weights() {
+    for resources in [
+        ResourceSpec {
+            cpu_weight: Some(0),
+            ..Default::default()
+        },
+        ResourceSpec {
+            io_weight: Some(10_001),
+            ..Default::default()
+        },
+        ResourceSpec {
+            cpu_quota_percent: Some(0),
+            ..Default::default()
+        },
+    ] {
+        let deployment = WorkloadDeployment {
+            name: "avena-nginx".to_string(),
+            creds: None,
+            digest: None,
+            spec: WorkloadSpec {
+                resources,
+                ..WorkloadSpec::new("docker.io/library/nginx")
+            },
+        };
+        assert!(deployment.render().is_err());
+    }
+}
+
+#[test]
+fn rejects_names_that_escape_the_systemd_dir() {
+    let bad = WorkloadDeployment {
+        name: "../evil".to_string(),
+        creds: None,
+        digest: None,
+        spec: WorkloadSpec::new("docker.io/library/busybox"),
+    };
+    assert!(bad.render().is_err());
+
+    let bad_volume = WorkloadDeployment {
+        name: "avena-ok".to_string(),
+        creds: None,
+        digest: None,
+        spec: WorkloadSpec {
+            volumes: vec![VolumeSpec {
+                name: "../../etc/passwd".to_string(),
+                path: "/data".to_string(),
+                readonly: false,
+            }],
+            ..WorkloadSpec::new("docker.io/library/busybox")
+        },
+    };
+    assert!(bad_volume.render().is_err());
+}
+
+#[test]
+fn rejects_secrets_that_add_options() {
+    for secret in [
+        SecretRef {
+            name: "db,type=env,target=PATH".to_string(),
+            env: None,
+        },
+        SecretRef {
+            name: "db".to_string(),
+            env: Some("PASSWORD,uid=0".to_string()),
+        },
+        SecretRef {
+            name: "db".to_string(),
+            env: Some("1PASSWORD".to_string()),
+        },
+    ] {
+        let deployment = WorkloadDeployment {
+            name: "avena-app".to_string(),
+            creds: None,
+            digest: None,
+            spec: WorkloadSpec {
+                secrets: vec![secret],
+                ..WorkloadSpec::new("docker.io/library/busybox")
+            },
+        };
+        assert!(deployment.render().is_err());
+    }
+}
@@ -0,0 +1,61 @@
+//! Quadlet diffing used by the workload reconciler to decide what to restart.
+
+use avena::messages::{PortSpec, VolumeSpec, WorkloadSpec};
+use avenad::workload::{ReconcilePlan, UnitChange, WorkloadDeployment};
+
+fn deployment(host_port: u16) -> WorkloadDeployment {
+    WorkloadDeployment {
+        name: "avena-nginx".to_string(),
+        creds: None,
+        digest: None,
+        spec: WorkloadSpec {
+            tag: Some("1.27".to_string()),
+            ports: vec![PortSpec {
+                container: 80,
+                host: host_port,
+            }],
+            volumes: vec![VolumeSpec {
+                name: "cache".to_string(),
+                path: "/var/cache/nginx".to_string(),
+                readonly: false,
+            }],
+            ..WorkloadSpec::new("docker.io/nginx")
+        },
+    }
+}
+
+#[tokio::test]
+async fn diff_tracks_quadlet_content() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let nginx = deployment(8080);
+    assert_eq!(nginx.diff(dir.path()).await.unwrap(), UnitChange::Created);
+
+    nginx.deploy(dir.path()).await.unwrap();
+    assert_eq!(nginx.diff(dir.path()).await.unwrap(), UnitChange::Unchanged);
+
+    let moved = deployment(9090);
+    assert_eq!(moved.diff(dir.path()).await.unwrap(), UnitChange::Updated);
+
+    std::fs::remove_file(dir.path().join("cache.volume")).unwrap();
+    assert_eq!(nginx.diff(dir.path()).await.unwrap(), UnitChange::Updated);
+}
+
+#[test]
+fn plan_groups_units_by_change() {
+    let mut plan = ReconcilePlan::default();
+    assert!(!plan.needs_reload());
+
+    plan.record("avena-nats.service".to_string(), UnitChange::Unchanged);
+    assert!(!plan.needs_reload());
+
+    plan.record("avena-nginx.service".to_string(), UnitChange::Updated);
+    plan.removed.push("avena-old.service".to_string());
+    assert!(plan.needs_reload());
+    assert!(plan.is_desired("avena-nats.service"));
+    assert!(!plan.is_desired("avena-old.service"));
+    assert_eq!(
+        plan.to_string(),
+        "0 created, 1 updated, 1 unchanged, 1 removed"
+    );
+}
@@ -0,0 +1,72 @@
+//! Reported workload state replicates over leaf links, so it stays readable
+//! from other nodes.
+
+use std::time::Duration;
+
+use avena::messages::{ResourceSpec, WorkloadState, WorkloadStatus, WorkloadSync};
+use avena::reported::StateReporter;
+use avena::test_utils::start_nats_server;
+
+fn running(name: &str) -> WorkloadState {
+    WorkloadState {
+        name: name.to_string(),
+        state: WorkloadStatus::Running,
+        exit_code: None,
+        restart_count: 0,
+        started_at: Some(1_700_000_000_000),
+        pid: Some(4242),
+        image: "docker.io/library/nginx".to_string(),
+        health: None,
+        limits: ResourceSpec::default(),
+        usage: None,
+        spec: None,
+        sync: WorkloadSync::InSync,
+        digest: None,
+        pull: None,
+        rollback: None,
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn reported_state_is_readable_from_other_nodes() {
+    let cluster = avena_test::cluster::TestCluster::with_hub(2).unwrap();
+
+    let device = cluster.connect_avena("node1").await.unwrap();
+    let reporter = StateReporter::open(device.js(), device.hlc(), "node1")
+        .await
+        .unwrap();
+    let first = reporter.report(vec![running("nginx")]).await.unwrap();
+    let second = reporter
+        .report(vec![running("nginx"), running("postgres")])
+        .await
+        .unwrap();
+    assert!(second.timestamp.is_newer_than(&first.timestamp));
+
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let operator = cluster.connect_avena("node2").await.unwrap();
+    let state = operator.reported_state("node1").await.unwrap().unwrap();
+    assert_eq!(state, second);
+    assert!(operator.reported_state("node2").await.unwrap().is_none());
+
+    let states = operator.reported_states().await.unwrap();
+    assert_eq!(states.len(), 1);
+    assert_eq!(states[0].workloads.len(), 2);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn no_reports_before_any_device_reported() {
+    let nats = match start_nats_server() {
+        Ok(n) => n,
+        Err(err) => {
+            eprintln!("Skipping test: failed to start nats-server ({err})");
+            return;
+        }
+    };
+
+    let operator = avena::Avena::connect_with_auth(&nats.url, "auth", "auth")
+        .await
+        .expect("connect avena");
+    assert!(operator.reported_state("node1").await.unwrap().is_none());
+    assert!(operator.reported_states().await.unwrap().is_empty());
+}
@@ -0,0 +1,169 @@
+//! The RPC layer against a live server: error envelopes, deadlines and HLC
+//! propagation in both directions.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use avena::hlc::{HlcClock, HybridTimestamp};
+use avena::messages::{ErrorCode, ErrorResponse, Message, PingRequest, PingResponse};
+use avena::rpc::{RpcClient, RpcServer};
+use avena::test_utils::start_nats_server;
+use avena::Error;
+
+async fn connect(url: &str) -> async_nats::Client {
+    async_nats::ConnectOptions::with_user_and_password("auth".into(), "auth".into())
+        .connect(url)
+        .await
+        .expect("connect nats")
+}
+
+fn pong(device: &str) -> PingResponse {
+    PingResponse {
+        device: device.to_string(),
+        avena_version: "0.1.0".to_string(),
+        uptime_ms: 0,
+        nats_name: String::new(),
+    }
+}
+
+/// A timestamp `ahead_ms` past the current wall time.
+fn ahead(ahead_ms: u64) -> HybridTimestamp {
+    let now = HybridTimestamp::now("elsewhere", None);
+    HybridTimestamp {
+        wall_time_ms: now.wall_time_ms + ahead_ms,
+        ..now
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn error_replies_become_remote_errors() {
+    let nats = match start_nats_server() {
+        Ok(n) => n,
+        Err(err) => {
+            eprintln!("Skipping test: failed to start nats-server ({err})");
+            return;
+        }
+    };
+    let nc = connect(&nats.url).await;
+
+    let server = RpcServer::new(nc.clone(), Arc::new(HlcClock::new("server")));
+    let handle = tokio::spawn(async move {
+        server
+            .serve("rpc.test.error", |_: PingRequest, _| async {
+                Err::<PingResponse, _>(ErrorResponse::unauthorized("no pings today"))
+            })
+            .await
+            .unwrap();
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let client = RpcClient::new(nc, Arc::new(HlcClock::new("client")));
+    let result = client
+        .request::<_, PingResponse>("rpc.test.error", &PingRequest {})
+        .await;
+    match result {
+        Err(Error::Remote {
+            subject,
+            code,
+            message,
+        }) => {
+            assert_eq!(subject, "rpc.test.error");
+            assert_eq!(code, ErrorCode::Unauthorized);
+            assert_eq!(message, "no pings today");
+        }
+        other => panic!("expected a remote error, got {other:?}"),
+    }
+
+    handle.abort();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn slow_handlers_reply_deadline_exceeded() {
+    let nats = match start_nats_server() {
+        Ok(n) => n,
+        Err(err) => {
+            eprintln!("Skipping test: failed to start nats-server ({err})");
+            return;
+        }
+    };
+    let nc = connect(&nats.url).await;
+
+    let server = RpcServer::new(nc.clone(), Arc::new(HlcClock::new("server")));
+    let handle = tokio::spawn(async move {
+        server
+            .serve("rpc.test.slow", |_: PingRequest, _| async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                Ok(pong("server"))
+            })
+            .await
+            .unwrap();
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // Announce a short deadline but keep waiting, so the server's error
+    // envelope arrives instead of a local timeout
+    let mut headers = async_nats::HeaderMap::new();
+    headers.insert("Avena-Timeout", "200");
+    let request = async_nats::Request::new()
+        .payload(PingRequest {}.encode().into())
+        .headers(headers)
+        .timeout(Some(Duration::from_secs(3)));
+    let reply = nc
+        .send_request("rpc.test.slow", request)
+        .await
+        .expect("error reply");
+
+    let marker = reply.headers.as_ref().and_then(|h| h.get("Avena-Error"));
+    assert_eq!(marker.map(|v| v.as_str()), Some("deadline_exceeded"));
+    let err = ErrorResponse::decode(&reply.payload).expect("decode error");
+    assert_eq!(err.code, ErrorCode::DeadlineExceeded);
+
+    handle.abort();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn both_sides_merge_hlc() {
+    let nats = match start_nats_server() {
+        Ok(n) => n,
+        Err(err) => {
+            eprintln!("Skipping test: failed to start nats-server ({err})");
+            return;
+        }
+    };
+    let nc = connect(&nats.url).await;
+
+    let server_hlc = Arc::new(HlcClock::new("server"));
+    let server = RpcServer::new(nc.clone(), server_hlc.clone());
+    let handle = tokio::spawn(async move {
+        server
+            .serve("rpc.test.hlc", |_: PingRequest, _| async {
+                Ok(pong("server"))
+            })
+            .await
+            .unwrap();
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let client_hlc = Arc::new(HlcClock::new("client"));
+    let client = RpcClient::new(nc, client_hlc.clone());
+
+    // The server picks up a client clock that runs ahead
+    let client_ahead = ahead(20_000);
+    client_hlc.receive(&client_ahead);
+    client
+        .request::<_, PingResponse>("rpc.test.hlc", &PingRequest {})
+        .await
+        .expect("first request");
+    assert!(server_hlc.current().wall_time_ms >= client_ahead.wall_time_ms);
+
+    // And the client picks up the server's once that is further ahead
+    let server_ahead = ahead(40_000);
+    server_hlc.receive(&server_ahead);
+    client
+        .request::<_, PingResponse>("rpc.test.hlc", &PingRequest {})
+        .await
+        .expect("second request");
+    assert!(client_hlc.current().wall_time_ms >= server_ahead.wall_time_ms);
+
+    handle.abort();
+}
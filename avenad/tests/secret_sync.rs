@@ -0,0 +1,90 @@
+//! Which podman secrets a device writes or removes for its sealed secrets.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use avena::secrets::{device_secrets, seal, secret_key, secrets_bucket, watch_device_secrets};
+use avena::test_utils::start_nats_server;
+use avenad::secrets::{sealed_hash, secret_changes, SecretChange};
+use futures::StreamExt;
+
+fn hashes(entries: &[(&str, &str)]) -> BTreeMap<String, String> {
+    entries
+        .iter()
+        .map(|(name, hash)| (name.to_string(), hash.to_string()))
+        .collect()
+}
+
+#[test]
+fn only_changed_secrets_are_written() {
+    let desired = hashes(&[("db-password", "a"), ("api-token", "b"), ("tls-key", "c")]);
+    let existing = hashes(&[("db-password", "a"), ("api-token", "old"), ("stale", "d")]);
+
+    let changes: Vec<_> = secret_changes(&desired, &existing).into_iter().collect();
+    assert_eq!(
+        changes,
+        [
+            ("api-token".to_string(), SecretChange::Updated),
+            ("stale".to_string(), SecretChange::Removed),
+            ("tls-key".to_string(), SecretChange::Created),
+        ]
+    );
+}
+
+#[test]
+fn sealing_again_changes_the_hash() {
+    let device = nkeys::KeyPair::new_user();
+    let first = seal("dev1", "token", &device.public_key(), b"same", None).unwrap();
+    let second = seal("dev1", "token", &device.public_key(), b"same", None).unwrap();
+
+    assert_ne!(sealed_hash(&first), sealed_hash(&second));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn secrets_put_after_the_watch_starts_are_seen() {
+    let nats = match start_nats_server() {
+        Ok(n) => n,
+        Err(err) => {
+            eprintln!("Skipping test: failed to start nats-server ({err})");
+            return;
+        }
+    };
+    let nc = async_nats::ConnectOptions::with_user_and_password("auth".into(), "auth".into())
+        .connect(&nats.url)
+        .await
+        .expect("connect nats");
+    let kv = secrets_bucket(&async_nats::jetstream::new(nc))
+        .await
+        .unwrap();
+    let device = nkeys::KeyPair::new_user();
+    let other = nkeys::KeyPair::new_user();
+
+    let mut watcher = watch_device_secrets(&kv, "dev1").await.unwrap();
+    for (id, key, name) in [
+        ("dev1", &device, "db-password"),
+        ("dev2", &other, "api-token"),
+    ] {
+        let sealed = seal(id, name, &key.public_key(), b"hunter2", None).unwrap();
+        kv.put(secret_key(id, name), Vec::from(sealed).into())
+            .await
+            .unwrap();
+    }
+
+    let entry = tokio::time::timeout(Duration::from_secs(5), watcher.next())
+        .await
+        .expect("watch saw the new secret")
+        .unwrap()
+        .unwrap();
+    assert_eq!(entry.key, secret_key("dev1", "db-password"));
+
+    let names: Vec<_> = device_secrets(&kv, "dev1")
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|secret| secret.name)
+        .collect();
+    assert_eq!(names, ["db-password"]);
+
+    kv.purge(secret_key("dev1", "db-password")).await.unwrap();
+    assert!(device_secrets(&kv, "dev1").await.unwrap().is_empty());
+}
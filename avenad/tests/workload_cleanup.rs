@@ -0,0 +1,76 @@
+//! Cleanup of the quadlet files and volumes left by removed workloads.
+
+use std::collections::HashSet;
+
+use avena::messages::{VolumeRetention, VolumeSpec, WorkloadSpec};
+use avenad::workload::{
+    removed_workloads, ReconcilePlan, RemovedWorkload, UnitChange, WorkloadDeployment,
+};
+
+fn deployment(
+    name: &str,
+    volumes: &[&str],
+    volume_retention: VolumeRetention,
+) -> WorkloadDeployment {
+    WorkloadDeployment {
+        name: name.to_string(),
+        creds: None,
+        digest: None,
+        spec: WorkloadSpec {
+            volumes: volumes
+                .iter()
+                .map(|name| VolumeSpec {
+                    name: name.to_string(),
+                    path: format!("/{name}"),
+                    readonly: false,
+                })
+                .collect(),
+            volume_retention,
+            ..WorkloadSpec::new("docker.io/library/postgres")
+        },
+    }
+}
+
+#[test]
+fn quadlet_records_volumes_and_retention() {
+    let db = deployment("avena-db", &["pgdata", "pgwal"], VolumeRetention::Purge);
+    let files = db.render().unwrap();
+
+    let removed = RemovedWorkload::from_quadlet("avena-db", &files[0].contents);
+    assert_eq!(removed.volumes, ["pgdata", "pgwal"]);
+    assert_eq!(removed.retention, VolumeRetention::Purge);
+
+    let kept = deployment("avena-db", &["pgdata"], VolumeRetention::Retain);
+    let files = kept.render().unwrap();
+    let removed = RemovedWorkload::from_quadlet("avena-db", &files[0].contents);
+    assert_eq!(removed.retention, VolumeRetention::Retain);
+}
+
+#[tokio::test]
+async fn removes_undesired_quadlets_but_shared_volumes() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = deployment("avena-db", &["pgdata", "shared"], VolumeRetention::Purge);
+    let web = deployment("avena-web", &["shared"], VolumeRetention::Retain);
+    db.deploy(dir.path()).await.unwrap();
+    web.deploy(dir.path()).await.unwrap();
+
+    let mut plan = ReconcilePlan::default();
+    plan.record(web.unit_name(), UnitChange::Unchanged);
+    let stale = removed_workloads(dir.path(), &plan).await.unwrap();
+    assert_eq!(stale.len(), 1);
+    assert_eq!(stale[0].unit_name(), "avena-db.service");
+
+    let in_use = HashSet::from(["shared".to_string()]);
+    let cleanup = stale[0].remove_files(dir.path(), &in_use).await.unwrap();
+    assert_eq!(cleanup.files, ["avena-db.container", "pgdata.volume"]);
+    assert_eq!(cleanup.purged, ["pgdata"]);
+    assert!(cleanup.retained.is_empty());
+
+    assert!(!dir.path().join("avena-db.container").exists());
+    assert!(dir.path().join("shared.volume").exists());
+    assert!(dir.path().join("avena-web.container").exists());
+    assert!(removed_workloads(dir.path(), &plan)
+        .await
+        .unwrap()
+        .is_empty());
+}
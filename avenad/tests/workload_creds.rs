@@ -0,0 +1,224 @@
+//! Per-workload NATS creds: issuing, rotating and revoking users, and
+//! mounting the creds file into the container.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use avena::messages::{PermSpec, VolumeRetention, WorkloadSpec};
+use avenad::creds::{CredsChange, WorkloadCreds, CREDS_ENV, CREDS_MOUNT, CREDS_TTL};
+use avenad::nats_jwt::{load_revocations, AccountClaims, NatsJwtManager, UserClaims};
+use avenad::workload::{deployed_creds, WorkloadDeployment};
+use nkeys::KeyPair;
+
+fn perms(publish: &[&str]) -> PermSpec {
+    PermSpec {
+        publish: publish.iter().map(|s| s.to_string()).collect(),
+        subscribe: vec!["sensors.>".to_string()],
+    }
+}
+
+fn workload_creds(root: &Path) -> WorkloadCreds {
+    WorkloadCreds::new(
+        root.join("creds"),
+        root.join("nats"),
+        Arc::new(NatsJwtManager::new().unwrap()),
+        Arc::new(KeyPair::new_account()),
+    )
+}
+
+/// Public key of the user last issued to `name`.
+fn issued_pubkey(root: &Path, name: &str) -> String {
+    let user = std::fs::read(root.join(format!("creds/{name}.user"))).unwrap();
+    let user: serde_json::Value = serde_json::from_slice(&user).unwrap();
+    user["pubkey"].as_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn issues_rotates_and_revokes_users() {
+    let root = tempfile::tempdir().unwrap();
+    let creds = workload_creds(root.path());
+
+    let change = creds
+        .issue("avena-sensor", &perms(&["data.>"]), false)
+        .await;
+    assert_eq!(change.unwrap(), CredsChange::Issued);
+    let file = std::fs::read_to_string(creds.path("avena-sensor")).unwrap();
+    assert!(file.contains("BEGIN NATS USER JWT"));
+    assert!(file.contains("BEGIN USER NKEY SEED"));
+    let first = issued_pubkey(root.path(), "avena-sensor");
+
+    let change = creds
+        .issue("avena-sensor", &perms(&["data.>"]), false)
+        .await;
+    assert_eq!(change.unwrap(), CredsChange::Unchanged);
+    assert_eq!(issued_pubkey(root.path(), "avena-sensor"), first);
+
+    // New permissions mean a new user, and the old one is revoked
+    let change = creds
+        .issue("avena-sensor", &perms(&["other.>"]), false)
+        .await;
+    assert_eq!(change.unwrap(), CredsChange::Rotated);
+    let second = issued_pubkey(root.path(), "avena-sensor");
+    assert_ne!(second, first);
+
+    let change = creds
+        .issue("avena-sensor", &perms(&["other.>"]), true)
+        .await;
+    assert_eq!(change.unwrap(), CredsChange::Rotated);
+
+    let revoked = load_revocations(&root.path().join("nats")).unwrap();
+    assert!(revoked.contains_key(&first));
+    assert!(revoked.contains_key(&second));
+
+    assert_eq!(
+        creds
+            .holders()
+            .await
+            .unwrap()
+            .into_iter()
+            .collect::<Vec<_>>(),
+        ["avena-sensor"]
+    );
+    assert!(creds.revoke("avena-sensor").await.unwrap());
+    assert!(!creds.revoke("avena-sensor").await.unwrap());
+    assert!(!creds.path("avena-sensor").exists());
+    assert!(creds.holders().await.unwrap().is_empty());
+}
+
+fn unix_now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+/// Claims of the JWT between the first two lines of a creds file.
+fn creds_claims(creds: &str) -> UserClaims {
+    let jwt = creds.lines().nth(1).unwrap();
+    let payload = jwt.split('.').nth(1).unwrap();
+    let payload = data_encoding::BASE64URL_NOPAD
+        .decode(payload.as_bytes())
+        .unwrap();
+    serde_json::from_slice(&payload).unwrap()
+}
+
+#[tokio::test]
+async fn users_expire_and_are_renewed_before_they_do() {
+    let root = tempfile::tempdir().unwrap();
+    let creds = workload_creds(root.path());
+
+    creds
+        .issue("avena-sensor", &perms(&["data.>"]), false)
+        .await
+        .unwrap();
+    let file = std::fs::read_to_string(creds.path("avena-sensor")).unwrap();
+    let exp = creds_claims(&file).exp.expect("user JWT expires");
+    assert!((exp - unix_now() - CREDS_TTL.as_secs() as i64).abs() < 60);
+    let first = issued_pubkey(root.path(), "avena-sensor");
+
+    // With a day left the user is replaced, but not revoked
+    let user_path = root.path().join("creds/avena-sensor.user");
+    let mut user: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(&user_path).unwrap()).unwrap();
+    user["expires"] = (unix_now() + 24 * 60 * 60).into();
+    std::fs::write(&user_path, serde_json::to_vec(&user).unwrap()).unwrap();
+
+    let change = creds
+        .issue("avena-sensor", &perms(&["data.>"]), false)
+        .await;
+    assert_eq!(change.unwrap(), CredsChange::Renewed);
+    assert_ne!(issued_pubkey(root.path(), "avena-sensor"), first);
+    assert!(!load_revocations(&root.path().join("nats"))
+        .unwrap()
+        .contains_key(&first));
+
+    let change = creds
+        .issue("avena-sensor", &perms(&["data.>"]), false)
+        .await;
+    assert_eq!(change.unwrap(), CredsChange::Unchanged);
+}
+
+#[tokio::test]
+async fn revocations_of_expired_users_are_dropped() {
+    let root = tempfile::tempdir().unwrap();
+    let creds = workload_creds(root.path());
+    let nats_dir = root.path().join("nats");
+    let recent = unix_now() - 60;
+    std::fs::create_dir_all(&nats_dir).unwrap();
+    std::fs::write(
+        nats_dir.join("AVENA.revocations.json"),
+        serde_json::json!({ "UEXPIRED": 0, "URECENT": recent }).to_string(),
+    )
+    .unwrap();
+
+    creds
+        .issue("avena-sensor", &perms(&["data.>"]), false)
+        .await
+        .unwrap();
+    let pubkey = issued_pubkey(root.path(), "avena-sensor");
+    creds.revoke("avena-sensor").await.unwrap();
+
+    let revoked = load_revocations(&nats_dir).unwrap();
+    assert!(!revoked.contains_key("UEXPIRED"));
+    assert!(revoked.contains_key("URECENT"));
+    assert!(revoked.contains_key(&pubkey));
+}
+
+#[tokio::test]
+async fn account_jwt_lists_revoked_users() {
+    let root = tempfile::tempdir().unwrap();
+    let creds = workload_creds(root.path());
+
+    creds
+        .issue("avena-sensor", &perms(&["data.>"]), false)
+        .await
+        .unwrap();
+    let pubkey = issued_pubkey(root.path(), "avena-sensor");
+    creds.revoke("avena-sensor").await.unwrap();
+    creds.publish_revocations().await.unwrap();
+
+    let jwt = std::fs::read_to_string(root.path().join("nats/AVENA.jwt")).unwrap();
+    let payload = jwt.split('.').nth(1).unwrap();
+    let payload = data_encoding::BASE64URL_NOPAD
+        .decode(payload.as_bytes())
+        .unwrap();
+    let claims: AccountClaims = serde_json::from_slice(&payload).unwrap();
+    assert!(claims.nats.revocations.contains_key(&pubkey));
+}
+
+#[test]
+fn only_workloads_with_permissions_want_creds() {
+    assert!(!WorkloadCreds::wanted(&PermSpec::default()));
+    assert!(WorkloadCreds::wanted(&perms(&[])));
+}
+
+#[tokio::test]
+async fn creds_are_mounted_and_named_in_the_environment() {
+    let systemd_dir = tempfile::tempdir().unwrap();
+    let path = PathBuf::from("/var/lib/avena/creds/avena-sensor.creds");
+    let deployment = WorkloadDeployment {
+        name: "avena-sensor".to_string(),
+        creds: Some(path.clone()),
+        digest: None,
+        spec: WorkloadSpec {
+            perms: perms(&["data.>"]),
+            volume_retention: VolumeRetention::Retain,
+            ..WorkloadSpec::new("docker.io/library/busybox")
+        },
+    };
+
+    let files = deployment.render().unwrap();
+    let quadlet = &files[0].contents;
+    assert!(quadlet.contains(&format!("Volume={}:{CREDS_MOUNT}:ro\n", path.display())));
+    assert!(quadlet.contains(&format!("Environment=\"{CREDS_ENV}={CREDS_MOUNT}\"\n")));
+
+    deployment.deploy(systemd_dir.path()).await.unwrap();
+    assert_eq!(
+        deployed_creds(systemd_dir.path(), "avena-sensor").await,
+        Some(path)
+    );
+    assert_eq!(
+        deployed_creds(systemd_dir.path(), "avena-other").await,
+        None
+    );
+}
@@ -0,0 +1,83 @@
+//! Startup ordering and cycle detection for workload dependencies.
+
+use std::collections::BTreeMap;
+
+use avena::messages::DependencyKind::{Requires, Wants};
+use avena::messages::{DependencyKind, DependencySpec, WorkloadSpec};
+use avenad::workload::{resolve_dependencies, DependencyError};
+
+fn workloads(deps: &[(&str, &[(&str, DependencyKind)])]) -> BTreeMap<String, WorkloadSpec> {
+    deps.iter()
+        .map(|(name, depends_on)| {
+            let spec = WorkloadSpec {
+                depends_on: depends_on
+                    .iter()
+                    .map(|(workload, kind)| DependencySpec {
+                        workload: workload.to_string(),
+                        kind: *kind,
+                    })
+                    .collect(),
+                ..WorkloadSpec::new(format!("docker.io/library/{name}"))
+            };
+            (name.to_string(), spec)
+        })
+        .collect()
+}
+
+#[test]
+fn dependencies_start_first() {
+    let (order, rejected) = resolve_dependencies(&workloads(&[
+        ("ingest", &[("postgres", Requires), ("metrics", Wants)]),
+        ("metrics", &[]),
+        ("postgres", &[]),
+    ]));
+
+    assert!(rejected.is_empty());
+    let position = |name| order.iter().position(|n| n == name).unwrap();
+    assert!(position("postgres") < position("ingest"));
+    assert!(position("metrics") < position("ingest"));
+}
+
+#[test]
+fn cycles_are_rejected() {
+    let (order, rejected) = resolve_dependencies(&workloads(&[
+        ("a", &[("b", Requires)]),
+        ("b", &[("c", Wants)]),
+        ("c", &[("a", Requires)]),
+        ("d", &[("d", Wants)]),
+        ("standalone", &[]),
+    ]));
+
+    assert_eq!(order, ["standalone"]);
+    assert_eq!(
+        rejected["a"],
+        DependencyError::Cycle(vec!["a".into(), "b".into(), "c".into(), "a".into()])
+    );
+    assert_eq!(
+        rejected["c"].to_string(),
+        "dependency cycle c -> a -> b -> c"
+    );
+    assert_eq!(
+        rejected["d"],
+        DependencyError::Cycle(vec!["d".into(), "d".into()])
+    );
+}
+
+#[test]
+fn missing_requirement_holds_back_dependents() {
+    let (order, rejected) = resolve_dependencies(&workloads(&[
+        ("ingest", &[("postgres", Requires)]),
+        ("dashboard", &[("ingest", Requires)]),
+        ("exporter", &[("ingest", Wants)]),
+    ]));
+
+    assert_eq!(order, ["exporter"]);
+    assert_eq!(
+        rejected["ingest"],
+        DependencyError::Unavailable("postgres".into())
+    );
+    assert_eq!(
+        rejected["dashboard"].to_string(),
+        "requires ingest, which is not deployed"
+    );
+}
@@ -0,0 +1,40 @@
+//! Reading container health and image digest out of `podman container
+//! inspect`.
+
+use avena::messages::WorkloadHealth;
+use avenad::workload::{digest_from_inspect, health_from_inspect};
+
+#[test]
+fn reads_podman_5_health() {
+    let inspect = br#"[{"Name":"avena-nginx","State":{"Status":"running","Health":{"Status":"unhealthy","FailingStreak":3}}}]"#;
+    assert_eq!(
+        health_from_inspect(inspect),
+        Some(WorkloadHealth::Unhealthy)
+    );
+}
+
+#[test]
+fn reads_podman_4_healthcheck() {
+    let inspect = br#"[{"State":{"Healthcheck":{"Status":"starting"}}}]"#;
+    assert_eq!(health_from_inspect(inspect), Some(WorkloadHealth::Starting));
+}
+
+#[test]
+fn no_health_check() {
+    let inspect = br#"[{"State":{"Status":"running","Health":{"Status":""}}}]"#;
+    assert_eq!(health_from_inspect(inspect), None);
+    assert_eq!(health_from_inspect(br#"[{"State":{}}]"#), None);
+    assert_eq!(health_from_inspect(b"[]"), None);
+    assert_eq!(health_from_inspect(b"Error: no such container"), None);
+}
+
+#[test]
+fn reads_image_digest() {
+    let inspect = br#"[{"Name":"avena-nginx","ImageDigest":"sha256:4c0fdaa8b6341bfdeca5f18f7837462c80cff90527ee35ef185571e1c327beac"}]"#;
+    assert_eq!(
+        digest_from_inspect(inspect).as_deref(),
+        Some("sha256:4c0fdaa8b6341bfdeca5f18f7837462c80cff90527ee35ef185571e1c327beac")
+    );
+    assert_eq!(digest_from_inspect(br#"[{"ImageDigest":""}]"#), None);
+    assert_eq!(digest_from_inspect(b"Error: no such container"), None);
+}
@@ -0,0 +1,149 @@
+//! Deciding when an updated workload is kept or rolled back, and the
+//! known-good state kept for it.
+
+use std::time::{Duration, Instant};
+
+use avena::hlc::HybridTimestamp;
+use avena::messages::{WorkloadHealth, WorkloadRollback, WorkloadSpec};
+use avenad::rollback::{
+    forget, load_state, save_state, stabilize_period, KnownGood, RollbackState, Trial, UnitCheck,
+    Verdict,
+};
+use avenad::workload::QuadletFile;
+
+const PERIOD: Duration = Duration::from_secs(60);
+
+fn spec(tag: &str) -> WorkloadSpec {
+    WorkloadSpec {
+        tag: Some(tag.to_string()),
+        ..WorkloadSpec::new("docker.io/library/nginx")
+    }
+}
+
+fn trial(started: Instant) -> Trial {
+    let files = vec![QuadletFile {
+        file_name: "avena-nginx.container".to_string(),
+        contents: "[Container]\nImage=docker.io/library/nginx:1.28\n".to_string(),
+    }];
+    Trial::new("nginx".to_string(), spec("1.28"), files, PERIOD, started)
+}
+
+fn check(active_state: &str, restarts: u32, health: Option<WorkloadHealth>) -> UnitCheck {
+    UnitCheck {
+        active_state: active_state.to_string(),
+        restarts,
+        health,
+    }
+}
+
+#[test]
+fn update_that_stays_up_becomes_known_good() {
+    let started = Instant::now();
+    let mut trial = trial(started);
+
+    assert_eq!(
+        trial.judge(&check("active", 0, None), started),
+        Verdict::Pending
+    );
+    assert_eq!(
+        trial.judge(&check("active", 0, None), started + PERIOD),
+        Verdict::Stable
+    );
+}
+
+#[test]
+fn health_check_must_pass_before_it_is_kept() {
+    let started = Instant::now();
+    let mut trial = trial(started);
+    let later = started + PERIOD * 2;
+
+    let starting = check("active", 0, Some(WorkloadHealth::Starting));
+    assert_eq!(trial.judge(&starting, later), Verdict::Pending);
+    let healthy = check("active", 0, Some(WorkloadHealth::Healthy));
+    assert_eq!(trial.judge(&healthy, later), Verdict::Stable);
+    let unhealthy = check("active", 0, Some(WorkloadHealth::Unhealthy));
+    assert_eq!(
+        trial.judge(&unhealthy, later),
+        Verdict::Failed("health check failed".to_string())
+    );
+}
+
+#[test]
+fn failing_or_restarting_units_are_rolled_back() {
+    let started = Instant::now();
+
+    let mut failed = trial(started);
+    assert_eq!(
+        failed.judge(&check("failed", 0, None), started),
+        Verdict::Failed("unit failed".to_string())
+    );
+
+    // Restarts counted before the first check are not the update's
+    let mut restarting = trial(started);
+    assert_eq!(
+        restarting.judge(&check("active", 2, None), started),
+        Verdict::Pending
+    );
+    assert_eq!(
+        restarting.judge(&check("activating", 4, None), started),
+        Verdict::Failed("unit restarted 2 times".to_string())
+    );
+
+    let mut stopped = trial(started);
+    assert_eq!(
+        stopped.judge(&check("inactive", 0, None), started),
+        Verdict::Abandoned
+    );
+}
+
+#[test]
+fn stabilization_period_comes_from_the_spec() {
+    let mut spec = spec("1.28");
+    assert_eq!(stabilize_period(&spec), Some(Duration::from_secs(60)));
+    spec.stabilize_secs = Some(300);
+    assert_eq!(stabilize_period(&spec), Some(Duration::from_secs(300)));
+    spec.stabilize_secs = Some(0);
+    assert_eq!(stabilize_period(&spec), None);
+}
+
+#[tokio::test]
+async fn rollback_state_is_kept_per_workload() {
+    let systemd_dir = tempfile::tempdir().unwrap();
+    assert!(load_state(systemd_dir.path(), "avena-nginx")
+        .await
+        .known_good
+        .is_none());
+
+    let known_good = KnownGood {
+        spec: spec("1.27"),
+        files: trial(Instant::now()).files,
+        since: 1_700_000_000_000,
+    };
+    let state = RollbackState {
+        known_good: Some(known_good.clone()),
+        rollback: Some(WorkloadRollback {
+            timestamp: HybridTimestamp {
+                wall_time_ms: 1_700_000_060_000,
+                counter: 0,
+                node_id: "dev1".to_string(),
+            },
+            reason: "unit failed".to_string(),
+            failed_spec: spec("1.28"),
+        }),
+    };
+    save_state(systemd_dir.path(), "avena-nginx", &state)
+        .await
+        .unwrap();
+
+    let loaded = load_state(systemd_dir.path(), "avena-nginx").await;
+    assert_eq!(loaded.known_good, Some(known_good));
+    assert!(loaded.rolled_back(&spec("1.28")));
+    assert!(!loaded.rolled_back(&spec("1.29")));
+
+    forget(systemd_dir.path(), "avena-nginx").await.unwrap();
+    forget(systemd_dir.path(), "avena-nginx").await.unwrap();
+    assert!(load_state(systemd_dir.path(), "avena-nginx")
+        .await
+        .rollback
+        .is_none());
+}